@@ -1,17 +1,37 @@
 #![allow(clippy::uninlined_format_args)]
 
-use thag_rs::logging::{configure_log, set_verbosity};
+use thag_rs::events::{configure_events, emit, Event};
+use thag_rs::log;
+use thag_rs::logging::{configure_log, set_sink_from_config, set_verbosity, Verbosity};
 use thag_rs::{debug_timings, ThagError};
 use thag_rs::{execute, get_args};
 
 use std::cell::RefCell;
+use std::process::ExitCode;
 use std::time::Instant;
 
-pub fn main() -> Result<(), ThagError> {
+/// Run `thag` and exit with a status reflecting the outcome: the script's own exit code
+/// if it ran and failed, or one of the codes documented in `thag_rs::errors` otherwise.
+pub fn main() -> ExitCode {
+    let code = match run() {
+        Ok(()) => 0,
+        Err(ThagError::ScriptExit(code)) => code,
+        Err(err) => {
+            log!(Verbosity::Quieter, "{err}");
+            err.exit_code()
+        }
+    };
+    emit(&Event::Exit { code });
+    exit_code(code)
+}
+
+fn run() -> Result<(), ThagError> {
     let start = Instant::now();
     let args = RefCell::new(get_args()); // Wrap args in a RefCell
 
     set_verbosity(&args.borrow())?;
+    set_sink_from_config()?;
+    configure_events(&args.borrow())?;
 
     configure_log();
     debug_timings(&start, "Configured logging");
@@ -30,3 +50,8 @@ pub fn main() -> Result<(), ThagError> {
 
     Ok(())
 }
+
+/// Exit codes outside the portable range 0-255 are truncated by the OS anyway.
+fn exit_code(code: i32) -> ExitCode {
+    ExitCode::from(u8::try_from(code).unwrap_or(u8::MAX))
+}
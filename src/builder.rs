@@ -1,26 +1,36 @@
 use crate::code_utils::{
     self, build_loop, create_next_repl_file, create_temp_source_file, extract_ast_expr,
-    extract_manifest, process_expr, read_file_contents, remove_inner_attributes,
-    strip_curly_braces, wrap_snippet, write_source,
+    extract_manifest, loop_segments, process_expr, read_file_contents, remove_inner_attributes,
+    strip_curly_braces, wrap_snippet, write_source, AsyncRuntime, OutputMode,
 };
 use crate::colors::{nu_resolve_style, MessageLevel};
 use crate::config::{self, RealContext, MAYBE_CONFIG};
-use crate::errors::ThagError;
+use crate::diagnostics::{render_diagnostic, DependencyProgress};
+use crate::errors::{ThagError, EXIT_FAILURE, EXIT_SIGNAL_BASE};
+use crate::events::{emit, events_enabled, events_replace_messages, Event, Phase, PhaseGuard};
+use crate::fixes::{
+    apply_suggestions, collect_suggestions, import_position, unified_diff, Suggestion,
+    MAX_FIX_ATTEMPTS,
+};
 use crate::logging::{is_debug_logging_enabled, Verbosity};
 use crate::manifest;
+use crate::render::{render_value, terminal_width, RESULT_FILE_VAR};
 use crate::repl::run_repl;
+use crate::script_env::script_env;
 use crate::shared::debug_timings;
 use crate::shared::{display_timings, Ast, BuildState};
+use crate::source_map::{SourceMap, SourceSegment};
 use crate::stdin::CrosstermEventReader;
 use crate::stdin::{edit, read};
+use crate::watch::{self, watch};
 use crate::VERSION;
 use crate::{
     cmd_args::{get_proc_flags, validate_args, Cli, ProcFlags},
     ScriptState,
 };
 use crate::{
-    debug_log, DYNAMIC_SUBDIR, FLOWER_BOX_LEN, PACKAGE_NAME, REPL_SUBDIR, RS_SUFFIX,
-    TEMP_SCRIPT_NAME, TMPDIR,
+    debug_log, DYNAMIC_SUBDIR, FLOWER_BOX_LEN, OUTPUT_OPTIONS_NAME, PACKAGE_NAME, REPL_SUBDIR,
+    RS_SUFFIX, TEMP_SCRIPT_NAME, TMPDIR,
 };
 use crate::{log, stdin};
 
@@ -29,12 +39,13 @@ use firestorm::{profile_fn, profile_section};
 use lazy_static::lazy_static;
 use log::{log_enabled, Level::Debug};
 use regex::Regex;
+use std::borrow::Cow;
 use std::string::ToString;
 use std::{
     fs::{self, OpenOptions},
-    io::Write,
+    io::{BufRead, BufReader, Write},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Child, Command, ExitStatus},
     time::Instant,
 };
 
@@ -50,7 +61,7 @@ pub fn execute(args: &mut Cli) -> Result<(), ThagError> {
 
     let start = Instant::now();
 
-    let proc_flags = get_proc_flags(args)?;
+    let proc_flags = get_proc_flags(args).map_err(|e| ThagError::Args(Box::new(e)))?;
 
     if log_enabled!(Debug) {
         log_init_setup(start, args, &proc_flags);
@@ -69,7 +80,7 @@ pub fn execute(args: &mut Cli) -> Result<(), ThagError> {
     } else {
         std::env::current_dir()?.canonicalize()?
     };
-    validate_args(args, &proc_flags)?;
+    validate_args(args, &proc_flags).map_err(|e| ThagError::Args(Box::new(e)))?;
     let repl_source_path = if is_repl && args.script.is_none() {
         Some(create_next_repl_file()?)
     } else {
@@ -207,7 +218,8 @@ fn process(
             let Some(filter) = args.filter.take() else {
                 return Err("Missing expression for --loop option".into());
             };
-            build_loop(args, filter)
+            build_state.source_segments = loop_segments(args, &filter);
+            build_loop(args, filter)?
         } else if is_edit {
             debug_log!("About to call stdin::edit()");
             let event_reader = CrosstermEventReader;
@@ -248,6 +260,8 @@ fn process(
             proc_flags,
             &start,
         )
+    } else if args.watch {
+        watch(args, proc_flags, script_state, build_state, &start)
     } else {
         gen_build_run(args, proc_flags, &mut build_state, None::<Ast>, &start)
     }
@@ -293,183 +307,440 @@ pub fn gen_build_run(
     // profile_fn!(gen_build_run);
 
     if build_state.must_gen {
-        let source_path: &Path = &build_state.source_path;
-        let start_parsing_rs = Instant::now();
-        let mut rs_source = read_file_contents(source_path)?;
-
-        // Strip off any shebang: it may have got us here but we don't want or need it
-        // in the gen_build_run process.
-        rs_source = if rs_source.starts_with("#!") && !rs_source.starts_with("#![") {
-            // debug_log!("rs_source (before)={rs_source}");
-            let split_once = rs_source.split_once('\n');
-            #[allow(unused_variables)]
-            let (shebang, rust_code) = split_once.ok_or("Failed to strip shebang")?;
-
-            debug_log!("Successfully stripped shebang {shebang}");
-            // debug_log!("rs_source (after)={rust_code}");
-            rust_code.to_string()
-        } else {
-            rs_source
-        };
-
-        // let mut rs_source = read_file_contents(&build_state.source_path)?;
-        let mut syntax_tree: Option<Ast> = if syntax_tree.is_none() {
-            code_utils::to_ast(&rs_source)
+        prepare_and_generate(args, proc_flags, build_state, syntax_tree)?;
+    } else {
+        log!(
+            Verbosity::Normal,
+            "{}",
+            nu_ansi_term::Color::Yellow
+                // .bold()
+                .paint("Skipping unnecessary generation step.  Use --force (-f) to override.")
+        );
+        // build_state.cargo_manifest = Some(default_manifest(build_state)?);
+        build_state.cargo_manifest = None; // Don't need it in memory, build will find it on disk
+    }
+    if build_state.must_build {
+        if proc_flags.contains(ProcFlags::FIX) {
+            build_with_fixes(args, proc_flags, build_state)?;
         } else {
-            syntax_tree
-        };
-
-        lazy_static! {
-            static ref RE: Regex = Regex::new(r"(?m)^\s*(async\s+)?fn\s+main\s*\(\s*\)").unwrap();
+            build(proc_flags, build_state)?;
         }
-        let main_methods = syntax_tree.as_ref().map_or_else(
-            || RE.find_iter(&rs_source).count(),
-            code_utils::count_main_methods,
+    } else {
+        log!(
+            Verbosity::Normal,
+            "{}",
+            nu_ansi_term::Color::Yellow
+                // .bold()
+                .paint("Skipping unnecessary cargo build step. Use --force (-f) to override.")
         );
-        let has_main = match main_methods {
-            0 => false,
-            1 => true,
-            _ => {
-                if args.multimain {
-                    true
-                } else {
-                    writeln!(
-                    &mut std::io::stderr(),
+    }
+    if proc_flags.contains(ProcFlags::RUN) {
+        run(proc_flags, &args.args, build_state)?;
+    }
+    let process = &format!(
+        "{} completed processing script {}",
+        PACKAGE_NAME,
+        nu_resolve_style(MessageLevel::Emphasis).paint(&build_state.source_name)
+    );
+    display_timings(start, process, proc_flags);
+    Ok(())
+}
+
+/// Prepare the source of the script or expression for generation, by analysing it,
+/// merging its manifest, and wrapping it if it is a snippet, then generate the program.
+/// # Errors
+///
+/// Will return `Err` if there is an error reading or analysing the source or returned by
+/// the generate function.
+#[allow(clippy::too_many_lines)]
+fn prepare_and_generate(
+    args: &Cli,
+    proc_flags: &ProcFlags,
+    build_state: &mut BuildState,
+    syntax_tree: Option<Ast>,
+) -> Result<(), ThagError> {
+    let source_path: &Path = &build_state.source_path;
+    let start_parsing_rs = Instant::now();
+    let mut rs_source = read_file_contents(source_path)?;
+
+    // Strip off any shebang: it may have got us here but we don't want or need it
+    // in the gen_build_run process.
+    let has_shebang = rs_source.starts_with("#!") && !rs_source.starts_with("#![");
+    rs_source = if has_shebang {
+        // debug_log!("rs_source (before)={rs_source}");
+        let split_once = rs_source.split_once('\n');
+        #[allow(unused_variables)]
+        let (shebang, rust_code) = split_once.ok_or("Failed to strip shebang")?;
+
+        debug_log!("Successfully stripped shebang {shebang}");
+        // debug_log!("rs_source (after)={rust_code}");
+        rust_code.to_string()
+    } else {
+        rs_source
+    };
+
+    // The loop filter has been wrapped in a program already, so its segments are set up front.
+    if !proc_flags.contains(ProcFlags::LOOP) {
+        build_state.source_segments = vec![original_segment(
+            proc_flags,
+            build_state,
+            &rs_source,
+            if has_shebang { 2 } else { 1 },
+        )];
+    }
+
+    // let mut rs_source = read_file_contents(&build_state.source_path)?;
+    let mut syntax_tree: Option<Ast> = if syntax_tree.is_none() {
+        code_utils::to_ast(&rs_source)
+    } else {
+        syntax_tree
+    };
+
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"(?m)^\s*(async\s+)?fn\s+main\s*\(").unwrap();
+    }
+    let main_methods = syntax_tree.as_ref().map_or_else(
+        || RE.find_iter(&rs_source).count(),
+        code_utils::count_main_methods,
+    );
+    let has_main = match main_methods {
+        0 => false,
+        1 => true,
+        _ => {
+            if args.multimain {
+                true
+            } else {
+                return Err(ThagError::Args(Box::new(format!(
                     "{main_methods} main methods found, only one allowed by default. Specify --multimain (-m) option to allow more"
-                )?;
-                    std::process::exit(1);
-                }
+                ).into())));
             }
-        };
+        }
+    };
 
-        // NB build scripts that are well-formed programs from the original source.
-        // Fun fact: Rust compiler will ignore shebangs:
-        // https://neosmart.net/blog/self-compiling-rust-code/
-        let is_file = syntax_tree.as_ref().map_or(false, Ast::is_file);
-        build_state.build_from_orig_source = has_main && args.script.is_some() && is_file;
+    // NB build scripts that are well-formed programs from the original source.
+    // Fun fact: Rust compiler will ignore shebangs:
+    // https://neosmart.net/blog/self-compiling-rust-code/
+    let is_file = syntax_tree.as_ref().is_some_and(Ast::is_file);
+    // A main method with typed parameters is rewritten to parse them from the command line.
+    let typed_main = has_main && syntax_tree.as_ref().is_some_and(code_utils::has_typed_main);
+    build_state.build_from_orig_source =
+        has_main && args.script.is_some() && is_file && !typed_main;
 
-        debug_log!(
-            "has_main={has_main}; build_state.build_from_orig_source={}",
-            build_state.build_from_orig_source
-        );
+    debug_log!(
+        "has_main={has_main}; build_state.build_from_orig_source={}",
+        build_state.build_from_orig_source
+    );
 
-        let rs_manifest: Manifest = { extract_manifest(&rs_source, start_parsing_rs) }?;
+    let rs_manifest: Manifest = { extract_manifest(&rs_source, start_parsing_rs) }?;
 
-        // debug_log!("rs_manifest={rs_manifest:#?}");
+    // debug_log!("rs_manifest={rs_manifest:#?}");
 
-        debug_log!("rs_source={rs_source}");
-        if build_state.rs_manifest.is_none() {
-            build_state.rs_manifest = Some(rs_manifest);
-        }
+    debug_log!("rs_source={rs_source}");
+    if build_state.rs_manifest.is_none() {
+        build_state.rs_manifest = Some(rs_manifest);
+    }
 
-        // debug_log!("syntax_tree={syntax_tree:#?}");
+    // debug_log!("syntax_tree={syntax_tree:#?}");
 
-        if build_state.rs_manifest.is_some() {
-            manifest::merge(build_state, &rs_source, &syntax_tree)?;
+    // A snippet that awaits needs an async runtime to run its main method on
+    let async_runtime = match syntax_tree {
+        Some(Ast::Expr(ref expr)) if !has_main && code_utils::is_async(expr) => {
+            code_utils::async_runtime()
         }
+        _ => AsyncRuntime::None,
+    };
+    if let Some(ref mut rs_manifest) = build_state.rs_manifest {
+        manifest::add_runtime_deps(&mut rs_manifest.dependencies, async_runtime);
+    }
 
-        // println!("build_state={build_state:#?}");
-        rs_source = if has_main {
-            // Strip off any enclosing braces, e.g.
-            if rs_source.starts_with('{') {
-                strip_curly_braces(&rs_source).unwrap_or(rs_source)
+    let is_loop = proc_flags.contains(ProcFlags::LOOP);
+    if is_loop
+        && args
+            .field_separator
+            .as_deref()
+            .is_some_and(code_utils::is_regex_separator)
+    {
+        if let Some(ref mut rs_manifest) = build_state.rs_manifest {
+            manifest::add_regex_deps(&mut rs_manifest.dependencies);
+        }
+    }
+    if is_loop && (args.jsonl || args.csv) {
+        if let Some(ref mut rs_manifest) = build_state.rs_manifest {
+            if args.jsonl {
+                manifest::add_json_deps(&mut rs_manifest.dependencies);
             } else {
-                rs_source
+                manifest::add_csv_deps(&mut rs_manifest.dependencies);
             }
+        }
+    }
+    if typed_main {
+        if let Some(ref mut rs_manifest) = build_state.rs_manifest {
+            manifest::add_clap_deps(&mut rs_manifest.dependencies);
+        }
+    }
+
+    // The wrapper template may import crates of its own, e.g. in its prelude
+    let template = if has_main {
+        None
+    } else {
+        Some(code_utils::wrapper_template(args, &rs_source)?)
+    };
+    if let (Some(ref template), Some(ref mut rs_manifest)) =
+        (&template, &mut build_state.rs_manifest)
+    {
+        let template_deps =
+            code_utils::infer_deps_from_source(&format!("{}\n{}", template.prelude, template.text));
+        manifest::search_deps(template_deps, &mut rs_manifest.dependencies);
+    }
+
+    // The snippet or loop wrapper needs serde to output results as JSON, or to pass them
+    // back to be rendered
+    if (args.output == OutputMode::Json && (!has_main || is_loop))
+        || (args.output == OutputMode::Rich && !has_main)
+    {
+        if let Some(ref mut rs_manifest) = build_state.rs_manifest {
+            manifest::add_json_deps(&mut rs_manifest.dependencies);
+        }
+    }
+
+    if build_state.rs_manifest.is_some() {
+        manifest::merge(build_state, &rs_source, &syntax_tree)?;
+    }
+
+    // println!("build_state={build_state:#?}");
+    rs_source = if has_main {
+        // Strip off any enclosing braces, e.g.
+        let rs_source = if rs_source.starts_with('{') {
+            strip_curly_braces(&rs_source).unwrap_or(rs_source)
         } else {
-            // let start_quote = Instant::now();
+            rs_source
+        };
+        if typed_main {
+            code_utils::wrap_typed_main(&rs_source)?
+        } else {
+            rs_source
+        }
+    } else {
+        // let start_quote = Instant::now();
 
-            // Remove any inner attributes from the syntax tree
-            let found = if let Some(Ast::Expr(syn::Expr::Block(ref mut expr_block))) = syntax_tree {
-                // Apply the RemoveInnerAttributes visitor to the expression block
-                remove_inner_attributes(expr_block)
-            } else {
-                false
-            };
+        // Remove any inner attributes from the syntax tree
+        let found = if let Some(Ast::Expr(syn::Expr::Block(ref mut expr_block))) = syntax_tree {
+            // Apply the RemoveInnerAttributes visitor to the expression block
+            remove_inner_attributes(expr_block)
+        } else {
+            false
+        };
 
-            let (inner_attribs, body) = if found {
-                code_utils::extract_inner_attribs(&rs_source)
-            } else {
-                (String::new(), rs_source)
-            };
+        let (inner_attribs, body) = if found {
+            code_utils::extract_inner_attribs(&rs_source)
+        } else {
+            (String::new(), rs_source)
+        };
 
-            let rust_code = syntax_tree.as_ref().map_or(body, |syntax_tree_ref| {
-                let returns_unit = match syntax_tree_ref {
-                    Ast::Expr(expr) => code_utils::is_unit_return_type(expr),
-                    Ast::File(_) => true, // Trivially true since we're here because there's no main
-                };
-                if returns_unit {
-                    debug_log!("Option B: returns unit type");
-                    quote::quote!(
-                        #syntax_tree_ref
-                    )
-                    .to_string()
-                } else {
-                    debug_log!("Option A: returns a substantive type");
+        let rust_code = syntax_tree.as_ref().map_or(body, |syntax_tree_ref| {
+            let tokens = quote::quote!(#syntax_tree_ref).to_string();
+            match syntax_tree_ref {
+                Ast::Expr(_) => {
                     debug_log!(
                         "args.unquote={:?}, MAYBE_CONFIG={:?}",
                         args.unquote,
                         MAYBE_CONFIG
                     );
-
-                    if proc_flags.contains(ProcFlags::UNQUOTE) {
-                        debug_log!("\nIn unquote leg\n");
-                        quote::quote!(
-                            println!("{}", format!("{:?}", #syntax_tree_ref).trim_matches('"'));
-                        )
-                        .to_string()
-                    } else {
-                        debug_log!("\nIn quote leg\n");
-                        quote::quote!(
-                            println!("{}", format!("{:?}", #syntax_tree_ref));
-                        )
-                        .to_string()
+                    match args.output {
+                        OutputMode::Text => code_utils::print_result(
+                            &tokens,
+                            code_utils::result_format(args),
+                            proc_flags.contains(ProcFlags::UNQUOTE),
+                        ),
+                        OutputMode::Json => code_utils::print_json_result(&tokens),
+                        OutputMode::Rich => {
+                            code_utils::print_rich_result(&tokens, code_utils::result_format(args))
+                        }
                     }
                 }
-            });
-
-            // display_timings(&start_quote, "Completed quote", proc_flags);
-            wrap_snippet(&inner_attribs, &rust_code)
-        };
+                // Nothing to print, since we're here because there's no main
+                Ast::File(_) => tokens,
+            }
+        });
 
-        let maybe_rs_source = if has_main && build_state.build_from_orig_source {
-            None
+        let rust_code = if args.slurp {
+            format!("{}{rust_code}", code_utils::SLURP_STDIN)
         } else {
-            Some(rs_source.as_str())
+            rust_code
         };
-        generate(build_state, maybe_rs_source, proc_flags)?;
+
+        // display_timings(&start_quote, "Completed quote", proc_flags);
+        wrap_snippet(
+            &template.unwrap_or_default(),
+            &inner_attribs,
+            &rust_code,
+            async_runtime,
+        )
+    };
+
+    let maybe_rs_source = if has_main && build_state.build_from_orig_source {
+        None
     } else {
+        Some(rs_source.as_str())
+    };
+    generate(build_state, maybe_rs_source, proc_flags).map_err(|e| ThagError::Generate(Box::new(e)))
+}
+
+/// Build the program, and if the build fails, apply the compiler's suggested fixes to the
+/// original source and try again, up to `MAX_FIX_ATTEMPTS` times. If the build then
+/// succeeds, show the changes made; if not, leave the script as it was.
+/// # Errors
+///
+/// Will return `Err` if the build still fails, or if there is an error writing the fixed
+/// source or generating the program from it.
+fn build_with_fixes(
+    args: &Cli,
+    proc_flags: &ProcFlags,
+    build_state: &mut BuildState,
+) -> Result<(), ThagError> {
+    profile_fn!(build_with_fixes);
+    let is_script = !proc_flags
+        .intersects(ProcFlags::EXPR | ProcFlags::STDIN | ProcFlags::EDIT | ProcFlags::LOOP);
+    let original_segments = build_state.source_segments.clone();
+    let original_source = read_file_contents(&build_state.source_path)?;
+
+    let mut suggestions = Vec::new();
+    let mut result = cargo_build(proc_flags, build_state, &mut suggestions);
+    let mut attempts = 0;
+    while matches!(result, Err(ThagError::Build(_))) && attempts < MAX_FIX_ATTEMPTS {
+        let (segments, fix_count) = fixed_segments(build_state, &suggestions);
+        if fix_count == 0 {
+            break;
+        }
+        attempts += 1;
         log!(
             Verbosity::Normal,
-            "{}",
-            nu_ansi_term::Color::Yellow
-                // .bold()
-                .paint("Skipping unnecessary generation step.  Use --force (-f) to override.")
+            "Applying {fix_count} suggested {} and rebuilding ...",
+            if fix_count == 1 { "fix" } else { "fixes" }
         );
-        // build_state.cargo_manifest = Some(default_manifest(build_state)?);
-        build_state.cargo_manifest = None; // Don't need it in memory, build will find it on disk
+        let rs_source = if proc_flags.contains(ProcFlags::LOOP) {
+            let mut loop_args = args.clone();
+            let mut filter = String::new();
+            for segment in &segments {
+                match segment.name.as_str() {
+                    "<begin>" => loop_args.begin = Some(segment.text.clone()),
+                    "<end>" => loop_args.end = Some(segment.text.clone()),
+                    _ => filter.clone_from(&segment.text),
+                }
+            }
+            build_state.source_segments = segments;
+            build_loop(&loop_args, filter)?
+        } else {
+            // Any shebang was stripped from the segment, so put it back
+            let text = segments.first().map_or("", |segment| segment.text.as_str());
+            match original_source.split_once('\n') {
+                Some((shebang, _))
+                    if is_script && shebang.starts_with("#!") && !shebang.starts_with("#![") =>
+                {
+                    format!("{shebang}\n{text}")
+                }
+                _ => text.to_string(),
+            }
+        };
+        write_source(&build_state.source_path, &rs_source)?;
+        let syntax_tree = if is_script {
+            None
+        } else {
+            Some(Ast::Expr(extract_ast_expr(&rs_source)?))
+        };
+        prepare_and_generate(args, proc_flags, build_state, syntax_tree)?;
+        result = cargo_build(proc_flags, build_state, &mut suggestions);
     }
-    if build_state.must_build {
-        build(proc_flags, build_state)?;
-    } else {
-        log!(
-            Verbosity::Normal,
-            "{}",
-            nu_ansi_term::Color::Yellow
-                // .bold()
-                .paint("Skipping unnecessary cargo build step. Use --force (-f) to override.")
-        );
+
+    if attempts > 0 {
+        if result.is_ok() {
+            for (original, fixed) in original_segments.iter().zip(&build_state.source_segments) {
+                if original.text != fixed.text {
+                    log!(Verbosity::Quiet, "{}", unified_diff(original, &fixed.text));
+                }
+            }
+        } else if is_script {
+            write_source(&build_state.source_path, &original_source)?;
+            log!(
+                Verbosity::Quieter,
+                "Suggested fixes did not resolve the errors, so the script has been left unchanged"
+            );
+        }
     }
-    if proc_flags.contains(ProcFlags::RUN) {
-        run(proc_flags, &args.args, build_state)?;
+    result
+}
+
+/// Apply the suggested fixes from the last build to the segments of original source,
+/// returning the fixed segments and the number of fixes applied.
+fn fixed_segments(
+    build_state: &BuildState,
+    fix_suggestions: &[Suggestion],
+) -> (Vec<SourceSegment>, usize) {
+    let segments = &build_state.source_segments;
+    let mut fix_count = 0;
+    let fixed = segments
+        .iter()
+        .enumerate()
+        .map(|(index, segment)| {
+            let mut suggestions: Vec<Suggestion> = fix_suggestions
+                .iter()
+                .filter(|suggestion| suggestion.applies_to(&segment.name))
+                .cloned()
+                .collect();
+            // Imports that rustc suggests adding to the wrapper go at the top of the snippet
+            if index == 0 && build_state.source_map.is_some() {
+                let (line, column) = import_position(segment);
+                suggestions.extend(
+                    fix_suggestions
+                        .iter()
+                        .filter(|suggestion| {
+                            suggestion.is_import()
+                                && !segments.iter().any(|s| suggestion.applies_to(&s.name))
+                        })
+                        .map(|suggestion| Suggestion {
+                            file_name: segment.name.clone(),
+                            line_start: line,
+                            column_start: column,
+                            line_end: line,
+                            column_end: column,
+                            replacement: format!("{}\n", suggestion.replacement.trim()),
+                        }),
+                );
+            }
+            let (text, applied) =
+                apply_suggestions(segment, &suggestions.iter().collect::<Vec<_>>());
+            fix_count += applied;
+            SourceSegment {
+                text,
+                ..segment.clone()
+            }
+        })
+        .collect();
+    (fixed, fix_count)
+}
+
+/// Identify the user's input that is about to be generated into a program, for the
+/// source map.
+fn original_segment(
+    proc_flags: &ProcFlags,
+    build_state: &BuildState,
+    rs_source: &str,
+    first_line: usize,
+) -> SourceSegment {
+    let name = if proc_flags.contains(ProcFlags::EXPR) {
+        "<expr>".to_string()
+    } else if proc_flags.contains(ProcFlags::STDIN) {
+        "<stdin>".to_string()
+    } else if proc_flags.contains(ProcFlags::EDIT) {
+        "<edit>".to_string()
+    } else {
+        build_state.source_path.display().to_string()
+    };
+    SourceSegment {
+        name,
+        text: rs_source.to_string(),
+        first_line,
     }
-    let process = &format!(
-        "{} completed processing script {}",
-        PACKAGE_NAME,
-        nu_resolve_style(MessageLevel::Emphasis).paint(&build_state.source_name)
-    );
-    display_timings(start, process, proc_flags);
-    Ok(())
 }
 
 /// Generate the source code and Cargo.toml file for the script.
@@ -482,12 +753,13 @@ pub fn gen_build_run(
 ///
 /// Will panic if it fails to unwrap the `BuildState.cargo_manifest`.
 pub fn generate(
-    build_state: &BuildState,
+    build_state: &mut BuildState,
     rs_source: Option<&str>,
     proc_flags: &ProcFlags,
 ) -> Result<(), ThagError> {
     // profile_fn!(generate);
     let start_gen = Instant::now();
+    let phase = PhaseGuard::start(Phase::Generate);
 
     if is_debug_logging_enabled() {
         debug_log!("In generate, proc_flags={proc_flags}");
@@ -508,12 +780,19 @@ pub fn generate(
         "GGGGGGGG Creating source file: {target_rs_path:?}"
     );
 
-    if !build_state.build_from_orig_source {
+    build_state.source_map = if build_state.build_from_orig_source {
+        None
+    } else {
         profile_section!(transform);
         let syntax_tree = syn_parse_file(rs_source)?;
         let rs_source = prettyplease_unparse(&syntax_tree);
         write_source(&target_rs_path, &rs_source)?;
-    }
+        Some(SourceMap::new(
+            &build_state.source_segments,
+            &build_state.source_name,
+            &rs_source,
+        ))
+    };
 
     // debug_log!("cargo_toml_path will be {:?}", &build_state.cargo_toml_path);
     if !Path::try_exists(&build_state.cargo_toml_path)? {
@@ -538,10 +817,15 @@ pub fn generate(
 
     let mut toml_file = fs::File::create(&build_state.cargo_toml_path)?;
     toml_file.write_all(cargo_manifest_str.as_bytes())?;
+    fs::write(
+        build_state.target_dir_path.join(OUTPUT_OPTIONS_NAME),
+        &build_state.output_options,
+    )?;
     // if is_debug_logging_enabled() {
     //     debug_log!("cargo_toml_path={:?}", &build_state.cargo_toml_path);
     //     debug_log!("##### Cargo.toml generation succeeded");
     // }
+    phase.finish(true);
     display_timings(&start_gen, "Completed generation", proc_flags);
 
     Ok(())
@@ -566,9 +850,19 @@ fn prettyplease_unparse(syntax_tree: &syn::File) -> String {
 /// # Panics
 /// Will panic if the cargo build process fails to spawn or if it can't move the executable.
 pub fn build(proc_flags: &ProcFlags, build_state: &BuildState) -> Result<(), ThagError> {
+    cargo_build(proc_flags, build_state, &mut Vec::new())
+}
+
+// Build the program, passing back any fixes suggested by the compiler for `--fix`.
+fn cargo_build(
+    proc_flags: &ProcFlags,
+    build_state: &BuildState,
+    fix_suggestions: &mut Vec<Suggestion>,
+) -> Result<(), ThagError> {
     // profile_fn!(build);
 
     let start_build = Instant::now();
+    let phase = PhaseGuard::start(Phase::Build);
     let quiet = proc_flags.contains(ProcFlags::QUIET);
     let quieter = proc_flags.contains(ProcFlags::QUIETER);
     let executable = proc_flags.contains(ProcFlags::EXECUTABLE);
@@ -582,12 +876,19 @@ pub fn build(proc_flags: &ProcFlags, build_state: &BuildState) -> Result<(), Tha
     let cargo_subcommand = if check { "check" } else { "build" };
     // Rustc writes to std
     let mut args = vec![cargo_subcommand, "--manifest-path", &cargo_toml_path_str];
-    if quiet || quieter {
+    // Unless the raw output is asked for, we use Cargo's JSON messages to map diagnostics
+    // back to the user's original input and show them in condensed form, and to report
+    // the progress of the dependencies. JSON events always need the JSON messages.
+    let raw_output = proc_flags.contains(ProcFlags::CARGO_OUTPUT) && !events_enabled();
+    if quiet || quieter || !raw_output {
         args.push("--quiet");
     }
     if executable {
         args.push("--release");
     }
+    if !raw_output {
+        args.push("--message-format=json");
+    }
 
     cargo_command.args(&args); // .current_dir(build_dir);
 
@@ -599,38 +900,105 @@ pub fn build(proc_flags: &ProcFlags, build_state: &BuildState) -> Result<(), Tha
         nu_resolve_style(MessageLevel::Emphasis).paint(&build_state.source_name)
     );
 
-    if quieter {
+    if quieter || !raw_output {
         // Pipe output
         cargo_command
             .stdout(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped());
     } else {
-        // Redirect stdout and stderr to inherit from the parent process (terminal)
+        // Show Cargo's output on our stderr, keeping stdout for the script's output
         cargo_command
-            .stdout(std::process::Stdio::inherit())
+            .stdout(std::io::stderr())
             .stderr(std::process::Stdio::inherit());
     }
 
     // Execute the command and handle the result
-    let output = cargo_command.spawn()?;
+    let child = cargo_command.spawn()?;
 
     // Wait for the process to finish
-    let exit_status = output.wait_with_output()?;
+    let exit_status = if raw_output {
+        child.wait_with_output()?.status
+    } else {
+        let (exit_status, suggestions) = report_cargo_messages(child, build_state)?;
+        *fix_suggestions = suggestions;
+        exit_status
+    };
 
-    if exit_status.status.success() {
+    if exit_status.success() {
         debug_log!("Build succeeded");
         if executable {
             deploy_executable(build_state)?;
         }
     } else {
-        return Err("Build failed".into());
+        return Err(ThagError::Build(Cow::Borrowed("Build failed")));
     };
 
+    phase.finish(true);
     display_timings(&start_build, "Completed build", proc_flags);
 
     Ok(())
 }
 
+/// Read Cargo's JSON output, showing the progress of the dependencies, and mapping each
+/// compiler message back to the original source where possible before reporting it as a
+/// diagnostic event and showing it in condensed form. Returns the exit status together
+/// with any suggested fixes for `--fix`.
+fn report_cargo_messages(
+    mut child: Child,
+    build_state: &BuildState,
+) -> Result<(ExitStatus, Vec<Suggestion>), ThagError> {
+    profile_fn!(report_cargo_messages);
+    let stderr = child.stderr.take();
+    // With --quiet, whatever Cargo still has to say on stderr is worth hearing, apart from
+    // its closing remark on a failed compilation, which would only repeat ours.
+    let stderr_logger = std::thread::spawn(move || {
+        if let Some(stderr) = stderr {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                if !line.starts_with("error: could not compile") {
+                    log!(Verbosity::Quieter, "{line}");
+                }
+            }
+        }
+    });
+
+    let mut progress = DependencyProgress::new(&build_state.cargo_toml_path);
+    let mut suggestions: Vec<Suggestion> = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            let Ok(mut record) = serde_json::from_str::<serde_json::Value>(&line) else {
+                continue;
+            };
+            match record["reason"].as_str() {
+                Some("compiler-artifact") => progress.update(&record),
+                Some("compiler-message") => {
+                    let message = &mut record["message"];
+                    if let Some(ref source_map) = build_state.source_map {
+                        source_map.rewrite_diagnostic(message);
+                    }
+                    emit(&Event::Diagnostic { message });
+                    for suggestion in collect_suggestions(message) {
+                        if !suggestions.contains(&suggestion) {
+                            suggestions.push(suggestion);
+                        }
+                    }
+                    if events_replace_messages() {
+                        continue;
+                    }
+                    if let Some((verbosity, rendered)) = render_diagnostic(message) {
+                        progress.clear();
+                        log!(verbosity, "{rendered}\n");
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    progress.finish();
+
+    let _ = stderr_logger.join();
+    Ok((child.wait()?, suggestions))
+}
+
 fn deploy_executable(build_state: &BuildState) -> Result<(), ThagError> {
     profile_fn!(deploy_executable);
     // Determine the output directory
@@ -702,7 +1070,8 @@ fn deploy_executable(build_state: &BuildState) -> Result<(), ThagError> {
 /// # Errors
 ///
 /// Will return `Err` if there is an error waiting for the spawned command
-/// that runs the user script.
+/// that runs the user script, or `ThagError::ScriptExit` with the script's
+/// exit code if it terminates unsuccessfully.
 pub fn run(
     proc_flags: &ProcFlags,
     args: &[String],
@@ -711,6 +1080,7 @@ pub fn run(
     // profile_fn!(run);
 
     let start_run = Instant::now();
+    let phase = PhaseGuard::start(Phase::Run);
     #[cfg(debug_assertions)]
     debug_log!("RRRRRRRR In run");
 
@@ -723,6 +1093,11 @@ pub fn run(
     let mut run_command = Command::new(format!("{}", target_path.display()));
 
     run_command.args(args);
+    run_command.envs(script_env(proc_flags, build_state)?);
+    if let Some(ref result_file) = build_state.result_file {
+        let _ = fs::remove_file(result_file);
+        run_command.env(RESULT_FILE_VAR, result_file);
+    }
 
     // #[cfg(debug_assertions)]
     debug_log!("Run command is {run_command:?}");
@@ -736,7 +1111,28 @@ pub fn run(
         nu_ansi_term::Color::Yellow.paint(&dash_line)
     );
 
-    let _exit_status = run_command.spawn()?.wait()?;
+    handle_signals()?;
+    let mut child = run_command.spawn()?;
+    let exit_status = if watch::is_watching() {
+        let Some(exit_status) = wait_forwarding_signals(&mut child, watch::wait_unless_changed)?
+        else {
+            log!(
+                Verbosity::Quiet,
+                "{}",
+                nu_ansi_term::Color::Yellow.paint("Script stopped by a change")
+            );
+            phase.finish(false);
+            return Ok(());
+        };
+        exit_status
+    } else {
+        wait_forwarding_signals(&mut child, |child| Ok(child.wait()?))?
+    };
+    if exit_status.success() {
+        if let Some(ref result_file) = build_state.result_file {
+            render_result(result_file)?;
+        }
+    }
 
     log!(
         Verbosity::Quiet,
@@ -744,10 +1140,133 @@ pub fn run(
         nu_ansi_term::Color::Yellow.paint(&dash_line)
     );
 
-    // #[cfg(debug_assertions)]
-    // debug_log!("Exit status={exit_status:#?}");
+    debug_log!("Exit status={exit_status:#?}");
 
+    phase.finish(exit_status.success());
     display_timings(&start_run, "Completed run", proc_flags);
 
+    if exit_status.success() {
+        Ok(())
+    } else {
+        Err(ThagError::ScriptExit(exit_code(exit_status)))
+    }
+}
+
+/// Render the result that the program has passed back in the given file, if any.
+fn render_result(result_file: &Path) -> Result<(), ThagError> {
+    let Ok(json) = fs::read_to_string(result_file) else {
+        return Ok(());
+    };
+    let _ = fs::remove_file(result_file);
+    let value: serde_json::Value = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+    println!("{}", render_value(&value, terminal_width()));
+    Ok(())
+}
+
+// The process id of the script being waited for, or 0 while there is none
+#[cfg(unix)]
+static SCRIPT_PID: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(0);
+// Whether SIGINT or SIGTERM has arrived while waiting for the current script
+#[cfg(unix)]
+static SCRIPT_SIGNALLED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+// Whether the signal handler has been installed
+#[cfg(unix)]
+static SIGNALS_HANDLED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Install the handler that `thag` keeps for SIGINT and SIGTERM for the rest of the process,
+/// if not already installed. While a script is running, SIGTERM is forwarded to the script so
+/// that it decides how to terminate, while SIGINT is not, since Ctrl-C in the terminal already
+/// reaches the script in the foreground process group. At any other time, such as while
+/// building or while watching for a change, either signal terminates `thag` as by default.
+/// # Errors
+/// Will return `Err` if the handler can't be registered.
+#[cfg(unix)]
+pub fn handle_signals() -> Result<(), ThagError> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+    use signal_hook::low_level::emulate_default_handler;
+    use std::sync::atomic::Ordering;
+
+    if SIGNALS_HANDLED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            SIGNALS_HANDLED.store(false, Ordering::SeqCst);
+            return Err(err.into());
+        }
+    };
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            let pid = SCRIPT_PID.load(Ordering::SeqCst);
+            if pid == 0 {
+                debug_log!("Terminating on signal {signal}");
+                let _ = emulate_default_handler(signal);
+                continue;
+            }
+            SCRIPT_SIGNALLED.store(true, Ordering::SeqCst);
+            if signal != SIGINT {
+                debug_log!("Forwarding signal {signal} to script process {pid}");
+                // SAFETY: `kill` has no memory safety preconditions.
+                unsafe {
+                    libc::kill(pid, signal);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// There are no signals to handle on Windows, where console control events are delivered
+/// to the whole process group.
+/// # Errors
+/// Never returns `Err` on this platform.
+#[cfg(not(unix))]
+pub fn handle_signals() -> Result<(), ThagError> {
     Ok(())
 }
+
+/// Wait for the script with the given function, with SIGINT and SIGTERM received by `thag`
+/// in the meantime left to the script as described for `handle_signals`. Either signal
+/// also ends any watch once the script has finished.
+#[cfg(unix)]
+fn wait_forwarding_signals<T>(
+    child: &mut Child,
+    wait: impl FnOnce(&mut Child) -> Result<T, ThagError>,
+) -> Result<T, ThagError> {
+    use std::sync::atomic::Ordering;
+
+    let pid = libc::pid_t::try_from(child.id()).map_err(|_| "Child process id out of range")?;
+    SCRIPT_SIGNALLED.store(false, Ordering::SeqCst);
+    SCRIPT_PID.store(pid, Ordering::SeqCst);
+    let result = wait(child);
+    SCRIPT_PID.store(0, Ordering::SeqCst);
+    if SCRIPT_SIGNALLED.load(Ordering::SeqCst) {
+        watch::stop();
+    }
+    result
+}
+
+/// Wait for the script to finish. Console control events are delivered to the whole
+/// process group on Windows, so there is nothing to forward.
+#[cfg(not(unix))]
+fn wait_forwarding_signals<T>(
+    child: &mut Child,
+    wait: impl FnOnce(&mut Child) -> Result<T, ThagError>,
+) -> Result<T, ThagError> {
+    wait(child)
+}
+
+/// Convert the script's exit status to the exit code `thag` should return, following
+/// the shell convention of 128 + signal number for a script killed by a signal.
+fn exit_code(exit_status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = exit_status.signal() {
+            return EXIT_SIGNAL_BASE + signal;
+        }
+    }
+    exit_status.code().unwrap_or(EXIT_FAILURE)
+}
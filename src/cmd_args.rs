@@ -1,4 +1,6 @@
+use crate::code_utils::{OutputMode, ResultFormat};
 use crate::debug_log;
+use crate::events::MessageFormat;
 use crate::RS_SUFFIX;
 use crate::{errors::ThagError, MAYBE_CONFIG};
 
@@ -6,6 +8,7 @@ use bitflags::bitflags;
 use clap::{ArgGroup, Parser};
 use core::{fmt, str};
 use firestorm::profile_fn;
+use std::path::PathBuf;
 
 /// The `clap` command-line interface for the `thag_rs` script runner and REPL.
 #[allow(clippy::struct_excessive_bools)]
@@ -24,8 +27,8 @@ use firestorm::profile_fn;
 pub struct Cli {
     /// Optional name of a script to run (`stem`.rs)
     pub script: Option<String>,
-    /// Set the arguments for the script
-    #[arg(last = true, requires = "script")]
+    /// Set the arguments for the script, or the input files for --loop
+    #[arg(last = true)]
     pub args: Vec<String>,
     /// Generate Rust source and individual cargo .toml if compiled file is stale
     #[arg(short, long = "gen", default_value_ifs([
@@ -58,6 +61,9 @@ pub struct Cli {
     /// Evaluate a quoted expression on the fly
     #[arg(short, long = "expr", conflicts_with_all(["generate", "build"]))]
     pub expression: Option<String>,
+    /// For --expr, first read all of stdin into `input`, a `String`, with its lines as `lines`, a `Vec<&str>`
+    #[arg(short = 'S', long, requires = "expression")]
+    pub slurp: bool,
     /// REPL mode (read–eval–print loop) for Rust expressions. Option: existing script name
     #[arg(short = 'r', long, conflicts_with_all(["generate", "build"]))]
     pub repl: bool,
@@ -82,6 +88,34 @@ pub struct Cli {
     //  clap issue 4707 may prevent `requires` from working, as I've experienced.
     #[arg(short = 'E', long, requires = "filter", value_name = "POST-LOOP")]
     pub end: Option<String>,
+    /// Field separator for --loop: split each line into `fields`, with their count in `nf`. A single space splits on whitespace; a longer separator containing regex metacharacters is a regex
+    #[arg(short = 'F', long, requires = "filter", value_name = "SEP")]
+    pub field_separator: Option<String>,
+    /// Edit the --loop input files in place, writing the output for each file back to it. Optionally back up the original with the given suffix, as in -i.bak
+    #[arg(
+        short = 'i',
+        long,
+        requires = "filter",
+        num_args = 0..=1,
+        default_missing_value = "",
+        value_name = "SUFFIX"
+    )]
+    pub in_place: Option<String>,
+    /// Print every line after running the --loop filter on it, in place of the filter's value, so that the filter can transform `line`
+    #[arg(short, long = "print", requires = "filter", conflicts_with = "output")]
+    pub print_lines: bool,
+    /// Parse each --loop input line as JSON, into a `serde_json::Value` named `rec`. Blank lines are ignored
+    #[arg(long, requires = "filter", conflicts_with_all(["csv", "field_separator"]))]
+    pub jsonl: bool,
+    /// Read --loop input as CSV with a header row, giving each record as `rec`, a map from column name to value, as well as `fields` and `nf`
+    #[arg(long, requires = "filter", conflicts_with_all(["field_separator", "print_lines"]))]
+    pub csv: bool,
+    /// Skip malformed --jsonl or --csv records with a warning, instead of failing on the first one
+    #[arg(long)]
+    pub skip_malformed: bool,
+    /// Run the --loop filter on batches of records in parallel on this many threads, or all available cores for 0, printing the output in input order. The filter only has shared access to state from --begin
+    #[arg(short, long, requires = "filter", value_name = "N")]
+    pub jobs: Option<usize>,
     /// Confirm that multiple main methods are valid for this script
     #[arg(short, long)]
     pub multimain: bool,
@@ -108,9 +142,36 @@ pub struct Cli {
         conflicts_with("multimain")
     )]
     pub unquote: Option<bool>,
+    /// Format for printing the value of an expression: display (falling back to debug), debug or pretty. Default: config value / display.
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub format: Option<ResultFormat>,
+    /// Output the value of an expression or loop filter as text, as JSON for other programs to parse, or as rich tables and charts
+    #[arg(long, value_enum, default_value_t = OutputMode::Text, value_name = "MODE")]
+    pub output: OutputMode,
+    /// Name of a wrapper template from the configuration, or path of a template file, to wrap a snippet or loop filter in
+    #[arg(long, value_name = "TEMPLATE")]
+    pub template: Option<String>,
+    /// Watch the script and any modules and path dependencies it uses, and rebuild and rerun it on every change
+    #[arg(short, long, requires = "script", conflicts_with_all(["executable", "check", "norun"]))]
+    pub watch: bool,
+    /// Clear the screen before each rerun in --watch mode
+    #[arg(long, requires = "watch")]
+    pub clear: bool,
     /// Edit configuration
     #[arg(short = 'C', long, conflicts_with_all(["generate", "build", "executable"]))]
     pub config: bool,
+    /// Show Cargo's raw build output instead of thag's condensed rendering of diagnostics
+    #[arg(long)]
+    pub cargo_output: bool,
+    /// If the build fails, apply the compiler's suggested fixes to the source and try again
+    #[arg(long, conflicts_with = "cargo_output")]
+    pub fix: bool,
+    /// Format of thag's progress messages: human-readable text, or newline-delimited JSON events for tools
+    #[arg(long, value_enum, default_value_t = MessageFormat::Human, value_name = "FORMAT")]
+    pub message_format: MessageFormat,
+    /// Write JSON events to this file instead of stderr. Used with --message-format json
+    #[arg(long, value_name = "PATH")]
+    pub message_file: Option<PathBuf>,
 }
 
 /// Getter for clap command-line arguments
@@ -169,6 +230,8 @@ bitflags! {
         const QUIETER = 262_144;
         const UNQUOTE = 524_288;
         const CONFIG = 1_048_576;
+        const CARGO_OUTPUT = 2_097_152;
+        const FIX = 4_194_304;
     }
 }
 
@@ -263,8 +326,20 @@ pub fn get_proc_flags(args: &Cli) -> Result<ProcFlags, ThagError> {
         proc_flags.set(ProcFlags::UNQUOTE, unquote);
 
         proc_flags.set(ProcFlags::CONFIG, args.config);
+        proc_flags.set(ProcFlags::CARGO_OUTPUT, args.cargo_output);
+        proc_flags.set(ProcFlags::FIX, args.fix);
 
-        if !is_loop && (args.toml.is_some() || args.begin.is_some() || args.end.is_some()) {
+        if !is_loop
+            && (args.toml.is_some()
+                || args.begin.is_some()
+                || args.end.is_some()
+                || args.field_separator.is_some()
+                || args.in_place.is_some()
+                || args.print_lines
+                || args.jsonl
+                || args.csv
+                || args.jobs.is_some())
+        {
             if args.toml.is_some() {
                 eprintln!("Option --toml (-T) requires --loop (-l)");
             }
@@ -274,8 +349,35 @@ pub fn get_proc_flags(args: &Cli) -> Result<ProcFlags, ThagError> {
             if args.end.is_some() {
                 eprintln!("Option --end (-E) requires --loop (-l)");
             }
+            if args.field_separator.is_some() {
+                eprintln!("Option --field-separator (-F) requires --loop (-l)");
+            }
+            if args.in_place.is_some() {
+                eprintln!("Option --in-place (-i) requires --loop (-l)");
+            }
+            if args.print_lines {
+                eprintln!("Option --print (-p) requires --loop (-l)");
+            }
+            if args.jsonl {
+                eprintln!("Option --jsonl requires --loop (-l)");
+            }
+            if args.csv {
+                eprintln!("Option --csv requires --loop (-l)");
+            }
+            if args.jobs.is_some() {
+                eprintln!("Option --jobs (-j) requires --loop (-l)");
+            }
             return Err("Missing --loop option".into());
         }
+        if args.slurp && args.expression.is_none() {
+            return Err("Option --slurp (-S) requires --expr (-e)".into());
+        }
+        if args.skip_malformed && !(args.jsonl || args.csv) {
+            return Err("Option --skip-malformed requires --jsonl or --csv".into());
+        }
+        if args.in_place.is_some() && args.args.is_empty() {
+            return Err("Option --in-place (-i) requires input files after --".into());
+        }
 
         // Check all good
         let formatted = proc_flags.to_string();
@@ -11,19 +11,18 @@ use crate::errors::ThagError;
 use crate::logging::Verbosity;
 use crate::nu_resolve_style;
 
+use crate::render::RESULT_FILE_VAR;
 use crate::shared::debug_timings;
 use crate::shared::{Ast, BuildState};
-use crate::{log, MessageLevel};
+use crate::source_map::SourceSegment;
+use crate::{log, MAYBE_CONFIG};
 use crate::{DYNAMIC_SUBDIR, REPL_SUBDIR, TEMP_SCRIPT_NAME, TMPDIR};
 
 use cargo_toml::{Edition, Manifest};
 use firestorm::profile_fn;
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::any::Any;
-use std::collections::HashMap;
 use std::fs::{remove_dir_all, remove_file, OpenOptions};
-use std::hash::BuildHasher;
 
 use std::io::BufRead;
 use std::io::{self, Write};
@@ -31,21 +30,17 @@ use std::option::Option;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use std::fs;
 use std::process::Output;
 use std::time::{Instant, SystemTime};
-use std::{fs, process};
-use syn::Type::Tuple;
+use strum::{Display, EnumString};
 use syn::{
     visit::Visit,
     visit_mut::{self, VisitMut},
-    BinOp::{
-        AddAssign, BitAndAssign, BitOrAssign, BitXorAssign, DivAssign, MulAssign, RemAssign,
-        ShlAssign, ShrAssign, SubAssign,
-    },
 };
 use syn::{
-    AttrStyle, Expr, ExprBlock, File, Item, ItemExternCrate, ItemMod, ReturnType, Stmt, UsePath,
-    UseRename,
+    AttrStyle, Expr, ExprBlock, File, FnArg, Item, ItemExternCrate, ItemMod, Pat, PatIdent,
+    PatType, ReturnType, Stmt, Type, UsePath, UseRename,
 };
 
 // To move inner attributes out of a syn AST for a snippet.
@@ -461,7 +456,8 @@ pub fn extract_ast_expr(rs_source: &str) -> Result<Expr, syn::Error> {
 
 /// Process a Rust expression
 /// # Errors
-/// Will return `Err` if there is any errors encountered opening or writing to the file.
+/// Will return `Err` if there is any errors encountered opening or writing to the file,
+/// or any error returned by the generate, build or run of the expression.
 pub fn process_expr(
     expr_ast: Expr,
     build_state: &mut BuildState,
@@ -472,9 +468,7 @@ pub fn process_expr(
 ) -> Result<(), ThagError> {
     let syntax_tree = Some(Ast::Expr(expr_ast));
     write_source(&build_state.source_path, rs_source)?;
-    let result = gen_build_run(args, proc_flags, build_state, syntax_tree, start);
-    log!(Verbosity::Normal, "{result:?}");
-    Ok(())
+    gen_build_run(args, proc_flags, build_state, syntax_tree, start)
 }
 
 /// Convert a Path to a string value, assuming the path contains only valid characters.
@@ -596,7 +590,7 @@ pub fn count_main_methods(syntax_tree: &Ast) -> usize {
     impl<'a> Visit<'a> for FindMainFns {
         fn visit_item_fn(&mut self, node: &'a syn::ItemFn) {
             profile_fn!(visit_item_fn);
-            if node.sig.ident == "main" && node.sig.inputs.is_empty() {
+            if node.sig.ident == "main" {
                 self.main_method_count += 1;
             }
         }
@@ -618,6 +612,94 @@ pub fn count_main_methods(syntax_tree: &Ast) -> usize {
     finder.main_method_count
 }
 
+/// Determine whether a script has a top-level `main()` method with parameters, such as
+/// `fn main(path: PathBuf, count: u32)`, which is to be rewritten by `wrap_typed_main`.
+#[must_use]
+pub fn has_typed_main(syntax_tree: &Ast) -> bool {
+    profile_fn!(has_typed_main);
+    let is_typed_main = |item: &Item| matches!(item, Item::Fn(item_fn) if item_fn.sig.ident == "main" && !item_fn.sig.inputs.is_empty());
+    match syntax_tree {
+        Ast::File(ast) => ast.items.iter().any(is_typed_main),
+        Ast::Expr(Expr::Block(expr_block)) => expr_block
+            .block
+            .stmts
+            .iter()
+            .any(|stmt| matches!(stmt, Stmt::Item(item) if is_typed_main(item))),
+        Ast::Expr(_) => false,
+    }
+}
+
+/// Rewrite a `main()` method with typed parameters into one without, that parses the
+/// parameters from the command line using `clap`. The parameters become the fields of a
+/// `clap::Parser` struct, so that their doc comments and any `#[arg(...)]` attributes
+/// determine the help and the form of the arguments, and the doc comments of `main()`
+/// become the description of the program. By default a `bool` parameter becomes a flag,
+/// an `Option` parameter becomes an optional `--name <VALUE>` argument, and any other
+/// parameter, including a `Vec`, becomes a positional argument.
+/// # Errors
+/// Will return `Err` if the source can't be parsed, or if a parameter is not a simple
+/// identifier with a type.
+pub fn wrap_typed_main(rs_source: &str) -> Result<String, ThagError> {
+    profile_fn!(wrap_typed_main);
+    let mut file = syn::parse_file(rs_source)?;
+    let Some(main_fn) = file.items.iter_mut().find_map(|item| match item {
+        Item::Fn(item_fn) if item_fn.sig.ident == "main" && !item_fn.sig.inputs.is_empty() => {
+            Some(item_fn)
+        }
+        _ => None,
+    }) else {
+        return Ok(rs_source.to_string());
+    };
+
+    let mut fields = Vec::new();
+    let mut bindings = Vec::new();
+    for input in &main_fn.sig.inputs {
+        let FnArg::Typed(PatType { attrs, pat, ty, .. }) = input else {
+            return Err("The main method can't take a `self` parameter".into());
+        };
+        let Pat::Ident(PatIdent {
+            mutability, ident, ..
+        }) = pat.as_ref()
+        else {
+            return Err(format!(
+                "Parameter `{}` of the main method must be a simple name to be used as a command-line argument",
+                quote::quote!(#pat)
+            )
+            .into());
+        };
+        let has_arg_attr = attrs.iter().any(|attr| attr.path().is_ident("arg"));
+        let is_named = matches!(ty.as_ref(), Type::Path(type_path)
+            if type_path.path.segments.last().is_some_and(|segment| segment.ident == "bool" || segment.ident == "Option"));
+        let arg_attr = if is_named && !has_arg_attr {
+            quote::quote!(#[arg(long)])
+        } else {
+            quote::quote!()
+        };
+        fields.push(quote::quote!(#(#attrs)* #arg_attr #ident: #ty));
+        bindings.push(quote::quote!(#mutability #ident));
+    }
+    let docs: Vec<_> = main_fn
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .cloned()
+        .collect();
+
+    main_fn.sig.inputs.clear();
+    main_fn.block.stmts.insert(
+        0,
+        syn::parse_quote!(let ThagMainArgs { #(#bindings),* } = <ThagMainArgs as clap::Parser>::parse();),
+    );
+    file.items.push(syn::parse_quote!(
+        #(#docs)*
+        #[derive(clap::Parser, Debug)]
+        struct ThagMainArgs {
+            #(#fields),*
+        }
+    ));
+    Ok(quote::quote!(#file).to_string())
+}
+
 /// Parse the code into an abstract syntax tree for inspection
 /// if possible (should work if the code will compile)
 #[must_use]
@@ -701,31 +783,446 @@ pub fn extract_inner_attribs(rs_source: &str) -> (String, String) {
     (inner_attribs, rest)
 }
 
-/// Convert a Rust code snippet into a program by wrapping it in a main method and other scaffolding.
+/// Convert a Rust code snippet into a program by wrapping it in a main method and other scaffolding,
+/// as laid out by the given wrapper template.
+/// If the snippet needs an async runtime, the main method is async and runs on that runtime.
 #[must_use]
-pub fn wrap_snippet(inner_attribs: &str, body: &str) -> String {
+pub fn wrap_snippet(
+    template: &WrapperTemplate,
+    inner_attribs: &str,
+    body: &str,
+    async_runtime: AsyncRuntime,
+) -> String {
     profile_fn!(wrap_snippet);
 
     debug_log!("In wrap_snippet");
 
     debug_log!("In wrap_snippet: inner_attribs={inner_attribs:#?}");
-    let wrapped_snippet = format!(
-        r##"#![allow(unused_imports,unused_macros,unused_variables,dead_code)]
-{inner_attribs}
-use std::error::Error;
-use std::io;
-use std::io::prelude::*;
+    let wrapped_snippet = template.render(inner_attribs, "", body, "", async_runtime);
+
+    debug_log!("wrapped_snippet={wrapped_snippet}");
+    wrapped_snippet
+}
+
+/// The default template for wrapping a snippet or loop filter in a program.
+pub const DEFAULT_TEMPLATE: &str = r##"#![allow(unused_imports,unused_macros,unused_variables,dead_code)]
+{{inner_attribs}}
+{{prelude}}
 
 #[doc = "Wrapped snippet in main method to make it a program."]
-fn main() -> Result<(), Box<dyn Error>> {{
-{body}
+{{main}} -> Result<(), Box<dyn Error>> {
+{{begin}}
+{{body}}
+{{end}}
 Ok(())
-}}
-"##
-    );
+}
+"##;
+
+/// The code that `--slurp` places before an expression to read all of stdin into `input`,
+/// with its lines as `lines`.
+pub const SLURP_STDIN: &str = r"#[allow(unused_variables)]
+let input = std::io::read_to_string(std::io::stdin())?;
+#[allow(unused_mut, unused_variables)]
+let mut lines: Vec<&str> = input.lines().collect();
+";
+
+/// The imports that the `{{prelude}}` placeholder of a wrapper template always provides.
+pub const DEFAULT_PRELUDE: &str = "use std::error::Error;
+use std::io;
+use std::io::prelude::*;";
 
-    debug_log!("wrapped_snippet={wrapped_snippet}");
-    wrapped_snippet
+/// A template for wrapping a snippet or loop filter in a program, with the imports that
+/// its `{{prelude}}` placeholder expands to.
+///
+/// The placeholders are:
+/// - `{{inner_attribs}}`: any inner attributes of the snippet, promoted to crate level
+/// - `{{prelude}}`: the default imports, followed by any extra ones configured for the template
+/// - `{{main}}`: the signature of the main method up to its return type, made async if needed
+/// - `{{begin}}`: the pre-loop logic of a loop filter
+/// - `{{body}}`: the snippet, or the loop that runs a loop filter on each line of input
+/// - `{{end}}`: the post-loop logic of a loop filter
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WrapperTemplate {
+    pub text: String,
+    pub prelude: String,
+}
+
+impl Default for WrapperTemplate {
+    fn default() -> Self {
+        Self {
+            text: DEFAULT_TEMPLATE.to_string(),
+            prelude: DEFAULT_PRELUDE.to_string(),
+        }
+    }
+}
+
+impl WrapperTemplate {
+    /// Fill in the placeholders of the template. Placeholders are only recognised in the
+    /// template itself, not in the code that replaces them, and unknown ones are left as is.
+    #[must_use]
+    pub fn render(
+        &self,
+        inner_attribs: &str,
+        begin: &str,
+        body: &str,
+        end: &str,
+        async_runtime: AsyncRuntime,
+    ) -> String {
+        profile_fn!(render);
+        lazy_static! {
+            static ref PLACEHOLDER: Regex = Regex::new(r"\{\{(\w+)\}\}").unwrap();
+        }
+        let main_fn = async_runtime.main_attribute().map_or_else(
+            || "fn main()".to_string(),
+            |attribute| format!("{attribute}\nasync fn main()"),
+        );
+        PLACEHOLDER
+            .replace_all(&self.text, |caps: &regex::Captures| {
+                match &caps[1] {
+                    "inner_attribs" => inner_attribs,
+                    "prelude" => &self.prelude,
+                    "main" => &main_fn,
+                    "begin" => begin,
+                    "body" => body,
+                    "end" => end,
+                    _ => &caps[0],
+                }
+                .to_string()
+            })
+            .into_owned()
+    }
+}
+
+/// Determine the wrapper template to use: the one named on the command line, or else the
+/// one named by `template` in the `[thag]` table of the script's toml block, or else the
+/// default. A name is looked up in the `[templates]` section of the configuration, and
+/// failing that taken as the path of a template file.
+/// # Errors
+/// Will return `Err` if there is no such template, or if its file can't be read.
+pub fn wrapper_template(args: &Cli, rs_source: &str) -> Result<WrapperTemplate, ThagError> {
+    profile_fn!(wrapper_template);
+    let maybe_name = args.template.clone().or_else(|| {
+        extract_toml_block(rs_source)
+            .and_then(|toml| toml.parse::<toml::Table>().ok())
+            .and_then(|table| {
+                table
+                    .get("thag")?
+                    .get("template")?
+                    .as_str()
+                    .map(String::from)
+            })
+    });
+    let Some(name) = maybe_name else {
+        return Ok(WrapperTemplate::default());
+    };
+    debug_log!("template name={name}");
+
+    let maybe_template = (*MAYBE_CONFIG)
+        .as_ref()
+        .and_then(|config| config.templates.get(&name));
+    let mut template = WrapperTemplate::default();
+    if let Some(configured) = maybe_template {
+        if let Some(ref text) = configured.text {
+            template.text.clone_from(text);
+        } else if let Some(ref file) = configured.file {
+            template.text = fs::read_to_string(file).map_err(|e| {
+                format!(
+                    "Error reading file {} of template {name}: {e}",
+                    file.display()
+                )
+            })?;
+        }
+        if let Some(ref prelude) = configured.prelude {
+            template.prelude = format!("{DEFAULT_PRELUDE}\n{}", prelude.trim_end());
+        }
+    } else if Path::new(&name).is_file() {
+        template.text = read_file_contents(Path::new(&name))?;
+    } else {
+        return Err(format!(
+            "Unknown template {name}: not found in the configuration or as a file"
+        )
+        .into());
+    }
+    Ok(template)
+}
+
+/// The async runtime to run a snippet on if it uses `.await` or async blocks.
+#[derive(Clone, Copy, Debug, Default, Display, EnumString, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+pub enum AsyncRuntime {
+    #[default]
+    Tokio,
+    AsyncStd,
+    /// No runtime: the snippet is left to fail to compile
+    None,
+}
+
+impl AsyncRuntime {
+    /// The attribute that makes an `async fn main` run on this runtime.
+    #[must_use]
+    pub const fn main_attribute(self) -> Option<&'static str> {
+        match self {
+            Self::Tokio => Some("#[tokio::main]"),
+            Self::AsyncStd => Some("#[async_std::main]"),
+            Self::None => None,
+        }
+    }
+}
+
+/// Determine the async runtime to use from the configuration, defaulting to tokio.
+#[must_use]
+pub fn async_runtime() -> AsyncRuntime {
+    (*MAYBE_CONFIG)
+        .as_ref()
+        .map_or_else(AsyncRuntime::default, |config| config.misc.async_runtime)
+}
+
+/// Determine whether a snippet expression needs an async runtime, because it awaits a
+/// future or creates an async block. Any functions the snippet defines are skipped, as
+/// they are responsible for their own bodies.
+#[must_use]
+pub fn is_async(expr: &Expr) -> bool {
+    profile_fn!(is_async);
+
+    #[derive(Default)]
+    struct FindAsync {
+        found: bool,
+    }
+
+    impl<'ast> Visit<'ast> for FindAsync {
+        fn visit_expr_await(&mut self, _: &'ast syn::ExprAwait) {
+            self.found = true;
+        }
+
+        fn visit_expr_async(&mut self, _: &'ast syn::ExprAsync) {
+            self.found = true;
+        }
+
+        fn visit_item_fn(&mut self, _: &'ast syn::ItemFn) {}
+
+        fn visit_impl_item_fn(&mut self, _: &'ast syn::ImplItemFn) {}
+
+        fn visit_trait_item_fn(&mut self, _: &'ast syn::TraitItemFn) {}
+    }
+
+    let mut finder = FindAsync::default();
+    finder.visit_expr(expr);
+    finder.found
+}
+
+/// How the value of a snippet expression is printed.
+#[derive(Clone, Copy, Debug, Default, Display, EnumString, PartialEq, Eq, clap::ValueEnum)]
+#[strum(serialize_all = "snake_case")]
+pub enum ResultFormat {
+    /// `Display` if the type implements it, otherwise `Debug`
+    #[default]
+    Display,
+    /// `Debug`
+    Debug,
+    /// Pretty-printed `Debug` (`{:#?}`), for nested structures
+    Pretty,
+}
+
+/// Whether the value of a snippet expression is printed as text or as JSON.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputMode {
+    /// Text, as chosen by `--format`
+    #[default]
+    Text,
+    /// JSON, serialised with `serde_json`: one line per result
+    Json,
+    /// Collections rendered as tables, sparklines or histograms, and anything else as text
+    Rich,
+}
+
+/// Determine the result format from the command line, falling back to the configuration.
+#[must_use]
+pub fn result_format(args: &Cli) -> ResultFormat {
+    args.format.unwrap_or_else(|| {
+        (*MAYBE_CONFIG)
+            .as_ref()
+            .map_or_else(ResultFormat::default, |config| config.misc.format)
+    })
+}
+
+/// Wrap a snippet expression in code that prints its value, unless the value is the unit
+/// type `()`, in which case it prints nothing. Rather than guessing the type from the
+/// syntax, this leaves the choice to the compiler by means of autoref specialisation:
+/// method resolution picks the impl with the most references that matches the type,
+/// before auto-dereferencing to try the next. So the impl for `&&ThagResult<()>` takes
+/// precedence over the `Display` impl for `&ThagResult<T>`, which in turn takes
+/// precedence over the `Debug` impl for `ThagResult<T>`.
+#[must_use]
+pub fn print_result(expr: &str, format: ResultFormat, unquote: bool) -> String {
+    profile_fn!(print_result);
+    let debug = if format == ResultFormat::Pretty {
+        "{:#?}"
+    } else {
+        "{:?}"
+    };
+    let print_debug = if unquote {
+        format!(r#"println!("{{}}", format!("{debug}", self.0).trim_matches('"'));"#)
+    } else {
+        format!(r#"println!("{debug}", self.0);"#)
+    };
+    let display_impl = if format == ResultFormat::Display {
+        r#"
+    trait ThagPrintDisplay {
+        fn thag_print(&self);
+    }
+    impl<T: std::fmt::Display> ThagPrintDisplay for &ThagResult<T> {
+        fn thag_print(&self) {
+            println!("{}", self.0);
+        }
+    }"#
+    } else {
+        ""
+    };
+    format!(
+        r"#[allow(dead_code, unreachable_code, unused_braces)]
+{{
+    struct ThagResult<T>(T);
+    trait ThagPrintUnit {{
+        fn thag_print(&self);
+    }}
+    impl ThagPrintUnit for &&ThagResult<()> {{
+        fn thag_print(&self) {{}}
+    }}{display_impl}
+    trait ThagPrintDebug {{
+        fn thag_print(&self);
+    }}
+    impl<T: std::fmt::Debug> ThagPrintDebug for ThagResult<T> {{
+        fn thag_print(&self) {{
+            {print_debug}
+        }}
+    }}
+    (&&&ThagResult({expr})).thag_print();
+}}"
+    )
+}
+
+/// Wrap a snippet expression in code that prints its value as a line of JSON, unless the
+/// value is the unit type `()`, in which case it prints nothing. As in `print_result`,
+/// autoref specialisation picks the impl: here the last resort is for types that don't
+/// implement `serde::Serialize`, and reports the type as an error when the program runs.
+#[must_use]
+pub fn print_json_result(expr: &str) -> String {
+    profile_fn!(print_json_result);
+    format!(
+        r#"#[allow(dead_code, unreachable_code, unused_braces)]
+{{
+    struct ThagResult<T>(T);
+    trait ThagPrintUnit {{
+        fn thag_print(&self);
+    }}
+    impl ThagPrintUnit for &&ThagResult<()> {{
+        fn thag_print(&self) {{}}
+    }}
+    trait ThagPrintJson {{
+        fn thag_print(&self);
+    }}
+    impl<T: serde::Serialize> ThagPrintJson for &ThagResult<T> {{
+        fn thag_print(&self) {{
+            match serde_json::to_string(&self.0) {{
+                Ok(json) => println!("{{json}}"),
+                Err(e) => {{
+                    eprintln!("Failed to output the result as JSON: {{e}}");
+                    std::process::exit(1);
+                }}
+            }}
+        }}
+    }}
+    trait ThagPrintNotSerialize {{
+        fn thag_print(&self);
+    }}
+    impl<T> ThagPrintNotSerialize for ThagResult<T> {{
+        fn thag_print(&self) {{
+            eprintln!(
+                "Can't output the result as JSON: type `{{}}` doesn't implement `serde::Serialize`",
+                std::any::type_name::<T>()
+            );
+            std::process::exit(1);
+        }}
+    }}
+    (&&&ThagResult({expr})).thag_print();
+}}"#
+    )
+}
+
+/// Wrap a snippet expression in code that passes its value to `thag` to render, by writing
+/// its JSON serialisation to the file named by the `THAG_RESULT_FILE` environment variable.
+/// As in `print_result`, autoref specialisation picks the impl: after the unit type, any
+/// type that implements `serde::Serialize` is written to the file, and other types are
+/// printed as text as usual. A value that fails to serialise is printed with `Debug` if
+/// it can be.
+#[must_use]
+pub fn print_rich_result(expr: &str, format: ResultFormat) -> String {
+    profile_fn!(print_rich_result);
+    let debug = if format == ResultFormat::Pretty {
+        "{:#?}"
+    } else {
+        "{:?}"
+    };
+    format!(
+        r#"#[allow(dead_code, unreachable_code, unused_braces)]
+{{
+    struct ThagResult<T>(T);
+    trait ThagPrintUnit {{
+        fn thag_print(&self);
+    }}
+    impl ThagPrintUnit for &&&&ThagResult<()> {{
+        fn thag_print(&self) {{}}
+    }}
+    // The JSON is serialised in full before anything is written, so that a value that JSON
+    // can't represent, such as a map with tuple keys, leaves no partial file behind
+    fn thag_write_result<T: serde::Serialize>(value: &T) -> bool {{
+        serde_json::to_string(value).is_ok_and(|json| {{
+            std::env::var_os("{RESULT_FILE_VAR}")
+                .is_some_and(|path| std::fs::write(path, json).is_ok())
+        }})
+    }}
+    trait ThagPrintRichDebug {{
+        fn thag_print(&self);
+    }}
+    impl<T: serde::Serialize + std::fmt::Debug> ThagPrintRichDebug for &&&ThagResult<T> {{
+        fn thag_print(&self) {{
+            if !thag_write_result(&self.0) {{
+                println!("{debug}", self.0);
+            }}
+        }}
+    }}
+    trait ThagPrintRich {{
+        fn thag_print(&self);
+    }}
+    impl<T: serde::Serialize> ThagPrintRich for &&ThagResult<T> {{
+        fn thag_print(&self) {{
+            if !thag_write_result(&self.0) {{
+                match serde_json::to_string_pretty(&self.0) {{
+                    Ok(json) => println!("{{json}}"),
+                    Err(err) => eprintln!("The result can't be shown: {{err}}"),
+                }}
+            }}
+        }}
+    }}
+    trait ThagPrintDisplay {{
+        fn thag_print(&self);
+    }}
+    impl<T: std::fmt::Display> ThagPrintDisplay for &ThagResult<T> {{
+        fn thag_print(&self) {{
+            println!("{{}}", self.0);
+        }}
+    }}
+    trait ThagPrintDebug {{
+        fn thag_print(&self);
+    }}
+    impl<T: std::fmt::Debug> ThagPrintDebug for ThagResult<T> {{
+        fn thag_print(&self) {{
+            println!("{debug}", self.0);
+        }}
+    }}
+    (&&&&&ThagResult({expr})).thag_print();
+}}"#
+    )
 }
 
 /// Write the source to the destination source-code path.
@@ -848,66 +1345,523 @@ pub fn create_temp_source_file() -> Result<PathBuf, ThagError> {
     Ok(path)
 }
 
-/// Combine the elements of a loop filter into a well-formed program.
-#[must_use]
-pub fn build_loop(args: &Cli, filter: String) -> String {
+/// Combine the elements of a loop filter into a well-formed program, as laid out by the
+/// wrapper template.
+/// # Errors
+/// Will return `Err` if the wrapper template can't be found or read, or if the filter is
+/// to run in parallel but breaks out of the record loop.
+pub fn build_loop(args: &Cli, filter: String) -> Result<String, ThagError> {
     profile_fn!(build_loop);
-    let maybe_ast = extract_ast_expr(&filter);
-    let returns_unit = maybe_ast.map_or_else(
-        |_| {
-            let expr_any: &dyn Any = &filter;
-            dbg!(&filter);
-            !expr_any.is::<()>()
-        },
-        |expr| is_unit_return_type(&expr),
-    );
+    if loop_jobs(args) > 1 && breaks_record_loop(&filter) {
+        return Err(ThagError::Args(Box::new(
+            "A --loop filter run with --jobs can't `break` out of the record loop, as the records are processed independently. Use `continue` to skip a record."
+                .into(),
+        )));
+    }
     let loop_toml = &args.toml;
     let loop_begin = &args.begin;
     let loop_end = &args.end;
-    let filter = if returns_unit {
-        filter
+    let filter = format!("{{{filter}}}");
+    let filter = if args.print_lines {
+        format!("{filter};\n            println!(\"{{line}}\");")
     } else {
-        format!(r#"println!("{{:?}}", {});"#, filter)
+        match args.output {
+            OutputMode::Text | OutputMode::Rich => {
+                print_result(&filter, result_format(args), false)
+            }
+            OutputMode::Json => print_json_result(&filter),
+        }
     };
-    // dbg!(&filter);
 
-    format!(
-        r#"{}
-use std::io::{{self, BufRead}};
-fn main() -> Result<(), Box<dyn std::error::Error>> {{
-    {}
-    // Read from stdin and execute main loop for each line
+    let toml_block = loop_toml.as_ref().map_or_else(String::new, |toml| {
+        log!(Verbosity::Verbose, "toml={toml}");
+        format!(
+            r#"/*[toml]
+{toml}
+*/
+"#
+        )
+    });
+    let template = wrapper_template(args, &toml_block)?;
+    let (split_setup, split_line) = field_splitting(args.field_separator.as_deref());
+    let records = RecordParsing::new(args);
+    let (edit_start, edit_finish) =
+        args.in_place
+            .as_ref()
+            .map_or_else(Default::default, |suffix| {
+                (
+                    "        let thag_temp_path = thag_edit_start(filename)?;\n".to_string(),
+                    format!("        thag_edit_finish(filename, &thag_temp_path, {suffix:?})?;\n"),
+                )
+            });
+    let output_support = output_support(args);
+    let file_setup = &records.file_setup;
+    let jobs = loop_jobs(args);
+    let record_loop = match jobs {
+        1 => {
+            let loop_head = &records.loop_head;
+            let record_setup = &records.record_setup;
+            format!(
+                r"{loop_head}            i += 1;
+            // Record numbers overall and within the current input, as per awk's NR and FNR
+            #[allow(unused_variables)]
+            let (nr, fnr) = (i, thag_index + 1);
+{record_setup}{split_line}            {filter}
+        }}
+"
+            )
+        }
+        _ => parallel_record_loop(&records, &split_line, &filter, jobs),
+    };
+    let body = format!(
+        r#"{output_support}    // Read from each input file in turn, or from stdin if there are none, and execute
+    // main loop for each line
+{split_setup}    let thag_files: Vec<String> = std::env::args().skip(1).collect();
+    let thag_inputs: Vec<Option<String>> = if thag_files.is_empty() {{
+        vec![None]
+    }} else {{
+        thag_files.into_iter().map(Some).collect()
+    }};
     #[allow(unused_variables)]
     let mut i = 0;
-    let stdin = io::stdin();
-    for line in stdin.lock().lines() {{
-        let line = line?;
-        i += 1;
-        {filter}
+    for thag_input in thag_inputs {{
+        #[allow(unused_variables)]
+        let filename = thag_input.as_deref().unwrap_or("-");
+        let thag_reader: Box<dyn std::io::BufRead> = match thag_input {{
+            Some(ref path) => Box::new(std::io::BufReader::new(std::fs::File::open(path)?)),
+            None => Box::new(std::io::stdin().lock()),
+        }};
+{edit_start}{file_setup}{record_loop}{edit_finish}    }}"#
+    );
+
+    Ok(toml_block
+        + &template.render(
+            "",
+            loop_begin.as_ref().map_or("", |prelude| {
+                log!(Verbosity::Verbose, "prelude={prelude}");
+                prelude
+            }),
+            &body,
+            loop_end.as_ref().map_or("", |postlude| {
+                log!(Verbosity::Verbose, "postlude={postlude}");
+                postlude
+            }),
+            AsyncRuntime::None,
+        ))
+}
+
+// Determine the number of threads to run a loop filter on: 1 by default, or all available
+// cores for `--jobs 0`.
+fn loop_jobs(args: &Cli) -> usize {
+    match args.jobs {
+        None => 1,
+        Some(0) => std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get),
+        Some(jobs) => jobs,
+    }
+}
+
+/// The number of records a parallel loop reads before running the filter on them.
+const PARALLEL_BATCH_SIZE: usize = 4096;
+
+// Generate the code that routes the output of a loop filter when it can't simply go to
+// stdout: when editing files in place, and when running the filter in parallel. Shadowing
+// `print!` and `println!` sends their output to the file being edited, via a temporary
+// file that then atomically replaces it, or captures it so that the output for each
+// record can be printed in order.
+fn output_support(args: &Cli) -> String {
+    let parallel = loop_jobs(args) > 1;
+    if args.in_place.is_none() && !parallel {
+        return String::new();
+    }
+    let mut support = String::new();
+    let mut routing = String::new();
+    if parallel {
+        support.push_str(
+            r"    thread_local! {
+        static THAG_CAPTURE: std::cell::RefCell<Option<String>> = std::cell::RefCell::new(None);
+    }
+",
+        );
+        routing.push_str(
+            r#"        let captured = THAG_CAPTURE.with(|capture| {
+            capture.borrow_mut().as_mut().map(|capture| {
+                std::fmt::Write::write_fmt(capture, args).expect("Error capturing output");
+            })
+        });
+        if captured.is_some() {
+            return;
+        }
+"#,
+        );
+    }
+    if args.in_place.is_some() {
+        support.push_str(
+            r"    thread_local! {
+        static THAG_OUT: std::cell::RefCell<Option<std::io::BufWriter<std::fs::File>>> =
+            std::cell::RefCell::new(None);
+    }
+",
+        );
+        routing.push_str(
+            r#"        let written = THAG_OUT.with(|out| {
+            out.borrow_mut().as_mut().map(|out| {
+                std::io::Write::write_fmt(out, args).expect("Error writing to file being edited");
+            })
+        });
+        if written.is_some() {
+            return;
+        }
+"#,
+        );
+    }
+    support.push_str(&format!(
+        r#"    fn thag_print(args: std::fmt::Arguments) {{
+{routing}        std::print!("{{args}}");
     }}
-    {}
-    Ok(())
-}}
+    #[allow(unused_macros)]
+    macro_rules! print {{
+        ($($arg:tt)*) => {{ thag_print(format_args!($($arg)*)) }};
+    }}
+    #[allow(unused_macros)]
+    macro_rules! println {{
+        () => {{ print!("\n") }};
+        ($($arg:tt)*) => {{ print!("{{}}\n", format_args!($($arg)*)) }};
+    }}
+"#
+    ));
+    if args.in_place.is_some() {
+        support.push_str(
+            r#"    fn thag_edit_start(path: &str) -> std::io::Result<std::path::PathBuf> {
+        let path = std::path::Path::new(path);
+        let file_name = path.file_name().ok_or(std::io::ErrorKind::InvalidInput)?;
+        let temp_path = path.with_file_name(format!(".{}.thag-tmp", file_name.to_string_lossy()));
+        let file = std::fs::File::create(&temp_path)?;
+        file.set_permissions(std::fs::metadata(path)?.permissions())?;
+        THAG_OUT.with(|out| *out.borrow_mut() = Some(std::io::BufWriter::new(file)));
+        Ok(temp_path)
+    }
+    fn thag_edit_finish(
+        path: &str,
+        temp_path: &std::path::Path,
+        backup_suffix: &str,
+    ) -> std::io::Result<()> {
+        if let Some(mut out) = THAG_OUT.with(|out| out.borrow_mut().take()) {
+            std::io::Write::flush(&mut out)?;
+        }
+        if !backup_suffix.is_empty() {
+            std::fs::copy(path, format!("{path}{backup_suffix}"))?;
+        }
+        std::fs::rename(temp_path, path)
+    }
 "#,
-        loop_toml.as_ref().map_or_else(String::new, |toml| {
-            log!(Verbosity::Verbose, "toml={toml}");
-            format!(
-                r#"/*[toml]
-{toml}
-*/"#
-            )
-        }),
-        loop_begin.as_ref().map_or("", |prelude| {
-            log!(Verbosity::Verbose, "prelude={prelude}");
-            prelude
-        }),
-        loop_end.as_ref().map_or("", |postlude| {
-            log!(Verbosity::Verbose, "postlude={postlude}");
-            postlude
-        })
+        );
+    }
+    support
+}
+
+/// Determine whether a loop filter contains a `break` out of the loop over the records,
+/// as opposed to one out of a loop or labelled block of its own.
+#[must_use]
+pub fn breaks_record_loop(filter: &str) -> bool {
+    #[derive(Default)]
+    struct FindBreak {
+        loop_depth: usize,
+        found: bool,
+    }
+    impl<'a> Visit<'a> for FindBreak {
+        fn visit_expr_break(&mut self, node: &'a syn::ExprBreak) {
+            if node.label.is_none() && self.loop_depth == 0 {
+                self.found = true;
+            }
+            syn::visit::visit_expr_break(self, node);
+        }
+        fn visit_expr_for_loop(&mut self, node: &'a syn::ExprForLoop) {
+            self.visit_expr(&node.expr);
+            self.loop_depth += 1;
+            self.visit_block(&node.body);
+            self.loop_depth -= 1;
+        }
+        fn visit_expr_loop(&mut self, node: &'a syn::ExprLoop) {
+            self.loop_depth += 1;
+            self.visit_block(&node.body);
+            self.loop_depth -= 1;
+        }
+        fn visit_expr_while(&mut self, node: &'a syn::ExprWhile) {
+            self.loop_depth += 1;
+            syn::visit::visit_expr_while(self, node);
+            self.loop_depth -= 1;
+        }
+        // A `break` in a closure or nested item can't refer to the record loop
+        fn visit_expr_closure(&mut self, _node: &'a syn::ExprClosure) {}
+        fn visit_item(&mut self, _node: &'a Item) {}
+    }
+
+    profile_fn!(breaks_record_loop);
+    let Ok(block) = syn::parse_str::<syn::Block>(&format!("{{{filter}}}")) else {
+        return false;
+    };
+    let mut finder = FindBreak::default();
+    finder.visit_block(&block);
+    finder.found
+}
+
+// Generate the loop over the records of an input that runs the filter on batches of
+// records in parallel on the given number of threads, printing the output for each
+// record in order. The filter only has shared access to variables from outside it.
+// It runs in a closure of its own that returns any error as text, since the filter's
+// errors needn't be `Send`, and in a loop of a single pass, so that `continue` skips
+// the record.
+fn parallel_record_loop(
+    records: &RecordParsing,
+    split_line: &str,
+    filter: &str,
+    jobs: usize,
+) -> String {
+    let RecordParsing {
+        loop_head,
+        bindings,
+        pattern,
+        binding_type,
+        record_setup,
+        ..
+    } = records;
+    format!(
+        r#"        // Run the filter on a record, capturing its output
+        let thag_process = |(nr, fnr, {pattern}): (usize, usize, {binding_type})|
+         -> Result<String, String> {{
+            THAG_CAPTURE.with(|capture| *capture.borrow_mut() = Some(String::new()));
+            (|| -> Result<(), Box<dyn std::error::Error>> {{
+                #[allow(clippy::never_loop)]
+                for _ in 0..1 {{
+{record_setup}{split_line}                    {filter}
+                }}
+                Ok(())
+            }})()
+            .map_err(|err| err.to_string())?;
+            Ok(THAG_CAPTURE
+                .with(|capture| capture.borrow_mut().take())
+                .unwrap_or_default())
+        }};
+        // Run the filter on a batch of records, split between the threads, and print the
+        // output for each record in order
+        let thag_flush = |thag_batch: Vec<(usize, usize, {binding_type})>|
+         -> Result<(), Box<dyn std::error::Error + Send + Sync>> {{
+            let thag_process = &thag_process;
+            let thag_chunk_size = thag_batch.len().div_ceil({jobs}).max(1);
+            let mut thag_records = thag_batch.into_iter();
+            let thag_outputs = std::thread::scope(|scope| {{
+                let handles: Vec<_> = (0..{jobs})
+                    .map(|_| {{
+                        let chunk: Vec<_> = thag_records.by_ref().take(thag_chunk_size).collect();
+                        scope.spawn(move || {{
+                            chunk
+                                .into_iter()
+                                .map(thag_process)
+                                .collect::<Result<Vec<String>, _>>()
+                        }})
+                    }})
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| {{
+                        handle
+                            .join()
+                            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                    }})
+                    .collect::<Vec<_>>()
+            }});
+            for thag_output in thag_outputs {{
+                for thag_output in thag_output? {{
+                    print!("{{thag_output}}");
+                }}
+            }}
+            Ok(())
+        }};
+        let mut thag_batch = Vec::with_capacity({PARALLEL_BATCH_SIZE});
+{loop_head}            i += 1;
+            // Record numbers overall and within the current input, as per awk's NR and FNR
+            thag_batch.push((i, thag_index + 1, {bindings}));
+            if thag_batch.len() == {PARALLEL_BATCH_SIZE} {{
+                thag_flush(std::mem::take(&mut thag_batch))
+                    .map_err(|err| -> Box<dyn std::error::Error> {{ err }})?;
+            }}
+        }}
+        thag_flush(thag_batch).map_err(|err| -> Box<dyn std::error::Error> {{ err }})?;
+"#
+    )
+}
+
+/// Determine whether a loop field separator is to be used as a regular expression rather
+/// than literally: that is, if it is more than one character long and contains any
+/// regular expression metacharacters, as in awk.
+#[must_use]
+pub fn is_regex_separator(separator: &str) -> bool {
+    separator.chars().count() > 1 && regex::escape(separator) != separator
+}
+
+/// Generate the code that splits each line of loop input into `fields`, with their count
+/// in `nf`, as per awk's `-F` option, returned as the setup before the loop and the
+/// splitting in the loop. A single space splits on runs of whitespace.
+fn field_splitting(field_separator: Option<&str>) -> (String, String) {
+    let Some(separator) = field_separator else {
+        return (String::new(), String::new());
+    };
+    let (setup, split) = if separator == " " {
+        (String::new(), "line.split_whitespace()".to_string())
+    } else if is_regex_separator(separator) {
+        (
+            format!("    let thag_field_regex = regex::Regex::new({separator:?})?;\n"),
+            "thag_field_regex.split(&line)".to_string(),
+        )
+    } else {
+        (String::new(), format!("line.split({separator:?})"))
+    };
+    (
+        setup,
+        format!(
+            r"        #[allow(unused_variables)]
+        let fields: Vec<&str> = {split}.collect();
+        #[allow(unused_variables)]
+        let nf = fields.len();
+"
+        ),
     )
 }
 
+// The code to read the records of an input and make them available to the filter: lines
+// for plain text, lines parsed into `rec` for --jsonl, or header-aware records for --csv.
+struct RecordParsing {
+    // Setup before reading an input
+    file_setup: String,
+    // The head of the loop over the records of an input, which binds `thag_index` and
+    // the record's bindings, skipping or failing on any malformed record
+    loop_head: String,
+    // The record's bindings, as an expression, and as a pattern and its type for a
+    // parallel loop to pass them to the filter
+    bindings: &'static str,
+    pattern: &'static str,
+    binding_type: &'static str,
+    // Any code that derives further variables for the filter from the record's bindings
+    record_setup: String,
+}
+
+impl RecordParsing {
+    fn new(args: &Cli) -> Self {
+        // Report a malformed record, given its kind and the line number, if not already
+        // reported in the error itself, then either skip it or fail
+        let on_malformed = |kind: &str, line_number: Option<&str>| {
+            let (location, location_arg) = line_number
+                .map_or((String::new(), String::new()), |line_number| {
+                    (":{}".to_string(), format!(", {line_number}"))
+                });
+            if args.skip_malformed {
+                format!(
+                    r#"eprintln!("{{filename}}{location}: skipping malformed {kind} record: {{err}}"{location_arg});
+                    continue;"#
+                )
+            } else {
+                format!(
+                    r#"return Err(format!("{{filename}}{location}: malformed {kind} record: {{err}}"{location_arg}).into());"#
+                )
+            }
+        };
+        if args.csv {
+            let on_malformed = on_malformed("CSV", None);
+            return Self {
+                file_setup: r"        let mut thag_csv = csv::Reader::from_reader(thag_reader);
+        #[allow(unused_variables)]
+        let headers = thag_csv.headers()?.clone();
+"
+                .to_string(),
+                loop_head: format!(
+                    r"        for (thag_index, record) in thag_csv.records().enumerate() {{
+            let record = match record {{
+                Ok(record) => record,
+                Err(err) => {{
+                    {on_malformed}
+                }}
+            }};
+"
+                ),
+                bindings: "record",
+                pattern: "record",
+                binding_type: "csv::StringRecord",
+                record_setup: r"            #[allow(unused_variables)]
+            let fields: Vec<&str> = record.iter().collect();
+            #[allow(unused_variables)]
+            let nf = fields.len();
+            #[allow(unused_variables)]
+            let rec: std::collections::HashMap<&str, &str> =
+                headers.iter().zip(record.iter()).collect();
+"
+                .to_string(),
+            };
+        }
+        // A parallel loop makes `line` mutable where it passes it to the filter instead
+        let parallel = loop_jobs(args) > 1;
+        let line_binding = if args.print_lines && !parallel {
+            "mut line"
+        } else {
+            "line"
+        };
+        let mut loop_head = format!(
+            r"        for (thag_index, line) in std::io::BufRead::lines(thag_reader).enumerate() {{
+            let {line_binding} = line?;
+"
+        );
+        if args.jsonl {
+            let on_malformed = on_malformed("JSON", Some("thag_index + 1"));
+            loop_head.push_str(&format!(
+                r"            if line.trim().is_empty() {{
+                continue;
+            }}
+            #[allow(unused_variables)]
+            let rec: serde_json::Value = match serde_json::from_str(&line) {{
+                Ok(rec) => rec,
+                Err(err) => {{
+                    {on_malformed}
+                }}
+            }};
+"
+            ));
+            return Self {
+                file_setup: String::new(),
+                loop_head,
+                bindings: "(line, rec)",
+                pattern: if args.print_lines {
+                    "(mut line, rec)"
+                } else {
+                    "(line, rec)"
+                },
+                binding_type: "(String, serde_json::Value)",
+                record_setup: String::new(),
+            };
+        }
+        Self {
+            file_setup: String::new(),
+            loop_head,
+            bindings: "line",
+            pattern: if args.print_lines { "mut line" } else { "line" },
+            binding_type: "String",
+            record_setup: String::new(),
+        }
+    }
+}
+
+/// Identify the pieces of user input that `build_loop` combines into a program, for the
+/// source map.
+#[must_use]
+pub fn loop_segments(args: &Cli, filter: &str) -> Vec<SourceSegment> {
+    [
+        ("<begin>", args.begin.as_deref()),
+        ("<loop>", Some(filter)),
+        ("<end>", args.end.as_deref()),
+    ]
+    .into_iter()
+    .filter_map(|(name, text)| text.map(|text| SourceSegment::new(name, text)))
+    .collect()
+}
+
 /// Clean up temporary files.
 /// # Errors
 /// Will return `Err` if there is any error deleting the file.
@@ -1008,316 +1962,6 @@ pub fn strip_curly_braces(haystack: &str) -> Option<String> {
         .map(|captures| captures[1].to_string())
 }
 
-/// Cache any functions that we may find in a snippet expression in a Hashmap, so
-/// that if the last statement in the expression is a function call, we can look
-/// up its return type and determine whether to wrap it in a println! statement.
-fn extract_functions(expr: &syn::Expr) -> HashMap<String, ReturnType> {
-    #[derive(Default)]
-    struct FindFns {
-        function_map: HashMap<String, ReturnType>,
-    }
-
-    impl<'ast> Visit<'ast> for FindFns {
-        fn visit_item_fn(&mut self, i: &'ast syn::ItemFn) {
-            // if is_debug_logging_enabled() {
-            //     debug_log!("Node={:#?}", node);
-            //     debug_log!("Ident={}", node.sig.ident);
-            //     debug_log!("Output={:#?}", &node.sig.output);
-            // }
-            self.function_map
-                .insert(i.sig.ident.to_string(), i.sig.output.clone());
-        }
-    }
-
-    let mut finder = FindFns::default();
-    finder.visit_expr(expr);
-
-    finder.function_map
-}
-
-/// Determine if the return type of the expression is unit (the empty tuple `()`),
-/// otherwise we wrap it in a println! statement.
-#[must_use]
-#[inline]
-pub fn is_unit_return_type(expr: &Expr) -> bool {
-    profile_fn!(is_unit_return_type);
-
-    let start = Instant::now();
-
-    let function_map = extract_functions(expr);
-
-    // debug_log!("function_map={function_map:#?}");
-    let is_unit_type = is_last_stmt_unit_type(expr, &function_map);
-
-    debug_timings(&start, "Determined probable snippet return type");
-    is_unit_type
-}
-
-/// Recursively alternate with function `is_stmt_unit_type` until we drill down through
-/// all the blocks, loops and if-conditions to find the last executable statement and
-/// determine if it returns a unit type or a value worth printing.
-///
-/// This function finds the last statement in a given expression and determines if it
-/// returns a unit type.
-/// # Panics
-/// Will panic if an unexpected expression type is found in the elso branch of an if-statement.
-#[allow(clippy::too_many_lines)]
-#[must_use]
-#[inline]
-pub fn is_last_stmt_unit_type<S: BuildHasher>(
-    expr: &Expr,
-    function_map: &HashMap<String, ReturnType, S>,
-) -> bool {
-    profile_fn!(is_last_stmt_unit_type);
-
-    debug_log!("%%%%%%%% expr={expr:#?}");
-    match expr {
-        Expr::ForLoop(for_loop) => {
-            // debug_log!("%%%%%%%% Expr::ForLoop(for_loop))");
-            for_loop.body.stmts.last().map_or(false, |last_stmt| {
-                is_stmt_unit_type(last_stmt, function_map)
-            })
-        }
-        Expr::If(expr_if) => {
-            // Cycle through if-else statements and return false if any one is found returning
-            // a non-unit value;
-            if let Some(last_stmt_in_then_branch) = expr_if.then_branch.stmts.last() {
-                // debug_log!("%%%%%%%% Some(last_stmt) = expr_if.then_branch.stmts.last()");
-                if !is_stmt_unit_type(last_stmt_in_then_branch, function_map) {
-                    return false;
-                };
-                expr_if.else_branch.as_ref().map_or(true, |stmt| {
-                    let expr_else = &*stmt.1;
-                    // The else branch expression may only be an If or Block expression,
-                    // not any of the other types of expression.
-                    match expr_else {
-                        // If it's a block, we're at the end of the if-else chain and can just
-                        // decide according to the return type of the last statement in the block.
-                        Expr::Block(expr_block) => {
-                            let else_is_unit_type =
-                                expr_block.block.stmts.last().map_or(false, |last_stmt_in_block| is_stmt_unit_type(last_stmt_in_block, function_map));
-                            else_is_unit_type
-                        }
-                        // If it's another if-statement, simply recurse through this method.
-                        Expr::If(_) => is_last_stmt_unit_type(expr_else, function_map),
-                        expr => {
-                            eprintln!("Possible logic error: expected else branch expression to be If or Block, found {:?}", expr);
-                            process::exit(1);
-                        }
-                    }
-                })
-            } else {
-                // debug_log!(
-                //     "%%%%%%%% Not if let Some(last_stmt) = expr_if.then_branch.stmts.last()"
-                // );
-                false
-            }
-        }
-        Expr::Block(expr_block) => {
-            if expr_block.block.stmts.is_empty() {
-                return true;
-            }
-            expr_block.block.stmts.last().map_or(false, |last_stmt| {
-                is_stmt_unit_type(last_stmt, function_map)
-            })
-        }
-        Expr::Match(expr_match) => {
-            for arm in &expr_match.arms {
-                // debug_log!("arm.body={:#?}", arm.body);
-                let expr = &*arm.body;
-                if is_last_stmt_unit_type(expr, function_map) {
-                    continue;
-                }
-                return false;
-            }
-
-            // debug_log!("%%%%%%%% Match arm returns non-unit type");
-            true
-        }
-        Expr::Call(expr_call) => {
-            if let Expr::Path(path) = &*expr_call.func {
-                if let Some(value) = is_path_unit_type(path, function_map) {
-                    return value;
-                }
-            }
-
-            false
-        }
-        Expr::Closure(ref expr_closure) => match &expr_closure.output {
-            ReturnType::Default => is_last_stmt_unit_type(&expr_closure.body, function_map),
-            ReturnType::Type(_, ty) => {
-                if let Tuple(tuple) = &**ty {
-                    tuple.elems.is_empty()
-                } else {
-                    false
-                }
-            }
-        },
-        Expr::MethodCall(expr_method_call) => {
-            is_last_stmt_unit_type(&expr_method_call.receiver, function_map)
-        }
-        Expr::Binary(expr_binary) => matches!(
-            expr_binary.op,
-            AddAssign(_)
-                | SubAssign(_)
-                | MulAssign(_)
-                | DivAssign(_)
-                | RemAssign(_)
-                | BitXorAssign(_)
-                | BitAndAssign(_)
-                | BitOrAssign(_)
-                | ShlAssign(_)
-                | ShrAssign(_)
-        ),
-        Expr::While(_)
-        | Expr::Loop(_)
-        | Expr::Break(_)
-        | Expr::Continue(_)
-        | Expr::Infer(_)
-        | Expr::Let(_) => true,
-        Expr::Array(_)
-        | Expr::Assign(_)
-        | Expr::Async(_)
-        | Expr::Await(_)
-        | Expr::Cast(_)
-        | Expr::Const(_)
-        | Expr::Field(_)
-        | Expr::Group(_)
-        | Expr::Index(_)
-        | Expr::Lit(_)
-        | Expr::Paren(_)
-        | Expr::Range(_)
-        | Expr::Reference(_)
-        | Expr::Repeat(_)
-        | Expr::Struct(_)
-        | Expr::Try(_)
-        | Expr::TryBlock(_)
-        | Expr::Tuple(_)
-        | Expr::Unary(_)
-        | Expr::Unsafe(_)
-        | Expr::Verbatim(_)
-        | Expr::Yield(_) => false,
-        Expr::Macro(ref expr_macro) => {
-            if let Some(segment) = expr_macro.mac.path.segments.last() {
-                let ident = &segment.ident.to_string();
-                return ident.starts_with("print")
-                    || ident.starts_with("write")
-                    || ident.starts_with("debug");
-            }
-            false // default - because no intrinsic way of knowing?
-        }
-        Expr::Path(ref path) => {
-            if let Some(value) = is_path_unit_type(path, function_map) {
-                return value;
-            }
-            false
-        }
-        Expr::Return(ref expr_return) => {
-            debug_log!("%%%%%%%% expr_return={expr_return:#?}");
-            expr_return.expr.is_none()
-        }
-        _ => {
-            log!(
-                Verbosity::Quiet,
-                "{}",
-                nu_resolve_style(MessageLevel::Warning).paint(format!(
-                    "Expression not catered for: {expr:#?}, wrapping expression in println!()"
-                ))
-            );
-            false
-        }
-    }
-}
-
-/// Check if a path represents a function, and if so, whether it has a unit or non-unit
-/// return type.
-#[must_use]
-#[inline]
-pub fn is_path_unit_type<S: BuildHasher>(
-    path: &syn::PatPath,
-    function_map: &HashMap<String, ReturnType, S>,
-) -> Option<bool> {
-    profile_fn!(is_path_unit_type);
-    if let Some(ident) = path.path.get_ident() {
-        if let Some(return_type) = function_map.get(&ident.to_string()) {
-            return Some(match return_type {
-                ReturnType::Default => {
-                    // debug_log!("%%%%%%%% ReturnType::Default");
-                    true
-                }
-                ReturnType::Type(_, ty) => {
-                    if let Tuple(tuple) = &**ty {
-                        // debug_log!("%%%%%%%% Tuple ReturnType");
-                        tuple.elems.is_empty()
-                    } else {
-                        // debug_log!("%%%%%%%% Non-unit return type");
-                        false
-                    }
-                }
-            });
-        }
-    }
-    None
-}
-
-/// Recursively alternate with function `is_last_stmt_unit` until we drill down through
-/// all the blocks, loops and if-conditions to find the last executable statement and
-/// determine if it returns a unit type or a value worth printing.
-pub fn is_stmt_unit_type<S: BuildHasher>(
-    stmt: &Stmt,
-    function_map: &HashMap<String, ReturnType, S>,
-) -> bool {
-    profile_fn!(is_stmt_unit_type);
-
-    debug_log!("%%%%%%%% stmt={stmt:#?}");
-    match stmt {
-        Stmt::Expr(expr, None) => {
-            // if is_debug_logging_enabled() {
-            //     debug_log!("%%%%%%%% expr={expr:#?}");
-            //     debug_log!("%%%%%%%% Stmt::Expr(_, None)");
-            // }
-            is_last_stmt_unit_type(expr, function_map)
-        } // Expression without semicolon
-        Stmt::Expr(expr, Some(_)) => {
-            // debug_log!("%%%%%%%% Stmt::Expr(_, Some(_))");
-            match expr {
-                Expr::Return(expr_return) => {
-                    debug_log!("%%%%%%%% expr_return={expr_return:#?}");
-                    expr_return.expr.is_none()
-                }
-                Expr::Yield(expr_yield) => {
-                    debug_log!("%%%%%%%% expr_yield={expr_yield:#?}");
-                    expr_yield.expr.is_none()
-                }
-                _ => true,
-            }
-        } // Expression with semicolon usually returns unit, except sometimes return or yield.
-        Stmt::Macro(m) => {
-            // debug_log!("%%%%%%%% Stmt::Macro({m:#?}), m.semi_token.is_some()={is_some}");
-            m.semi_token.is_some()
-        }
-        Stmt::Local(_) => true,
-        Stmt::Item(item) => match item {
-            Item::ExternCrate(_)
-            | Item::Fn(_)
-            | Item::ForeignMod(_)
-            | Item::Impl(_)
-            | Item::Struct(_)
-            | Item::Trait(_)
-            | Item::TraitAlias(_)
-            | Item::Type(_)
-            | Item::Union(_)
-            | Item::Use(_)
-            | Item::Mod(_) => true,
-            Item::Macro(m) => {
-                // debug_log!("%%%%%%%% Item::Macro({m:#?}), m.semi_token.is_some()={is_some}");
-                m.semi_token.is_some()
-            }
-            _ => false, // default
-        },
-    }
-}
-
 /// # Errors
 /// Will return `Err` if there is any error parsing expressions
 pub fn is_main_fn_returning_unit(file: &File) -> Result<bool, ThagError> {
@@ -3,12 +3,14 @@ use lazy_static::lazy_static;
 use mockall::{automock, predicate::str};
 use serde::Deserialize;
 use serde_with::{serde_as, DisplayFromStr};
+use std::collections::BTreeMap;
 #[cfg(target_os = "windows")]
 use std::env;
 use std::fs::{self, OpenOptions};
 use std::io::Write;
 use std::path::PathBuf;
 
+use crate::code_utils::{AsyncRuntime, ResultFormat};
 use crate::colors::{ColorSupport, TermTheme};
 
 use crate::debug_log;
@@ -41,6 +43,7 @@ pub struct Config {
     pub logging: Logging,
     pub colors: Colors,
     pub misc: Misc,
+    pub templates: BTreeMap<String, Template>,
 }
 
 #[allow(dead_code)]
@@ -50,6 +53,7 @@ pub struct Config {
 pub struct Logging {
     #[serde_as(as = "DisplayFromStr")]
     pub default_verbosity: Verbosity,
+    pub log_file: Option<PathBuf>,
 }
 
 #[allow(dead_code)]
@@ -71,6 +75,21 @@ pub struct Colors {
 pub struct Misc {
     #[serde_as(as = "DisplayFromStr")]
     pub unquote: bool,
+    #[serde_as(as = "DisplayFromStr")]
+    pub format: ResultFormat,
+    #[serde_as(as = "DisplayFromStr")]
+    pub async_runtime: AsyncRuntime,
+}
+
+/// A named template for wrapping snippets and loop filters in a program, given either
+/// inline as `text` or as a `file` to read it from, plus any extra imports to add to the
+/// prelude. If neither `text` nor `file` is given, the default template is used.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Template {
+    pub text: Option<String>,
+    pub file: Option<PathBuf>,
+    pub prelude: Option<String>,
 }
 
 #[automock]
@@ -0,0 +1,230 @@
+use firestorm::profile_fn;
+use lazy_static::lazy_static;
+use nu_ansi_term::Style;
+use regex::Regex;
+use serde_json::Value;
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use crate::colors::{nu_resolve_style, MessageLevel};
+use crate::events::events_replace_messages;
+use crate::log;
+use crate::logging::{Verbosity, LOGGER};
+
+/// Render a rustc diagnostic, in the form of the `message` object of a Cargo
+/// `compiler-message` record, as condensed, styled text, together with the verbosity
+/// level at which it should be shown: errors always, warnings unless `-qq` is in force.
+/// Returns `None` for messages that are neither errors nor warnings, or that merely
+/// summarise the others.
+#[must_use]
+pub fn render_diagnostic(message: &Value) -> Option<(Verbosity, String)> {
+    profile_fn!(render_diagnostic);
+    lazy_static! {
+        static ref SUMMARY_RE: Regex = Regex::new(
+            r"^(aborting due to|\d+ (warning|error)s? emitted|.* generated \d+ warnings?)"
+        )
+        .unwrap();
+    }
+    let level = message["level"].as_str()?;
+    let (verbosity, message_level) = match level {
+        "error" | "error: internal compiler error" => (Verbosity::Quieter, MessageLevel::Error),
+        "warning" => (Verbosity::Quiet, MessageLevel::Warning),
+        _ => return None,
+    };
+    let text = message["message"].as_str()?;
+    if SUMMARY_RE.is_match(text) {
+        return None;
+    }
+    let level_style = nu_resolve_style(message_level);
+    let code = message["code"]["code"]
+        .as_str()
+        .map_or_else(String::new, |code| format!("[{code}]"));
+
+    let mut rendered = String::new();
+    let _ = write!(
+        rendered,
+        "{}{}",
+        level_style.paint(format!("{level}{code}")),
+        nu_resolve_style(MessageLevel::Normal).paint(format!(": {text}"))
+    );
+
+    let empty = Vec::new();
+    let spans = message["spans"].as_array().unwrap_or(&empty);
+    if let Some(primary) = spans.iter().find(|span| span["is_primary"] == true) {
+        render_location(&mut rendered, primary);
+        render_snippet(&mut rendered, spans, primary, level_style);
+    }
+
+    for child in message["children"].as_array().unwrap_or(&empty) {
+        render_child(&mut rendered, child);
+    }
+    Some((verbosity, rendered))
+}
+
+fn render_location(rendered: &mut String, span: &Value) {
+    let _ = write!(
+        rendered,
+        "\n  {} {}:{}:{}",
+        nu_resolve_style(MessageLevel::Heading).paint("-->"),
+        span["file_name"].as_str().unwrap_or_default(),
+        span["line_start"],
+        span["column_start"]
+    );
+}
+
+// Show the source lines of the spans in the same file as the primary span, each followed by
+// an underline and label for each span that starts on it.
+fn render_snippet(rendered: &mut String, spans: &[Value], primary: &Value, level_style: Style) {
+    let gutter_style = nu_resolve_style(MessageLevel::Heading);
+    let mut spans: Vec<&Value> = spans
+        .iter()
+        .filter(|span| span["file_name"] == primary["file_name"])
+        .collect();
+    spans.sort_by_key(|span| (span["line_start"].as_u64(), span["column_start"].as_u64()));
+    let width = spans
+        .iter()
+        .filter_map(|span| span["line_start"].as_u64())
+        .max()
+        .map_or(1, |line| line.to_string().len());
+
+    let mut current_line = None;
+    for span in spans {
+        let Some(text) = span["text"].get(0) else {
+            continue;
+        };
+        let line = span["line_start"].as_u64();
+        if current_line != line {
+            current_line = line;
+            let _ = write!(
+                rendered,
+                "\n{} {}",
+                gutter_style.paint(format!("{:>width$} |", line.unwrap_or_default())),
+                text["text"].as_str().unwrap_or_default()
+            );
+        }
+        let start = usize::try_from(text["highlight_start"].as_u64().unwrap_or(1)).unwrap_or(1);
+        let end = usize::try_from(text["highlight_end"].as_u64().unwrap_or(1)).unwrap_or(1);
+        let (mark, style) = if span["is_primary"] == true {
+            ("^", level_style)
+        } else {
+            ("-", gutter_style)
+        };
+        let underline = format!(
+            "{}{} {}",
+            " ".repeat(start.saturating_sub(1)),
+            mark.repeat(end.saturating_sub(start).max(1)),
+            span["label"].as_str().unwrap_or_default()
+        );
+        let _ = write!(
+            rendered,
+            "\n{} {}",
+            gutter_style.paint(format!("{:>width$} |", "")),
+            style.paint(underline.trim_end())
+        );
+    }
+}
+
+// Show a note or help message, with any suggested replacement.
+fn render_child(rendered: &mut String, child: &Value) {
+    let suggestion = child["spans"]
+        .as_array()
+        .and_then(|spans| {
+            spans
+                .iter()
+                .filter_map(|span| span["suggested_replacement"].as_str())
+                .map(str::trim_end)
+                .find(|replacement| !replacement.is_empty())
+        })
+        .map_or_else(String::new, |replacement| format!(": `{replacement}`"));
+    let _ = write!(
+        rendered,
+        "\n  {} {}: {}{suggestion}",
+        nu_resolve_style(MessageLevel::Heading).paint("="),
+        nu_resolve_style(MessageLevel::Emphasis).paint(child["level"].as_str().unwrap_or("note")),
+        child["message"].as_str().unwrap_or_default(),
+    );
+}
+
+/// Tracks Cargo's compilation of the script's dependencies from its `compiler-artifact`
+/// records, showing a progress line on the terminal as each one is compiled.
+#[derive(Debug)]
+pub struct DependencyProgress {
+    manifest_path: PathBuf,
+    compiled: usize,
+    visible: bool,
+    showing: bool,
+}
+
+impl DependencyProgress {
+    /// Start tracking the dependencies of the package with the given Cargo.toml. The progress
+    /// line is only shown if messages are going to a terminal at normal verbosity or above.
+    #[must_use]
+    pub fn new(manifest_path: &Path) -> Self {
+        let visible = io::stderr().is_terminal()
+            && !events_replace_messages()
+            && LOGGER.lock().is_ok_and(|logger| {
+                logger.writes_to_stderr() && logger.verbosity as u8 >= Verbosity::Normal as u8
+            });
+        Self {
+            manifest_path: manifest_path
+                .canonicalize()
+                .unwrap_or_else(|_| manifest_path.to_path_buf()),
+            compiled: 0,
+            visible,
+            showing: false,
+        }
+    }
+
+    /// Record a Cargo `compiler-artifact` record, updating the progress line if the
+    /// artifact is a freshly compiled dependency.
+    pub fn update(&mut self, record: &Value) {
+        if record["fresh"] == true {
+            return;
+        }
+        let Some(manifest_path) = record["manifest_path"].as_str() else {
+            return;
+        };
+        let manifest_path = Path::new(manifest_path);
+        if manifest_path == self.manifest_path
+            || manifest_path
+                .canonicalize()
+                .is_ok_and(|path| path == self.manifest_path)
+        {
+            return;
+        }
+        self.compiled += 1;
+        if self.visible {
+            eprint!(
+                "\r\x1b[2K{} {} ({})",
+                nu_resolve_style(MessageLevel::Subheading).paint("Compiling dependencies:"),
+                self.compiled,
+                nu_resolve_style(MessageLevel::Ghost)
+                    .paint(record["target"]["name"].as_str().unwrap_or_default())
+            );
+            let _ = io::stderr().flush();
+            self.showing = true;
+        }
+    }
+
+    /// Clear the progress line, if shown, to make way for other output.
+    pub fn clear(&mut self) {
+        if self.showing {
+            eprint!("\r\x1b[2K");
+            let _ = io::stderr().flush();
+            self.showing = false;
+        }
+    }
+
+    /// Clear the progress line and report the number of dependencies compiled, if any.
+    pub fn finish(&mut self) {
+        self.clear();
+        if self.compiled > 0 {
+            log!(
+                Verbosity::Verbose,
+                "Compiled {} dependencies",
+                self.compiled
+            );
+        }
+    }
+}
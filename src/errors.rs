@@ -11,19 +11,35 @@ use syn::Error as SynError;
 use toml::de::Error as TomlDeError;
 use toml::ser::Error as TomlSerError;
 
+/// Exit code for general errors not covered by a more specific code.
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit code for invalid or inconsistent command-line arguments.
+pub const EXIT_USAGE: i32 = 2;
+/// Exit code for failure to generate the Rust source or Cargo.toml for the script.
+pub const EXIT_GENERATE: i32 = 3;
+/// Exit code for failure of the Cargo build or check of the script.
+pub const EXIT_BUILD: i32 = 4;
+/// Added to the signal number to form the exit code when the script is killed by a signal,
+/// as per shell convention.
+pub const EXIT_SIGNAL_BASE: i32 = 128;
+
 #[derive(Debug)]
 pub enum ThagError {
+    Args(Box<ThagError>),              // For invalid command-line arguments
     BitFlagsParse(BitFlagsParseError), // For bitflags parse error
+    Build(Cow<'static, str>),          // For failure of the Cargo build
     Cancelled,                         // For user electing to cancel
     ClapError(ClapError),              // For clap errors
     Command(&'static str),             // For errors during Cargo build or program execution
     Dyn(Box<dyn Error>), // For boxed dynamic errors from 3rd parties (firestorm in first instance)
     FromStr(Cow<'static, str>), // For simple errors from a string
+    Generate(Box<ThagError>), // For errors generating the source or Cargo.toml
     Io(std::io::Error),  // For I/O errors
     LockMutexGuard(&'static str), // For lock errors with MutexGuard
     NoneOption(&'static str), // For unwrapping Options
     OsString(std::ffi::OsString), // For unconvertible OsStrings
     Reedline(ReedlineError), // For reedline errors
+    ScriptExit(i32),     // For the script terminating with a non-zero exit code
     SerdeMerge(SerdeMergeError), // For serde_merge errors
     StrumParse(StrumParseError), // For strum parse enum
     Syn(SynError),       // For syn errors
@@ -32,7 +48,20 @@ pub enum ThagError {
     Toml(CargoTomlError), // For cargo_toml errors
 }
 
-impl ThagError {}
+impl ThagError {
+    /// The process exit code that `thag` should terminate with for this error.
+    /// A non-zero exit code from the script itself is passed through unchanged.
+    #[must_use]
+    pub const fn exit_code(&self) -> i32 {
+        match self {
+            Self::Args(_) | Self::ClapError(_) => EXIT_USAGE,
+            Self::Generate(_) => EXIT_GENERATE,
+            Self::Build(_) => EXIT_BUILD,
+            Self::ScriptExit(code) => *code,
+            _ => EXIT_FAILURE,
+        }
+    }
+}
 
 impl From<io::Error> for ThagError {
     fn from(err: io::Error) -> Self {
@@ -121,7 +150,14 @@ impl From<Box<dyn Error>> for ThagError {
 impl std::fmt::Display for ThagError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::Args(e) | Self::Generate(e) => write!(f, "{e}"),
             Self::BitFlagsParse(e) => write!(f, "{e:?}"),
+            Self::Build(s) => {
+                for line in s.lines() {
+                    writeln!(f, "{line}")?;
+                }
+                Ok(())
+            }
             Self::Cancelled => write!(f, "Cancelled"),
             Self::ClapError(e) => write!(f, "{e:?}"),
             Self::Command(s) | Self::NoneOption(s) => {
@@ -144,6 +180,7 @@ impl std::fmt::Display for ThagError {
                 Ok(())
             }
             Self::Reedline(e) => write!(f, "{e:?}"),
+            Self::ScriptExit(code) => write!(f, "Script exited with status {code}"),
             Self::SerdeMerge(e) => write!(f, "{e:?}"),
             Self::StrumParse(e) => write!(f, "{e:?}"),
             Self::Syn(e) => write!(f, "{e:?}"),
@@ -160,7 +197,9 @@ impl Error for ThagError {
             // The cause is the underlying implementation error type. Is implicitly
             // cast to the trait object `&error::Error`. This works because the
             // underlying type already implements the `Error` trait.
+            Self::Args(e) | Self::Generate(e) => Some(&**e),
             Self::BitFlagsParse(e) => Some(e),
+            Self::Build(_e) => None,
             Self::Cancelled => Some(self),
             Self::ClapError(ref e) => Some(e),
             Self::Command(_e) => Some(self),
@@ -171,6 +210,7 @@ impl Error for ThagError {
             Self::NoneOption(_e) => Some(self),
             Self::OsString(ref _o) => Some(self),
             Self::Reedline(e) => Some(e),
+            Self::ScriptExit(_code) => None,
             Self::SerdeMerge(ref e) => Some(e),
             Self::StrumParse(ref e) => Some(e),
             Self::Syn(e) => Some(e),
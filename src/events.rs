@@ -0,0 +1,196 @@
+#![allow(clippy::uninlined_format_args)]
+use firestorm::profile_fn;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+use std::time::Instant;
+
+use crate::cmd_args::Cli;
+use crate::logging::Verbosity;
+use crate::ThagError;
+
+/// The format of the progress information that `thag` reports as it goes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// Human-readable, styled messages on stderr
+    #[default]
+    Human,
+    /// Newline-delimited JSON events for editor plugins, CI wrappers and the like
+    Json,
+}
+
+/// The main processing phases of a `thag` run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Generate,
+    Build,
+    Run,
+}
+
+/// An event in the JSON event stream. Each event is serialised as a single line of JSON
+/// with an `event` field naming the variant.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A processing phase has started.
+    PhaseStart { phase: Phase },
+    /// A processing phase has finished, successfully or otherwise.
+    PhaseFinish {
+        phase: Phase,
+        success: bool,
+        duration_ms: u128,
+    },
+    /// A timing reported by `display_timings`.
+    Timing { process: &'a str, duration_ms: u128 },
+    /// Dependencies inferred from the script's `use` and `extern crate` statements.
+    Dependencies { inferred: &'a [String] },
+    /// A compiler diagnostic, in the form of the `message` object of a Cargo
+    /// `compiler-message` record.
+    Diagnostic { message: &'a serde_json::Value },
+    /// A message that would otherwise be logged as text.
+    Message { level: Verbosity, text: &'a str },
+    /// The exit code `thag` is about to terminate with.
+    Exit { code: i32 },
+}
+
+struct EventSink {
+    writer: Box<dyn Write + Send>,
+    // Events share stderr with human-readable messages, so the latter must be suppressed.
+    on_stderr: bool,
+}
+
+static EVENTS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref EVENT_SINK: Mutex<Option<EventSink>> = Mutex::new(None);
+}
+
+/// Set up the JSON event stream if requested on the command line.
+/// # Errors
+/// Will return `Err` if the event file cannot be opened or the sink mutex cannot be locked.
+pub fn configure_events(args: &Cli) -> Result<(), ThagError> {
+    profile_fn!(configure_events);
+    if args.message_format == MessageFormat::Json {
+        enable_events(args.message_file.as_ref())?;
+    }
+    Ok(())
+}
+
+/// Start emitting JSON events, to the given file if any, otherwise to stderr.
+/// # Errors
+/// Will return `Err` if the event file cannot be opened or the sink mutex cannot be locked.
+pub fn enable_events(message_file: Option<&PathBuf>) -> Result<(), ThagError> {
+    let sink = if let Some(path) = message_file {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        EventSink {
+            writer: Box::new(file),
+            on_stderr: false,
+        }
+    } else {
+        EventSink {
+            writer: Box::new(io::stderr()),
+            on_stderr: true,
+        }
+    };
+    *EVENT_SINK.lock()? = Some(sink);
+    EVENTS_ENABLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Whether JSON events are being emitted.
+pub fn events_enabled() -> bool {
+    EVENTS_ENABLED.load(Ordering::SeqCst)
+}
+
+/// Whether JSON events are being written to stderr, in which case they take the place
+/// of the human-readable messages.
+pub fn events_replace_messages() -> bool {
+    events_enabled()
+        && EVENT_SINK
+            .lock()
+            .is_ok_and(|sink| sink.as_ref().is_some_and(|s| s.on_stderr))
+}
+
+/// Write an event to the event stream, if enabled.
+pub fn emit(event: &Event) {
+    if !events_enabled() {
+        return;
+    }
+    let Ok(mut guard) = EVENT_SINK.lock() else {
+        return;
+    };
+    if let Some(ref mut sink) = *guard {
+        if let Ok(json) = serde_json::to_string(event) {
+            // Nowhere left to report a failure to write an event
+            let _ = writeln!(sink.writer, "{json}");
+            let _ = sink.writer.flush();
+        }
+    }
+}
+
+/// Report the start of a processing phase.
+pub fn phase_start(phase: Phase) {
+    emit(&Event::PhaseStart { phase });
+}
+
+/// Report the end of a processing phase, given the time it started.
+pub fn phase_finish(phase: Phase, start: &Instant, success: bool) {
+    emit(&Event::PhaseFinish {
+        phase,
+        success,
+        duration_ms: start.elapsed().as_millis(),
+    });
+}
+
+/// Reports the start of a processing phase when created, and its finish when `finish`
+/// is called. If the guard is dropped unfinished, as when the phase returns early with an
+/// error, the phase is reported as having failed.
+#[must_use]
+pub struct PhaseGuard {
+    phase: Phase,
+    start: Instant,
+    finished: bool,
+}
+
+impl PhaseGuard {
+    /// Report the start of the given phase.
+    pub fn start(phase: Phase) -> Self {
+        phase_start(phase);
+        Self {
+            phase,
+            start: Instant::now(),
+            finished: false,
+        }
+    }
+
+    /// Report the finish of the phase.
+    pub fn finish(mut self, success: bool) {
+        phase_finish(self.phase, &self.start, success);
+        self.finished = true;
+    }
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            phase_finish(self.phase, &self.start, false);
+        }
+    }
+}
+
+/// Remove terminal styling from a message for use in an event.
+#[must_use]
+pub fn strip_ansi(text: &str) -> String {
+    lazy_static! {
+        static ref ANSI_RE: Regex = Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").unwrap();
+    }
+    ANSI_RE.replace_all(text, "").into_owned()
+}
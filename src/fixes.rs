@@ -0,0 +1,254 @@
+use firestorm::profile_fn;
+use serde_json::Value;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::colors::{nu_resolve_style, MessageLevel};
+use crate::source_map::{matching_pairs, SourceSegment};
+
+/// The number of times `--fix` will apply suggestions and rebuild before giving up.
+pub const MAX_FIX_ATTEMPTS: usize = 5;
+
+// Lines of unchanged context to show around each change in a diff.
+const DIFF_CONTEXT: usize = 2;
+
+/// A replacement for a span of source code, as suggested by rustc. Lines and columns
+/// are 1-based, and the end is exclusive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Suggestion {
+    pub file_name: String,
+    pub line_start: usize,
+    pub column_start: usize,
+    pub line_end: usize,
+    pub column_end: usize,
+    pub replacement: String,
+}
+
+impl Suggestion {
+    /// Whether the suggestion adds a `use` declaration.
+    #[must_use]
+    pub fn is_import(&self) -> bool {
+        self.replacement.trim_start().starts_with("use ")
+    }
+
+    /// Whether the suggestion applies to the named original source.
+    #[must_use]
+    pub fn applies_to(&self, name: &str) -> bool {
+        self.file_name == name
+            || Path::new(&self.file_name)
+                .canonicalize()
+                .ok()
+                .zip(Path::new(name).canonicalize().ok())
+                .is_some_and(|(file, name)| file == name)
+    }
+}
+
+/// Collect the suggestions from a rustc error diagnostic, in the form of the `message`
+/// object of a Cargo `compiler-message` record, that are safe to apply without review,
+/// namely those that rustc marks as machine-applicable.
+#[must_use]
+pub fn collect_suggestions(message: &Value) -> Vec<Suggestion> {
+    profile_fn!(collect_suggestions);
+    if message["level"] != "error" {
+        return Vec::new();
+    }
+    let mut suggestions = Vec::new();
+    for child in message["children"].as_array().into_iter().flatten() {
+        for span in child["spans"].as_array().into_iter().flatten() {
+            if span["suggestion_applicability"] != "MachineApplicable" {
+                continue;
+            }
+            let Some(suggestion) = to_suggestion(span) else {
+                continue;
+            };
+            if !suggestions.contains(&suggestion) {
+                suggestions.push(suggestion);
+            }
+        }
+    }
+    suggestions
+}
+
+fn to_suggestion(span: &Value) -> Option<Suggestion> {
+    let number = |key: &str| span[key].as_u64().and_then(|n| usize::try_from(n).ok());
+    Some(Suggestion {
+        file_name: span["file_name"].as_str()?.to_string(),
+        line_start: number("line_start")?,
+        column_start: number("column_start")?,
+        line_end: number("line_end")?,
+        column_end: number("column_end")?,
+        replacement: span["suggested_replacement"].as_str()?.to_string(),
+    })
+}
+
+/// Apply suggestions to the text of a segment of original source, returning the fixed text
+/// and the number of suggestions that changed it. Suggestions that fall outside the segment,
+/// or that overlap one already applied, are skipped.
+#[must_use]
+pub fn apply_suggestions(segment: &SourceSegment, suggestions: &[&Suggestion]) -> (String, usize) {
+    profile_fn!(apply_suggestions);
+    let text = &segment.text;
+    let mut edits: Vec<(usize, usize, &str)> = suggestions
+        .iter()
+        .filter_map(|suggestion| {
+            let start = byte_offset(segment, suggestion.line_start, suggestion.column_start)?;
+            let end = byte_offset(segment, suggestion.line_end, suggestion.column_end)?;
+            (start <= end).then_some((start, end, suggestion.replacement.as_str()))
+        })
+        .collect();
+    edits.sort_by_key(|&(start, end, _)| (start, end));
+
+    let mut fixed = String::with_capacity(text.len());
+    let mut position = 0;
+    let mut applied = 0;
+    for (start, end, replacement) in edits {
+        if start < position {
+            continue;
+        }
+        fixed.push_str(&text[position..start]);
+        fixed.push_str(replacement);
+        if text[start..end] != *replacement {
+            applied += 1;
+        }
+        position = end;
+    }
+    fixed.push_str(&text[position..]);
+    (fixed, applied)
+}
+
+// Convert a 1-based line and character column in the named source to a byte offset in
+// the text of the segment.
+fn byte_offset(segment: &SourceSegment, line: usize, column: usize) -> Option<usize> {
+    let index = line.checked_sub(segment.first_line)?;
+    let mut line_start = 0;
+    for (i, line_text) in segment.text.split_inclusive('\n').enumerate() {
+        if i == index {
+            let content = line_text.trim_end_matches(['\n', '\r']);
+            let offset = content
+                .char_indices()
+                .map(|(offset, _)| offset)
+                .chain(std::iter::once(content.len()))
+                .nth(column.checked_sub(1)?)?;
+            return Some(line_start + offset);
+        }
+        line_start += line_text.len();
+    }
+    // Allow for an insertion at the very end of the text
+    (index == segment.text.split_inclusive('\n').count() && column == 1).then_some(line_start)
+}
+
+/// Find where to insert an import into a snippet, as a suggestion position in the named
+/// source: the start of the first line of code after any leading comments, `toml` block
+/// and inner attributes.
+#[must_use]
+pub fn import_position(segment: &SourceSegment) -> (usize, usize) {
+    let mut in_comment = false;
+    let index = segment
+        .text
+        .lines()
+        .position(|line| {
+            let line = line.trim();
+            if in_comment {
+                in_comment = !line.contains("*/");
+                return false;
+            }
+            if line.starts_with("/*") {
+                in_comment = !line.contains("*/");
+                return false;
+            }
+            !(line.is_empty() || line.starts_with("//") || line.starts_with("#!["))
+        })
+        .unwrap_or(0);
+    (segment.first_line + index, 1)
+}
+
+/// Show the differences between the original and fixed text of a segment of source in
+/// unified diff format, with line numbers as in the named source.
+#[must_use]
+pub fn unified_diff(original: &SourceSegment, fixed: &str) -> String {
+    profile_fn!(unified_diff);
+    let old: Vec<&str> = original.text.lines().collect();
+    let new: Vec<&str> = fixed.lines().collect();
+
+    // Lines of each side, in order: (old index, new index), with None for the side that
+    // lacks the line.
+    let mut lines: Vec<(Option<usize>, Option<usize>)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (old_index, new_index) in matching_pairs(&old, &new)
+        .into_iter()
+        .chain(std::iter::once((old.len(), new.len())))
+    {
+        lines.extend((i..old_index).map(|i| (Some(i), None)));
+        lines.extend((j..new_index).map(|j| (None, Some(j))));
+        if old_index < old.len() {
+            lines.push((Some(old_index), Some(new_index)));
+        }
+        (i, j) = (old_index + 1, new_index + 1);
+    }
+
+    let changed: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, (old_index, new_index))| old_index.is_none() || new_index.is_none())
+        .map(|(index, _)| index)
+        .collect();
+    let heading_style = nu_resolve_style(MessageLevel::Heading);
+    let mut diff = format!(
+        "{}\n{}",
+        heading_style.paint(format!("--- {}", original.name)),
+        heading_style.paint(format!("+++ {}", original.name))
+    );
+
+    // Group the changes into hunks with their surrounding context
+    let mut hunk_start = 0;
+    while hunk_start < changed.len() {
+        let mut hunk_end = hunk_start;
+        while hunk_end + 1 < changed.len()
+            && changed[hunk_end + 1] - changed[hunk_end] <= 2 * DIFF_CONTEXT + 1
+        {
+            hunk_end += 1;
+        }
+        let first = changed[hunk_start].saturating_sub(DIFF_CONTEXT);
+        let last = (changed[hunk_end] + DIFF_CONTEXT).min(lines.len() - 1);
+        let hunk = &lines[first..=last];
+
+        let old_count = hunk
+            .iter()
+            .filter(|(old_index, _)| old_index.is_some())
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|(_, new_index)| new_index.is_some())
+            .count();
+        let old_start = lines[..first].iter().filter(|(o, _)| o.is_some()).count();
+        let new_start = lines[..first].iter().filter(|(_, n)| n.is_some()).count();
+        let _ = write!(
+            diff,
+            "\n{}",
+            heading_style.paint(format!(
+                "@@ -{},{old_count} +{},{new_count} @@",
+                old_start + original.first_line,
+                new_start + original.first_line
+            ))
+        );
+        for &(old_index, new_index) in hunk {
+            let _ = match (old_index, new_index) {
+                (Some(old_index), Some(_)) => write!(diff, "\n {}", old[old_index]),
+                (Some(old_index), None) => write!(
+                    diff,
+                    "\n{}",
+                    nu_resolve_style(MessageLevel::Error).paint(format!("-{}", old[old_index]))
+                ),
+                (None, Some(new_index)) => write!(
+                    diff,
+                    "\n{}",
+                    nu_resolve_style(MessageLevel::Subheading)
+                        .paint(format!("+{}", new[new_index]))
+                ),
+                (None, None) => Ok(()),
+            };
+        }
+        hunk_start = hunk_end + 1;
+    }
+    diff
+}
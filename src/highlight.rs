@@ -0,0 +1,408 @@
+use firestorm::profile_fn;
+use nu_ansi_term::{Color, Style};
+use reedline::{Highlighter, StyledText};
+use std::ops::Range;
+
+use crate::colors::{ColorSupport, NuColor, TermTheme, XtermColor, COLOR_SUPPORT, TERM_THEME};
+
+/// The categories of Rust token distinguished for syntax highlighting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword,
+    /// A primitive type or a name starting with an upper-case letter.
+    Type,
+    /// A name followed by an opening parenthesis, or declared by `fn`.
+    Function,
+    /// A macro invocation, including its `!`.
+    Macro,
+    Lifetime,
+    /// An outer or inner attribute, including its brackets.
+    Attribute,
+    /// A string, byte string, raw string or character literal.
+    String,
+    Number,
+    Comment,
+    Identifier,
+    Punctuation,
+    Whitespace,
+}
+
+/// A token of Rust source, identified by its byte range in the source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub range: Range<usize>,
+}
+
+/// The Rust keywords in use, including `true` and `false`.
+pub const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+];
+
+/// The names of Rust's primitive types.
+pub const PRIMITIVE_TYPES: &[&str] = &[
+    "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "str", "u8", "u16",
+    "u32", "u64", "u128", "usize",
+];
+
+/// Split Rust source into tokens for highlighting, covering the whole of the source.
+///
+/// Unlike a `proc_macro2` token stream this tolerates the incomplete code of input that is
+/// still being typed, such as unbalanced delimiters and unterminated literals or comments,
+/// which simply run to the end of the source, and it keeps comments.
+#[must_use]
+pub fn tokenize(source: &str) -> Vec<Token> {
+    profile_fn!(tokenize);
+    let chars: Vec<char> = source.chars().collect();
+    let offsets: Vec<usize> = source
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(std::iter::once(source.len()))
+        .collect();
+    let at = |i: usize| chars.get(i).copied().unwrap_or('\0');
+
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let (kind, end) = match (at(i), at(i + 1)) {
+            (c, _) if c.is_whitespace() => (
+                TokenKind::Whitespace,
+                scan_while(&chars, i, char::is_whitespace),
+            ),
+            ('/', '/') => (TokenKind::Comment, scan_while(&chars, i, |c| c != '\n')),
+            ('/', '*') => (TokenKind::Comment, scan_block_comment(&chars, i)),
+            ('#', '[') | ('#', '!') if at(i + 1) == '[' || at(i + 2) == '[' => {
+                (TokenKind::Attribute, scan_attribute(&chars, i))
+            }
+            ('"', _) => (TokenKind::String, scan_quoted(&chars, i + 1, '"')),
+            ('\'', '\\') => (TokenKind::String, scan_quoted(&chars, i + 1, '\'')),
+            ('\'', _) if at(i + 2) == '\'' => (TokenKind::String, i + 3),
+            ('\'', c) if is_ident_start(c) => (
+                TokenKind::Lifetime,
+                scan_while(&chars, i + 1, is_ident_continue),
+            ),
+            (c, _) if c.is_ascii_digit() => (TokenKind::Number, scan_number(&chars, i)),
+            (c, _) if is_ident_start(c) => scan_string_literal(&chars, i)
+                .map_or_else(|| scan_word(&chars, i), |end| (TokenKind::String, end)),
+            _ => (TokenKind::Punctuation, i + 1),
+        };
+        let end = end.min(chars.len());
+        // The name in a function declaration, which may have generics
+        let kind = if kind == TokenKind::Identifier
+            && tokens
+                .iter()
+                .rev()
+                .find(|token: &&Token| token.kind != TokenKind::Whitespace)
+                .is_some_and(|token| &source[token.range.clone()] == "fn")
+        {
+            TokenKind::Function
+        } else {
+            kind
+        };
+        tokens.push(Token {
+            kind,
+            range: offsets[i]..offsets[end],
+        });
+        i = end;
+    }
+    tokens
+}
+
+/// Tokenize the lines of a text, returning for each line the byte ranges within it of the
+/// tokens other than whitespace. Tokens that span lines, such as block comments, are split
+/// at the line ends.
+#[must_use]
+pub fn tokenize_lines(lines: &[String]) -> Vec<Vec<(Range<usize>, TokenKind)>> {
+    profile_fn!(tokenize_lines);
+    let source = lines.join("\n");
+    let line_starts: Vec<usize> = lines
+        .iter()
+        .scan(0, |start, line| {
+            let line_start = *start;
+            *start += line.len() + 1;
+            Some(line_start)
+        })
+        .collect();
+    let mut line_tokens = vec![vec![]; lines.len()];
+    for token in tokenize(&source) {
+        if token.kind == TokenKind::Whitespace {
+            continue;
+        }
+        let mut row = line_starts.partition_point(|&start| start <= token.range.start) - 1;
+        let mut start = token.range.start;
+        while start < token.range.end {
+            let line_start = line_starts[row];
+            let end = token.range.end.min(line_start + lines[row].len());
+            if end > start {
+                line_tokens[row].push((start - line_start..end - line_start, token.kind));
+            }
+            // Skip the newline
+            start = end + 1;
+            row += 1;
+        }
+    }
+    line_tokens
+}
+
+/// Determine the style in which to highlight a kind of token, based on the current
+/// terminal's level of colour support and light or dark theme.
+#[must_use]
+pub fn token_style(kind: TokenKind) -> Style {
+    let is_light = matches!(*TERM_THEME, TermTheme::Light);
+    match COLOR_SUPPORT.as_ref() {
+        Some(ColorSupport::Xterm256) => xterm256_style(kind, is_light),
+        Some(ColorSupport::Ansi16) => ansi16_style(kind, is_light),
+        _ => Style::default(),
+    }
+}
+
+#[allow(clippy::match_same_arms)]
+fn xterm256_style(kind: TokenKind, is_light: bool) -> Style {
+    let xterm = |color: XtermColor| color.get_color().normal();
+    match (kind, is_light) {
+        (TokenKind::Keyword, true) => xterm(XtermColor::DarkViolet).bold(),
+        (TokenKind::Keyword, false) => xterm(XtermColor::Orchid).bold(),
+        (TokenKind::Type, true) => xterm(XtermColor::DeepCerulean),
+        (TokenKind::Type, false) => xterm(XtermColor::MalibuBlue),
+        (TokenKind::Function, true) => xterm(XtermColor::EndeavourBlue),
+        (TokenKind::Function, false) => xterm(XtermColor::DarkAnakiwaBlue),
+        (TokenKind::Macro, true) => xterm(XtermColor::Purple).bold(),
+        (TokenKind::Macro, false) => xterm(XtermColor::Heliotrope).bold(),
+        (TokenKind::Lifetime, true) => xterm(XtermColor::RoseofSharonOrange).italic(),
+        (TokenKind::Lifetime, false) => xterm(XtermColor::DarkTachaOrange).italic(),
+        (TokenKind::Attribute, true) => xterm(XtermColor::ScorpionOlive),
+        (TokenKind::Attribute, false) => xterm(XtermColor::Gray),
+        (TokenKind::String, true) => xterm(XtermColor::JapaneseLaurel),
+        (TokenKind::String, false) => xterm(XtermColor::DarkPastelGreen),
+        (TokenKind::Number, true) => xterm(XtermColor::BrightRed),
+        (TokenKind::Number, false) => xterm(XtermColor::Salmon),
+        (TokenKind::Comment, _) => xterm(XtermColor::Boulder).italic(),
+        (TokenKind::Identifier | TokenKind::Punctuation | TokenKind::Whitespace, _) => {
+            Style::default()
+        }
+    }
+}
+
+#[allow(clippy::match_same_arms)]
+fn ansi16_style(kind: TokenKind, is_light: bool) -> Style {
+    match kind {
+        TokenKind::Keyword => Color::Magenta.bold(),
+        TokenKind::Type => Color::Cyan.normal(),
+        TokenKind::Function => Color::Blue.normal(),
+        TokenKind::Macro => Color::Magenta.normal(),
+        TokenKind::Lifetime if is_light => Color::Red.italic(),
+        TokenKind::Lifetime => Color::Yellow.italic(),
+        TokenKind::Attribute => Color::DarkGray.normal(),
+        TokenKind::String => Color::Green.normal(),
+        TokenKind::Number if is_light => Color::Red.normal(),
+        TokenKind::Number => Color::Yellow.normal(),
+        TokenKind::Comment => Color::DarkGray.italic(),
+        TokenKind::Identifier | TokenKind::Punctuation | TokenKind::Whitespace => Style::default(),
+    }
+}
+
+/// Convert a highlighting style to the equivalent `ratatui` style for the TUI editor.
+#[must_use]
+pub fn ratatui_style(style: Style) -> ratatui::style::Style {
+    use ratatui::style::{Color as TuiColor, Modifier};
+
+    let mut tui_style = ratatui::style::Style::default();
+    if let Some(color) = style.foreground {
+        tui_style = tui_style.fg(match color {
+            Color::Black => TuiColor::Black,
+            Color::DarkGray => TuiColor::DarkGray,
+            Color::Red => TuiColor::Red,
+            Color::LightRed => TuiColor::LightRed,
+            Color::Green => TuiColor::Green,
+            Color::LightGreen => TuiColor::LightGreen,
+            Color::Yellow => TuiColor::Yellow,
+            Color::LightYellow => TuiColor::LightYellow,
+            Color::Blue => TuiColor::Blue,
+            Color::LightBlue => TuiColor::LightBlue,
+            Color::Purple | Color::Magenta => TuiColor::Magenta,
+            Color::LightPurple | Color::LightMagenta => TuiColor::LightMagenta,
+            Color::Cyan => TuiColor::Cyan,
+            Color::LightCyan => TuiColor::LightCyan,
+            // ANSI white and bright white, which ratatui calls gray and white
+            Color::White => TuiColor::Gray,
+            Color::LightGray => TuiColor::White,
+            Color::Fixed(code) => TuiColor::Indexed(code),
+            Color::Rgb(r, g, b) => TuiColor::Rgb(r, g, b),
+            Color::Default => TuiColor::Reset,
+        });
+    }
+    for (is_set, modifier) in [
+        (style.is_bold, Modifier::BOLD),
+        (style.is_dimmed, Modifier::DIM),
+        (style.is_italic, Modifier::ITALIC),
+        (style.is_underline, Modifier::UNDERLINED),
+    ] {
+        if is_set {
+            tui_style = tui_style.add_modifier(modifier);
+        }
+    }
+    tui_style
+}
+
+/// A `reedline` highlighter for Rust input to the REPL.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RustHighlighter;
+
+impl Highlighter for RustHighlighter {
+    fn highlight(&self, line: &str, _cursor: usize) -> StyledText {
+        let mut styled_text = StyledText::new();
+        for token in tokenize(line) {
+            styled_text.push((token_style(token.kind), line[token.range].to_string()));
+        }
+        styled_text
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c == '_' || c.is_alphabetic()
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c == '_' || c.is_alphanumeric()
+}
+
+fn scan_while(chars: &[char], start: usize, predicate: impl Fn(char) -> bool) -> usize {
+    chars[start..]
+        .iter()
+        .position(|&c| !predicate(c))
+        .map_or(chars.len(), |len| start + len)
+}
+
+// Scan a possibly nested block comment starting at `/*`.
+fn scan_block_comment(chars: &[char], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            ('/', Some('*')) => {
+                depth += 1;
+                i += 2;
+            }
+            ('*', Some('/')) => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    i
+}
+
+// Scan an attribute starting at `#`, up to its matching closing bracket.
+fn scan_attribute(chars: &[char], start: usize) -> usize {
+    let mut depth = 0;
+    for (i, &c) in chars.iter().enumerate().skip(start) {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => (),
+        }
+    }
+    chars.len()
+}
+
+// Scan the rest of a literal from just after its opening quote, allowing for escapes.
+fn scan_quoted(chars: &[char], start: usize, quote: char) -> usize {
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            c if c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    i
+}
+
+// Scan a prefixed string or character literal, such as `b"..."`, `r#"..."#` or `b'.'`,
+// if one starts here.
+fn scan_string_literal(chars: &[char], start: usize) -> Option<usize> {
+    let at = |i: usize| chars.get(i).copied().unwrap_or('\0');
+    let mut i = start;
+    if matches!(at(i), 'b' | 'c') {
+        i += 1;
+    }
+    if at(i) == 'r' {
+        let hashes = scan_while(chars, i + 1, |c| c == '#') - (i + 1);
+        i += 1 + hashes;
+        if at(i) != '"' {
+            return None;
+        }
+        let mut j = i + 1;
+        while j < chars.len() {
+            if chars[j] == '"' && (1..=hashes).all(|k| at(j + k) == '#') {
+                return Some(j + 1 + hashes);
+            }
+            j += 1;
+        }
+        return Some(j);
+    }
+    match (i > start, at(i)) {
+        (true, '"') => Some(scan_quoted(chars, i + 1, '"')),
+        (true, '\'') if at(start) == 'b' => Some(scan_quoted(chars, i + 1, '\'')),
+        _ => None,
+    }
+}
+
+// Scan and classify a keyword, name or macro invocation.
+fn scan_word(chars: &[char], start: usize) -> (TokenKind, usize) {
+    let at = |i: usize| chars.get(i).copied().unwrap_or('\0');
+    let mut end = scan_while(chars, start, is_ident_continue);
+    // A raw identifier
+    if end == start + 1 && chars[start] == 'r' && at(end) == '#' && is_ident_start(at(end + 1)) {
+        end = scan_while(chars, end + 1, is_ident_continue);
+        return (TokenKind::Identifier, end);
+    }
+    let word: String = chars[start..end].iter().collect();
+    if at(end) == '!' && at(end + 1) != '=' && !KEYWORDS.contains(&word.as_str()) {
+        return (TokenKind::Macro, end + 1);
+    }
+    let kind = if KEYWORDS.contains(&word.as_str()) {
+        TokenKind::Keyword
+    } else if PRIMITIVE_TYPES.contains(&word.as_str()) || word.starts_with(char::is_uppercase) {
+        TokenKind::Type
+    } else if at(end) == '(' {
+        TokenKind::Function
+    } else {
+        TokenKind::Identifier
+    };
+    (kind, end)
+}
+
+// Scan a numeric literal, including any fractional part, exponent and suffix, but not the
+// `..` of a range.
+fn scan_number(chars: &[char], start: usize) -> usize {
+    let at = |i: usize| chars.get(i).copied().unwrap_or('\0');
+    let mut i = scan_while(chars, start, is_ident_continue);
+    loop {
+        match at(i) {
+            '.' if at(i + 1).is_ascii_digit() => {
+                i = scan_while(chars, i + 1, is_ident_continue);
+            }
+            '+' | '-' if matches!(at(i - 1), 'e' | 'E') && at(i + 1).is_ascii_digit() => {
+                let is_hex = chars[start..i].starts_with(&['0', 'x']);
+                if is_hex {
+                    return i;
+                }
+                i = scan_while(chars, i + 1, is_ident_continue);
+            }
+            _ => return i,
+        }
+    }
+}
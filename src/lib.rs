@@ -8,12 +8,22 @@ pub mod cmd_args;
 pub mod code_utils;
 pub mod colors;
 pub mod config;
+pub mod diagnostics;
 pub mod errors;
+pub mod events;
+pub mod fixes;
+pub mod highlight;
 pub mod logging;
 pub mod manifest;
+pub mod render;
 pub mod repl;
+pub mod repl_completion;
+pub mod repl_session;
+pub mod script_env;
 pub mod shared;
+pub mod source_map;
 pub mod stdin;
+pub mod watch;
 
 // Re-export commonly used items for convenience
 pub use builder::{execute, gen_build_run};
@@ -34,8 +44,10 @@ pub use stdin::{edit, read};
 // pub use crate::{gen_build_run, BuildState, DYNAMIC_SUBDIR, REPL_SUBDIR, TEMP_SCRIPT_NAME, TMPDIR};
 pub const DYNAMIC_SUBDIR: &str = "rs_dyn";
 pub const FLOWER_BOX_LEN: usize = 70;
+pub const OUTPUT_OPTIONS_NAME: &str = "output_options.txt";
 pub const PACKAGE_NAME: &str = env!("CARGO_PKG_NAME");
 pub const REPL_SUBDIR: &str = "rs_repl";
+pub const RESULT_FILE_NAME: &str = "result.json";
 pub const RS_SUFFIX: &str = ".rs";
 pub const TEMP_DIR_NAME: &str = "temp";
 pub const TEMP_SCRIPT_NAME: &str = "temp.rs";
@@ -2,7 +2,9 @@
 use env_logger::{Builder, Env, WriteStyle};
 use firestorm::profile_fn;
 use lazy_static::lazy_static;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Mutex,
@@ -10,6 +12,7 @@ use std::sync::{
 use strum::EnumString;
 
 use crate::debug_log;
+use crate::events::{emit, events_enabled, events_replace_messages, strip_ansi, Event};
 use crate::{Cli, ThagError, MAYBE_CONFIG};
 
 static DEBUG_LOG_ENABLED: AtomicBool = AtomicBool::new(false);
@@ -24,7 +27,8 @@ pub fn is_debug_logging_enabled() -> bool {
 }
 
 /// An enum of the supported verbosity levels.
-#[derive(Clone, Copy, Debug, Default, Deserialize, EnumString, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, EnumString, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
 pub enum Verbosity {
     Quieter = 0,
@@ -36,24 +40,58 @@ pub enum Verbosity {
 }
 
 /// Define the Logger.
+/// Messages are written to stderr unless another sink has been set, so that they
+/// don't get mixed up with the output of the script on stdout.
 pub struct Logger {
     pub verbosity: Verbosity,
+    sink: Option<Box<dyn Write + Send>>,
 }
 
 impl Logger {
-    /// Construct a new Logger with the given Verbosity level.
+    /// Construct a new Logger with the given Verbosity level, writing to stderr.
     #[must_use]
     pub const fn new(verbosity: Verbosity) -> Self {
-        Self { verbosity }
+        Self {
+            verbosity,
+            sink: None,
+        }
     }
 
     /// Log a message if it passes the verbosity filter.
-    pub fn log(&self, verbosity: Verbosity, message: &str) {
+    /// If JSON events are enabled, the message is also reported as an event, and if the
+    /// events are going to stderr the text itself is suppressed.
+    pub fn log(&mut self, verbosity: Verbosity, message: &str) {
         if verbosity as u8 <= self.verbosity as u8 {
-            println!("{}", message);
+            if events_enabled() {
+                emit(&Event::Message {
+                    level: verbosity,
+                    text: &strip_ansi(message),
+                });
+                if events_replace_messages() {
+                    return;
+                }
+            }
+            if let Some(ref mut sink) = self.sink {
+                // Nowhere left to report a failure to log
+                let _ = writeln!(sink, "{}", message);
+                let _ = sink.flush();
+            } else {
+                eprintln!("{}", message);
+            }
         }
     }
 
+    /// Whether messages are being written to stderr rather than another sink.
+    #[must_use]
+    pub const fn writes_to_stderr(&self) -> bool {
+        self.sink.is_none()
+    }
+
+    /// Redirect messages to the given sink instead of stderr.
+    pub fn set_sink(&mut self, sink: Box<dyn Write + Send>) {
+        self.sink = Some(sink);
+    }
+
     /// Set the verbosity level.
     pub fn set_verbosity(&mut self, verbosity: Verbosity) {
         self.verbosity = verbosity;
@@ -92,6 +130,25 @@ pub fn set_verbosity(args: &Cli) -> Result<(), ThagError> {
     set_global_verbosity(verbosity)
 }
 
+/// Send `thag`'s own messages to the log file configured in `config.toml`, if any,
+/// instead of stderr.
+/// # Errors
+/// Will return `Err` if the log file cannot be opened or the logger mutex cannot be locked.
+pub fn set_sink_from_config() -> Result<(), ThagError> {
+    profile_fn!(set_sink_from_config);
+    if let Some(log_file) = MAYBE_CONFIG
+        .as_ref()
+        .and_then(|config| config.logging.log_file.as_ref())
+    {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file)?;
+        LOGGER.lock()?.set_sink(Box::new(file));
+    }
+    Ok(())
+}
+
 /// Set the logging verbosity for the current execution.
 /// # Errors
 /// Will return `Err` if the logger mutex cannot be locked.
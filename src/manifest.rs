@@ -1,5 +1,5 @@
 #![allow(clippy::uninlined_format_args)]
-use cargo_toml::{Dependency, Manifest};
+use cargo_toml::{Dependency, DependencyDetail, Manifest};
 use firestorm::profile_fn;
 use lazy_static::lazy_static;
 use mockall::automock;
@@ -11,10 +11,11 @@ use std::path::PathBuf;
 use std::process::{Command, Output};
 use std::time::Instant;
 
-use crate::code_utils::{infer_deps_from_ast, infer_deps_from_source}; // Valid if no circular dependency
+use crate::code_utils::{infer_deps_from_ast, infer_deps_from_source, AsyncRuntime}; // Valid if no circular dependency
 use crate::colors::{nu_resolve_style, MessageLevel};
 use crate::debug_log;
 use crate::errors::ThagError;
+use crate::events::{emit, Event};
 use crate::log;
 use crate::logging::Verbosity;
 #[cfg(target_os = "windows")]
@@ -126,6 +127,38 @@ as shown if you don't need special features:
     Ok((name, version))
 }
 
+/// Resolve a dependency given as `<crate>[@<version>]` and any features, as for the REPL
+/// `dep add` command, using a Cargo search for the latest version if none is given.
+/// # Errors
+/// Will return `Err` if the crate name or version is empty, or if no version is given and
+/// the Cargo search does not find the crate.
+pub fn resolve_dependency<R: CommandRunner>(
+    runner: &R,
+    spec: &str,
+    features: Vec<String>,
+) -> Result<(String, Dependency), ThagError> {
+    profile_fn!(resolve_dependency);
+    let (name, version) = match spec.split_once('@') {
+        Some((name, version)) if !name.is_empty() && !version.is_empty() => {
+            (name.to_string(), version.to_string())
+        }
+        Some(_) => {
+            return Err(format!("Invalid dependency [{spec}]: expected <crate>[@<version>]").into())
+        }
+        None => cargo_search(runner, spec)?,
+    };
+    let dependency = if features.is_empty() {
+        Dependency::Simple(version)
+    } else {
+        Dependency::Detailed(Box::new(DependencyDetail {
+            version: Some(version),
+            features,
+            ..Default::default()
+        }))
+    };
+    Ok((name, dependency))
+}
+
 /// Attempt to capture the dependency name and version from the first line returned by
 /// Cargo from the search by dependency name.
 /// # Errors
@@ -236,6 +269,9 @@ pub fn merge(
     let rs_inferred_deps = syntax_tree
         .as_ref()
         .map_or_else(|| infer_deps_from_source(rs_source), infer_deps_from_ast);
+    emit(&Event::Dependencies {
+        inferred: &rs_inferred_deps,
+    });
 
     debug_log!("build_state.rs_manifest={0:#?}\n", build_state.rs_manifest);
 
@@ -265,6 +301,79 @@ pub fn merge(
     Ok(())
 }
 
+/// Add the `serde` and `serde_json` dependencies needed to output a snippet's result as
+/// JSON, unless the script already specifies them. `serde` comes with its `derive`
+/// feature so that the snippet's own types can derive `Serialize`.
+pub fn add_json_deps(rs_dep_map: &mut BTreeMap<String, Dependency>) {
+    profile_fn!(add_json_deps);
+    rs_dep_map.entry("serde".to_string()).or_insert_with(|| {
+        Dependency::Detailed(Box::new(DependencyDetail {
+            version: Some("1.0".to_string()),
+            features: vec!["derive".to_string()],
+            ..Default::default()
+        }))
+    });
+    rs_dep_map
+        .entry("serde_json".to_string())
+        .or_insert_with(|| Dependency::Simple("1.0".to_string()));
+}
+
+/// Add the `clap` dependency, with its `derive` feature, that parses the command-line
+/// arguments of a main method with typed parameters, unless the script already specifies it.
+pub fn add_clap_deps(rs_dep_map: &mut BTreeMap<String, Dependency>) {
+    profile_fn!(add_clap_deps);
+    rs_dep_map.entry("clap".to_string()).or_insert_with(|| {
+        Dependency::Detailed(Box::new(DependencyDetail {
+            version: Some("4".to_string()),
+            features: vec!["derive".to_string()],
+            ..Default::default()
+        }))
+    });
+}
+
+/// Add the `regex` dependency that a loop filter needs to split its input on a regular
+/// expression, unless the script already specifies it.
+pub fn add_regex_deps(rs_dep_map: &mut BTreeMap<String, Dependency>) {
+    profile_fn!(add_regex_deps);
+    rs_dep_map
+        .entry("regex".to_string())
+        .or_insert_with(|| Dependency::Simple("1".to_string()));
+}
+
+/// Add the `csv` dependency that `--loop --csv` uses to read records, unless the script
+/// already specifies it.
+pub fn add_csv_deps(rs_dep_map: &mut BTreeMap<String, Dependency>) {
+    profile_fn!(add_csv_deps);
+    rs_dep_map
+        .entry("csv".to_string())
+        .or_insert_with(|| Dependency::Simple("1".to_string()));
+}
+
+/// Add the dependency for the async runtime that a snippet needs, with the features its
+/// `main` attribute requires, unless the script already specifies it.
+pub fn add_runtime_deps(
+    rs_dep_map: &mut BTreeMap<String, Dependency>,
+    async_runtime: AsyncRuntime,
+) {
+    profile_fn!(add_runtime_deps);
+    let (name, feature) = match async_runtime {
+        AsyncRuntime::Tokio => ("tokio", "full"),
+        AsyncRuntime::AsyncStd => ("async-std", "attributes"),
+        AsyncRuntime::None => return,
+    };
+    if rs_dep_map.contains_key(name) || rs_dep_map.contains_key(&name.replace('-', "_")) {
+        return;
+    }
+    rs_dep_map.insert(
+        name.to_string(),
+        Dependency::Detailed(Box::new(DependencyDetail {
+            version: Some("1".to_string()),
+            features: vec![feature.to_string()],
+            ..Default::default()
+        })),
+    );
+}
+
 pub fn search_deps(rs_inferred_deps: Vec<String>, rs_dep_map: &mut BTreeMap<String, Dependency>) {
     profile_fn!(search_deps);
     for dep_name in rs_inferred_deps {
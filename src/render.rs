@@ -0,0 +1,370 @@
+use firestorm::profile_fn;
+use ratatui::symbols::{bar, block, line};
+use serde_json::Value;
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal};
+
+use crate::colors::{nu_resolve_style, MessageLevel};
+
+/// The environment variable in which `thag` tells the generated program where to write
+/// the JSON serialisation of a snippet's result for `--output rich`.
+pub const RESULT_FILE_VAR: &str = "THAG_RESULT_FILE";
+
+// The width to assume when the output is not going to a terminal.
+const DEFAULT_WIDTH: usize = 80;
+
+// The narrowest a table column may be squeezed to fit the width.
+const MIN_COLUMN_WIDTH: usize = 3;
+
+// The number of bins in a histogram.
+const HISTOGRAM_BINS: usize = 10;
+
+// Sparkline glyphs from lowest to highest.
+const SPARK_LEVELS: [&str; 8] = [
+    bar::ONE_EIGHTH,
+    bar::ONE_QUARTER,
+    bar::THREE_EIGHTHS,
+    bar::HALF,
+    bar::FIVE_EIGHTHS,
+    bar::THREE_QUARTERS,
+    bar::SEVEN_EIGHTHS,
+    bar::FULL,
+];
+
+// Partial histogram bar glyphs, by eighths.
+const BAR_EIGHTHS: [&str; 8] = [
+    "",
+    block::ONE_EIGHTH,
+    block::ONE_QUARTER,
+    block::THREE_EIGHTHS,
+    block::HALF,
+    block::FIVE_EIGHTHS,
+    block::THREE_QUARTERS,
+    block::SEVEN_EIGHTHS,
+];
+
+/// The width available for rendering: that of the terminal if stdout is one, otherwise a
+/// default of 80 columns.
+#[must_use]
+pub fn terminal_width() -> usize {
+    if io::stdout().is_terminal() {
+        crossterm::terminal::size().map_or(DEFAULT_WIDTH, |(width, _)| usize::from(width))
+    } else {
+        DEFAULT_WIDTH
+    }
+}
+
+/// Render a snippet's result, in the form of its JSON serialisation, for the terminal:
+/// a sequence of tuples or structs as an aligned table, a map or struct as a two-column
+/// table, and a sequence of numbers as a sparkline, or as a histogram if there are too
+/// many numbers to fit the width. Anything else is shown as plain text.
+#[must_use]
+pub fn render_value(value: &Value, width: usize) -> String {
+    profile_fn!(render_value);
+    match value {
+        Value::Array(items) => {
+            if let Some(numbers) = numbers(items) {
+                if numbers.len() <= width {
+                    sparkline(&numbers)
+                } else {
+                    histogram(&numbers, width)
+                }
+            } else if let Some(rows) = tuple_rows(items) {
+                table(None, &rows, width)
+            } else if let Some((header, rows)) = struct_rows(items) {
+                table(Some(&header), &rows, width)
+            } else {
+                plain(value)
+            }
+        }
+        Value::Object(map) if !map.is_empty() => {
+            let rows: Vec<Vec<Cell>> = map
+                .iter()
+                .map(|(key, value)| vec![Cell::text(key), Cell::from(value)])
+                .collect();
+            table(None, &rows, width)
+        }
+        _ => plain(value),
+    }
+}
+
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        _ => serde_json::to_string_pretty(value).unwrap_or_default(),
+    }
+}
+
+// The values of a sequence of at least two numbers.
+fn numbers(items: &[Value]) -> Option<Vec<f64>> {
+    if items.len() < 2 {
+        return None;
+    }
+    items.iter().map(Value::as_f64).collect()
+}
+
+// The rows of a sequence of tuples, all of the same length.
+fn tuple_rows(items: &[Value]) -> Option<Vec<Vec<Cell>>> {
+    let len = items.first()?.as_array()?.len();
+    if len == 0 {
+        return None;
+    }
+    items
+        .iter()
+        .map(|item| {
+            item.as_array()
+                .filter(|fields| fields.len() == len)
+                .map(|fields| fields.iter().map(Cell::from).collect())
+        })
+        .collect()
+}
+
+// The header and rows of a sequence of structs, with a column for every field name found,
+// in order of appearance.
+fn struct_rows(items: &[Value]) -> Option<(Vec<Cell>, Vec<Vec<Cell>>)> {
+    let objects: Vec<_> = items.iter().map(Value::as_object).collect::<Option<_>>()?;
+    let mut names: Vec<&String> = Vec::new();
+    for name in objects.iter().flat_map(|object| object.keys()) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    if objects.is_empty() || names.is_empty() {
+        return None;
+    }
+    let rows = objects
+        .iter()
+        .map(|object| {
+            names
+                .iter()
+                .map(|name| object.get(*name).map_or_else(|| Cell::text(""), Cell::from))
+                .collect()
+        })
+        .collect();
+    Some((
+        names.into_iter().map(|name| Cell::text(name)).collect(),
+        rows,
+    ))
+}
+
+// A table cell: its text, and whether it is numeric and so right-aligned.
+struct Cell {
+    text: String,
+    numeric: bool,
+}
+
+impl Cell {
+    fn text(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            numeric: false,
+        }
+    }
+}
+
+impl From<&Value> for Cell {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::String(text) => Self::text(text),
+            Value::Null => Self::text(""),
+            Value::Number(_) => Self {
+                text: value.to_string(),
+                numeric: true,
+            },
+            _ => Self::text(&value.to_string()),
+        }
+    }
+}
+
+fn table(header: Option<&[Cell]>, rows: &[Vec<Cell>], width: usize) -> String {
+    let columns = rows.first().map_or(0, Vec::len);
+    let mut widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            header
+                .into_iter()
+                .chain(rows.iter().map(Vec::as_slice))
+                .map(|row| row[column].text.chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    // Squeeze the widest columns until the table fits
+    let separator = format!(" {} ", line::VERTICAL);
+    let gaps = 3 * columns.saturating_sub(1);
+    while widths.iter().sum::<usize>() + gaps > width {
+        match widths.iter_mut().max() {
+            Some(widest) if *widest > MIN_COLUMN_WIDTH => *widest -= 1,
+            _ => break,
+        }
+    }
+
+    let ghost = nu_resolve_style(MessageLevel::Ghost);
+    let format_row = |row: &[Cell], heading: bool| {
+        row.iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(column, (cell, &width))| {
+                let text = truncate(&cell.text, width);
+                let text = if cell.numeric {
+                    format!("{text:>width$}")
+                } else if column + 1 < columns {
+                    format!("{text:<width$}")
+                } else {
+                    text
+                };
+                if heading {
+                    nu_resolve_style(MessageLevel::Heading)
+                        .paint(text)
+                        .to_string()
+                } else {
+                    nu_resolve_style(MessageLevel::Normal)
+                        .paint(text)
+                        .to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(&ghost.paint(&separator).to_string())
+    };
+
+    let mut rendered = Vec::new();
+    if let Some(header) = header {
+        rendered.push(format_row(header, true));
+        let rule = widths
+            .iter()
+            .map(|&width| line::HORIZONTAL.repeat(width))
+            .collect::<Vec<_>>()
+            .join(&format!("{0}{1}{0}", line::HORIZONTAL, line::CROSS));
+        rendered.push(ghost.paint(rule).to_string());
+    }
+    rendered.extend(rows.iter().map(|row| format_row(row, false)));
+    rendered.join("\n")
+}
+
+// Cut text down to the given number of characters, marking any truncation with an ellipsis.
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        text.to_string()
+    } else {
+        let mut truncated: String = text.chars().take(width.saturating_sub(1)).collect();
+        truncated.push('…');
+        truncated
+    }
+}
+
+fn sparkline(numbers: &[f64]) -> String {
+    let (min, max) = range(numbers);
+    let line: String = numbers
+        .iter()
+        .map(|&number| {
+            let level = if max > min {
+                // Truncation is intended: the result is in 0..=7.
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let level = ((number - min) / (max - min) * 7.0).round() as usize;
+                level
+            } else {
+                SPARK_LEVELS.len() / 2
+            };
+            SPARK_LEVELS[level.min(SPARK_LEVELS.len() - 1)]
+        })
+        .collect();
+    format!(
+        "{}\n{}",
+        nu_resolve_style(MessageLevel::Subheading).paint(line),
+        summary(numbers, min, max)
+    )
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn summary(numbers: &[f64], min: f64, max: f64) -> String {
+    let mean = numbers.iter().sum::<f64>() / numbers.len() as f64;
+    nu_resolve_style(MessageLevel::Ghost)
+        .paint(format!(
+            "n={} min={} max={} mean={}",
+            numbers.len(),
+            format_number(min),
+            format_number(max),
+            format_number(mean)
+        ))
+        .to_string()
+}
+
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn histogram(numbers: &[f64], width: usize) -> String {
+    let (min, max) = range(numbers);
+    let bins = if max > min { HISTOGRAM_BINS } else { 1 };
+    let bin_width = (max - min) / bins as f64;
+    let mut counts = vec![0_usize; bins];
+    for &number in numbers {
+        let bin = if bin_width > 0.0 {
+            ((number - min) / bin_width) as usize
+        } else {
+            0
+        };
+        counts[bin.min(bins - 1)] += 1;
+    }
+
+    // Align the labels on the dash between the bounds of each bin
+    let bounds: Vec<(String, String)> = (0..bins)
+        .map(|bin| {
+            let low = (bin as f64).mul_add(bin_width, min);
+            (format_number(low), format_number(low + bin_width))
+        })
+        .collect();
+    let low_width = bounds.iter().map(|(low, _)| low.len()).max().unwrap_or(0);
+    let high_width = bounds.iter().map(|(_, high)| high.len()).max().unwrap_or(0);
+    let labels: Vec<String> = bounds
+        .iter()
+        .map(|(low, high)| format!("{low:>low_width$} – {high:<high_width$}"))
+        .collect();
+    let label_width = low_width + high_width + 3;
+    let most = counts.iter().copied().max().unwrap_or(1).max(1);
+    let count_width = most.to_string().len();
+    // Leave room for the label, the separator, and the count after the bar
+    let bar_width = width.saturating_sub(label_width + count_width + 4).max(1);
+
+    let heading = nu_resolve_style(MessageLevel::Heading);
+    let bar_style = nu_resolve_style(MessageLevel::Subheading);
+    let ghost = nu_resolve_style(MessageLevel::Ghost);
+    let mut rendered = String::new();
+    for (label, &count) in labels.iter().zip(&counts) {
+        let eighths = count * bar_width * 8 / most;
+        let bar = format!(
+            "{}{}",
+            block::FULL.repeat(eighths / 8),
+            BAR_EIGHTHS[eighths % 8]
+        );
+        let _ = writeln!(
+            rendered,
+            "{} {} {} {}",
+            heading.paint(label),
+            ghost.paint(line::VERTICAL),
+            bar_style.paint(bar),
+            count
+        );
+    }
+    rendered.push_str(&summary(numbers, min, max));
+    rendered
+}
+
+fn range(numbers: &[f64]) -> (f64, f64) {
+    numbers
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &number| {
+            (min.min(number), max.max(number))
+        })
+}
+
+// Show whole numbers without a fractional part, and others to at most 3 decimal places.
+fn format_number(number: f64) -> String {
+    if number.fract() == 0.0 && number.abs() < 1e15 {
+        format!("{number:.0}")
+    } else {
+        let text = format!("{number:.3}");
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
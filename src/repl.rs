@@ -4,8 +4,12 @@ use crate::code_utils::{self, clean_up, display_dir_contents, extract_ast_expr,
 #[cfg(debug_assertions)]
 use crate::debug_log;
 use crate::errors::ThagError;
+use crate::highlight::{tokenize, RustHighlighter, TokenKind};
 use crate::log;
 use crate::logging::Verbosity;
+use crate::manifest::{self, RealCommandRunner};
+use crate::repl_completion::ReplCompleter;
+use crate::repl_session::{self, EntryKind, Session};
 use crate::shared::Ast;
 use crate::{
     colors::{nu_resolve_style, MessageLevel},
@@ -13,20 +17,22 @@ use crate::{
     shared::BuildState,
 };
 
+use cargo_toml::Dependency;
 use clap::{ArgMatches, CommandFactory, Parser};
 use firestorm::profile_fn;
 use lazy_static::lazy_static;
 use reedline::{
-    default_emacs_keybindings, ColumnarMenu, DefaultCompleter, DefaultHinter, DefaultValidator,
-    EditCommand, Emacs, FileBackedHistory, KeyCode, KeyModifiers, Keybindings, MenuBuilder, Prompt,
-    PromptEditMode, PromptHistorySearch, PromptHistorySearchStatus, Reedline, ReedlineEvent,
-    ReedlineMenu, Signal,
+    default_emacs_keybindings, ColumnarMenu, DefaultHinter, EditCommand, Emacs, FileBackedHistory,
+    KeyCode, KeyModifiers, Keybindings, MenuBuilder, Prompt, PromptEditMode, PromptHistorySearch,
+    PromptHistorySearchStatus, Reedline, ReedlineEvent, ReedlineMenu, Signal, ValidationResult,
+    Validator,
 };
 use regex::Regex;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::OpenOptions;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use strum::{EnumIter, EnumString, IntoEnumIterator, IntoStaticStr};
 
@@ -54,6 +60,12 @@ pub static DEFAULT_MULTILINE_INDICATOR: &str = "";
 /// */
 /// From here they will be extracted to a dedicated Cargo.toml file.
 /// In this case the whole expression must be enclosed in curly braces to include the TOML in the expression.
+/// Items such as `use` declarations, functions and structs, and `let` bindings, accumulate in the
+/// session, so that later expressions can use them. Each evaluation replays the `let` bindings,
+/// so their initializers run again each time. Other statements are not replayed, so changes they
+/// make to bindings, such as `v.push(1)`, are lost after that evaluation, and the REPL warns of them.
+/// A new item replaces any earlier one of the same name.
+/// Dependencies added with the dep command likewise apply to every later evaluation in the session.
 /// At any stage before exiting the REPL, or at least as long as your TMPDIR is not cleared, you can
 /// go back and edit your expression or its generated Cargo.toml file and copy or save them from the
 /// editor or directly from their temporary disk locations.
@@ -71,6 +83,12 @@ enum ReplCommand {
     Delete,
     /// List temporary files for this eval
     List,
+    /// List the items and let bindings accumulated in this session
+    Session,
+    /// Drop session entries by their numbers in the session list, or all of them with `drop all`
+    Drop,
+    /// Manage session dependencies: `dep add <crate>[@<version>] [<feature>...]`, `dep rm <crate>...` or `dep list`
+    Dep,
     /// Edit history
     History,
     /// Show help information
@@ -98,6 +116,7 @@ pub struct Context<'a> {
     pub proc_flags: &'a ProcFlags,
     pub build_state: &'a mut BuildState,
     pub start: Instant,
+    pub session: Session,
 }
 
 /// A struct to implement the Prompt trait.
@@ -136,6 +155,83 @@ impl Prompt for ReplPrompt {
     }
 }
 
+/// A `reedline` validator that keeps reading input until it could be complete Rust, so that
+/// input ending in an open delimiter, unterminated literal or comment, or anything else
+/// that more input could complete, such as a trailing binary operator, continues on a new
+/// line. Input that is complete but invalid is submitted, to have its parse error shown.
+#[allow(clippy::module_name_repetitions)]
+pub struct ReplValidator;
+impl Validator for ReplValidator {
+    fn validate(&self, line: &str) -> ValidationResult {
+        if is_incomplete(line) {
+            ValidationResult::Incomplete
+        } else {
+            ValidationResult::Complete
+        }
+    }
+}
+
+/// Determine whether REPL input is incomplete, in that it fails to parse only because
+/// it ends too soon, as opposed to being valid or invalid regardless of what follows.
+#[must_use]
+pub fn is_incomplete(input: &str) -> bool {
+    profile_fn!(is_incomplete);
+    let tokens: Vec<_> = tokenize(input)
+        .into_iter()
+        .filter(|token| token.kind != TokenKind::Whitespace)
+        .collect();
+    let mut depth = 0;
+    for token in &tokens {
+        match &input[token.range.clone()] {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" => depth -= 1,
+            _ => continue,
+        }
+        if depth < 0 {
+            return false;
+        }
+    }
+    if depth > 0 {
+        return true;
+    }
+    // An unterminated string literal or block comment at the end
+    if let Some(last) = tokens
+        .last()
+        .filter(|last| matches!(last.kind, TokenKind::String | TokenKind::Comment))
+    {
+        if proc_macro2::TokenStream::from_str(&input[last.range.clone()]).is_err() {
+            return true;
+        }
+    }
+    // Parsed as by `repl_session::parse_entries`, an error located on the last line, which
+    // holds only the closing brace, means the input ran out.
+    repl_session::parse_entries(input)
+        .is_err_and(|err| err.span().start().line == input.split('\n').count() + 2)
+}
+
+/// Format an error from `repl_session::parse_entries` concisely, with the line of input
+/// that it refers to and a caret marking the position.
+#[must_use]
+pub fn format_parse_error(input: &str, err: &syn::Error) -> String {
+    // Allow for the line holding the opening brace that `parse_entries` adds
+    let start = err.span().start();
+    start
+        .line
+        .checked_sub(2)
+        .and_then(|index| input.split('\n').nth(index))
+        .map_or_else(
+            || format!("Error parsing code: {err}"),
+            |line| {
+                format!(
+                    "Error parsing code at line {}, column {}: {err}\n{line}\n{}^",
+                    start.line - 1,
+                    start.column + 1,
+                    " ".repeat(start.column)
+                )
+            },
+        )
+}
+
 fn add_menu_keybindings(keybindings: &mut Keybindings) {
     keybindings.add_binding(
         KeyModifiers::NONE,
@@ -181,6 +277,7 @@ pub fn run_repl(
         proc_flags,
         build_state,
         start,
+        session: Session::default(),
     };
     // get_emacs_keybindings();
     let context: &mut Context = &mut context;
@@ -192,7 +289,13 @@ pub fn run_repl(
         .map(String::from)
         .collect::<Vec<String>>();
 
-    let completer = Box::new(DefaultCompleter::new_with_wordlen(cmd_vec.clone(), 2));
+    // The completer sees the names defined in the session as updated before each read
+    let session_names = Arc::new(Mutex::new(BTreeSet::new()));
+    let completer = Box::new(ReplCompleter::new(
+        cmd_vec.clone(),
+        Arc::clone(&session_names),
+        context.build_state.cargo_home.clone(),
+    ));
 
     // Use the interactive menu to select options from the completer
     let columnar_menu = ColumnarMenu::default()
@@ -209,14 +312,14 @@ pub fn run_repl(
 
     let edit_mode = Box::new(Emacs::new(keybindings.clone()));
 
-    // let highlighter = Box::<ExampleHighlighter>::default();
+    let highlighter = Box::<RustHighlighter>::default();
     let mut line_editor = Reedline::create()
-        .with_validator(Box::new(DefaultValidator))
+        .with_validator(Box::new(ReplValidator))
         .with_hinter(Box::new(
             DefaultHinter::default().with_style(nu_resolve_style(MessageLevel::Ghost).italic()),
         ))
         .with_history(history)
-        // .with_highlighter(highlighter)
+        .with_highlighter(highlighter)
         .with_completer(completer)
         .with_menu(ReedlineMenu::EngineCompleter(completion_menu))
         .with_edit_mode(edit_mode);
@@ -228,6 +331,9 @@ pub fn run_repl(
 
     disp_repl_banner(cmd_list);
     loop {
+        if let Ok(mut names) = session_names.lock() {
+            *names = context.session.names();
+        }
         let sig = line_editor.read_line(&prompt)?;
         let input: &str = match sig {
             Signal::Success(ref buffer) => buffer,
@@ -244,7 +350,10 @@ pub fn run_repl(
         }
 
         let (first_word, rest) = parse_line(rs_source);
-        let maybe_cmd = {
+        // A name defined in the session is evaluated rather than taken for a command
+        let maybe_cmd = if context.session.defines(&first_word) {
+            None
+        } else {
             let mut matches = 0;
             let mut cmd = String::new();
             for key in &cmd_vec {
@@ -267,9 +376,47 @@ pub fn run_repl(
 
         if let Some(cmd) = maybe_cmd {
             if let Ok(repl_command) = ReplCommand::from_str(&cmd) {
-                let args = clap::Command::new("")
-                    .no_binary_name(true)
-                    .try_get_matches_from_mut(rest)?;
+                let command = match repl_command {
+                    ReplCommand::Drop => clap::Command::new("").arg(
+                        clap::Arg::new("entries")
+                            .num_args(1..)
+                            .required(true)
+                            .help("The numbers of the entries to drop, or `all`"),
+                    ),
+                    ReplCommand::Dep => clap::Command::new("")
+                        .subcommand_required(true)
+                        .subcommand(
+                            clap::Command::new("add")
+                                .about("Add a dependency, by default the latest version")
+                                .arg(
+                                    clap::Arg::new("crate")
+                                        .required(true)
+                                        .help("The crate, optionally as <crate>@<version>"),
+                                )
+                                .arg(
+                                    clap::Arg::new("features")
+                                        .num_args(0..)
+                                        .help("Features of the crate to enable"),
+                                ),
+                        )
+                        .subcommand(
+                            clap::Command::new("rm").about("Remove dependencies").arg(
+                                clap::Arg::new("crates")
+                                    .num_args(1..)
+                                    .required(true)
+                                    .help("The crates to remove"),
+                            ),
+                        )
+                        .subcommand(clap::Command::new("list").about("List the dependencies")),
+                    _ => clap::Command::new(""),
+                };
+                let args = match command.no_binary_name(true).try_get_matches_from(rest) {
+                    Ok(args) => args,
+                    Err(err) => {
+                        nu_color_println!(nu_resolve_style(MessageLevel::Error), "{err}");
+                        continue;
+                    }
+                };
                 match repl_command {
                     ReplCommand::Banner => disp_repl_banner(cmd_list),
                     ReplCommand::Help => {
@@ -294,6 +441,15 @@ pub fn run_repl(
                     ReplCommand::List => {
                         list(&args, context)?;
                     }
+                    ReplCommand::Session => {
+                        show_session(&args, context)?;
+                    }
+                    ReplCommand::Drop => {
+                        drop_entries(&args, context)?;
+                    }
+                    ReplCommand::Dep => {
+                        dep(&args, context)?;
+                    }
                     ReplCommand::History => {
                         edit_history(&args, context)?;
                     }
@@ -381,28 +537,74 @@ pub fn run_repl(
             }
         }
 
-        let rs_manifest = extract_manifest(rs_source, Instant::now())?;
-        context.build_state.rs_manifest = Some(rs_manifest);
-
-        let maybe_ast = extract_ast_expr(rs_source);
+        eval(rs_source, context)?;
+    }
+    Ok(())
+}
 
-        if let Ok(expr_ast) = maybe_ast {
-            code_utils::process_expr(
-                expr_ast,
-                context.build_state,
-                rs_source,
-                context.args,
-                context.proc_flags,
-                &context.start,
-            )?;
-        } else {
+/// Evaluate input to the REPL in the context of the session so far, adding its items and
+/// `let` bindings to the session if it succeeds, and returning whether it did.
+/// # Errors
+/// Will return `Err` if a toml block in the input can't be parsed. Failures to parse,
+/// build or run the input itself are reported and give `Ok(false)`.
+pub fn eval(rs_source: &str, context: &mut Context) -> Result<bool, ThagError> {
+    let entries = match repl_session::parse_entries(rs_source) {
+        Ok(entries) => entries,
+        Err(err) => {
             nu_color_println!(
                 nu_resolve_style(MessageLevel::Error),
-                "Error parsing code: {maybe_ast:#?}"
+                "{}",
+                format_parse_error(rs_source, &err)
             );
+            return Ok(false);
         }
+    };
+
+    let mut rs_manifest = extract_manifest(rs_source, Instant::now())?;
+    let dependencies = rs_manifest.dependencies.clone();
+    context
+        .session
+        .merge_dependencies(&mut rs_manifest.dependencies);
+    context.build_state.rs_manifest = Some(rs_manifest);
+
+    // Evaluate the input in the context of the session so far
+    let session_source = context.session.source_with(rs_source);
+    let maybe_ast = extract_ast_expr(&session_source);
+
+    let Ok(expr_ast) = maybe_ast else {
+        nu_color_println!(
+            nu_resolve_style(MessageLevel::Error),
+            "Error parsing code: {maybe_ast:#?}"
+        );
+        return Ok(false);
+    };
+    let result = code_utils::process_expr(
+        expr_ast,
+        context.build_state,
+        &session_source,
+        context.args,
+        context.proc_flags,
+        &context.start,
+    );
+    let succeeded = result.is_ok();
+    if succeeded {
+        context.session.add(entries, dependencies);
+        let mutated = context.session.mutated_bindings(rs_source);
+        if !mutated.is_empty() {
+            nu_color_println!(
+                nu_resolve_style(MessageLevel::Warning),
+                "This input may change {}, but only items and `let` bindings are replayed in later evaluations, so any such change will be lost. To keep a change, make it in the initializer of a new `let` binding.",
+                mutated.iter().map(|name| format!("`{name}`")).collect::<Vec<_>>().join(", ")
+            );
+        }
+    } else if !entries.is_empty() {
+        log!(
+            Verbosity::Normal,
+            "Definitions not added to the session, as evaluation failed"
+        );
     }
-    Ok(())
+    log!(Verbosity::Normal, "{result:?}");
+    Ok(succeeded)
 }
 
 fn show_key_bindings(formatted_bindings: Vec<(String, String)>, max_key_len: usize) {
@@ -976,7 +1178,7 @@ pub fn edit_history(
     context: &mut Context,
 ) -> Result<Option<String>, ThagError> {
     let history_path = &context.build_state.cargo_home.join(HISTORY_FILE);
-    println!("history_path={history_path:#?}");
+    log!(Verbosity::Verbose, "history_path={history_path:#?}");
     OpenOptions::new()
         .write(true)
         .create(true)
@@ -1028,6 +1230,167 @@ pub fn run_expr(_args: &ArgMatches, context: &mut Context) -> Result<Option<Stri
     Ok(Some(String::from("End of run")))
 }
 
+/// List the items and `let` bindings accumulated in the session, numbered for `drop`.
+/// # Errors
+/// Currently will not return any errors.
+#[allow(clippy::unnecessary_wraps)]
+pub fn show_session(
+    _args: &ArgMatches,
+    context: &mut Context,
+) -> Result<Option<String>, ThagError> {
+    let session = &context.session;
+    if session.entries().is_empty() {
+        log!(Verbosity::Quieter, "The session has no entries");
+    }
+    for (index, entry) in session.entries().iter().enumerate() {
+        let kind = match entry.kind {
+            EntryKind::Item => "item",
+            EntryKind::Let => "let",
+        };
+        nu_color_println!(
+            nu_resolve_style(MessageLevel::Emphasis),
+            "{:>3}. [{kind}] {}",
+            index + 1,
+            entry.name.as_deref().unwrap_or("")
+        );
+        log!(Verbosity::Quieter, "{}", entry.source);
+    }
+    if !session.dependencies().is_empty() {
+        log!(
+            Verbosity::Quieter,
+            "Dependencies: {}",
+            session
+                .dependencies()
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(Some(String::from("End of session")))
+}
+
+/// Drop entries from the session, given their numbers in the session list or `all`.
+/// # Errors
+/// Currently will not return any errors: invalid entry numbers are reported and ignored.
+#[allow(clippy::unnecessary_wraps)]
+pub fn drop_entries(args: &ArgMatches, context: &mut Context) -> Result<Option<String>, ThagError> {
+    let selections: Vec<&String> = args
+        .get_many::<String>("entries")
+        .map(Iterator::collect)
+        .unwrap_or_default();
+    if selections
+        .iter()
+        .any(|selection| selection.as_str() == "all")
+    {
+        context.session.clear();
+        log!(Verbosity::Quieter, "Dropped all session entries");
+        return Ok(Some(String::from("End of drop")));
+    }
+    let numbers: Result<Vec<usize>, _> = selections
+        .iter()
+        .map(|selection| selection.parse::<usize>())
+        .collect();
+    let result = numbers
+        .map_err(|err| ThagError::from(format!("Invalid entry number: {err}")))
+        .and_then(|numbers| context.session.drop_entries(&numbers));
+    match result {
+        Ok(dropped) => {
+            for entry in dropped {
+                log!(Verbosity::Quieter, "Dropped {}", entry.source);
+            }
+        }
+        Err(err) => nu_color_println!(nu_resolve_style(MessageLevel::Error), "{err}"),
+    }
+    Ok(Some(String::from("End of drop")))
+}
+
+/// Add, remove or list the dependencies of the session, which apply to every later
+/// evaluation in addition to those in its toml block, if any. Removing a crate also drops
+/// the session's `use` declarations of it, from which it would otherwise be inferred again.
+/// # Errors
+/// Currently will not return any errors: crates that can't be resolved or removed are
+/// reported and ignored.
+#[allow(clippy::unnecessary_wraps)]
+pub fn dep(args: &ArgMatches, context: &mut Context) -> Result<Option<String>, ThagError> {
+    match args.subcommand() {
+        Some(("add", add_args)) => {
+            let spec = add_args
+                .get_one::<String>("crate")
+                .map_or("", String::as_str);
+            let features = add_args
+                .get_many::<String>("features")
+                .map(|features| features.cloned().collect())
+                .unwrap_or_default();
+            match manifest::resolve_dependency(&RealCommandRunner, spec, features) {
+                Ok((name, dependency)) => {
+                    log!(
+                        Verbosity::Quieter,
+                        "Added {name} = {}",
+                        dependency_toml(&dependency)
+                    );
+                    if let Some(rs_manifest) = context.build_state.rs_manifest.as_mut() {
+                        rs_manifest
+                            .dependencies
+                            .insert(name.clone(), dependency.clone());
+                    }
+                    context.session.add_dependency(name, dependency);
+                }
+                Err(err) => nu_color_println!(nu_resolve_style(MessageLevel::Error), "{err}"),
+            }
+        }
+        Some(("rm", rm_args)) => {
+            for name in rm_args.get_many::<String>("crates").into_iter().flatten() {
+                match context.session.remove_dependency(name) {
+                    Some(removed) => {
+                        if let Some(rs_manifest) = context.build_state.rs_manifest.as_mut() {
+                            rs_manifest.dependencies.remove(&removed);
+                        }
+                        log!(Verbosity::Quieter, "Removed {removed}");
+                        drop_uses_of(&removed, context);
+                    }
+                    // The crate may still be inferred from the session's use declarations
+                    None if drop_uses_of(name, context) => (),
+                    None => nu_color_println!(
+                        nu_resolve_style(MessageLevel::Error),
+                        "No session dependency {name}"
+                    ),
+                }
+            }
+        }
+        _ => {
+            let dependencies = context.session.dependencies();
+            if dependencies.is_empty() {
+                log!(Verbosity::Quieter, "The session has no dependencies");
+            }
+            for (name, dependency) in dependencies {
+                log!(
+                    Verbosity::Quieter,
+                    "{name} = {}",
+                    dependency_toml(dependency)
+                );
+            }
+        }
+    }
+    Ok(Some(String::from("End of dep")))
+}
+
+// Drop the session entries from which a removed crate would be inferred as a dependency
+// again, reporting whether there were any.
+fn drop_uses_of(crate_name: &str, context: &mut Context) -> bool {
+    let dropped = context.session.drop_uses_of(crate_name);
+    for entry in &dropped {
+        log!(Verbosity::Quieter, "Dropped {}", entry.source);
+    }
+    !dropped.is_empty()
+}
+
+// Format a dependency as its value in a Cargo.toml `[dependencies]` table.
+fn dependency_toml(dependency: &Dependency) -> String {
+    toml::Value::try_from(dependency)
+        .map_or_else(|_| format!("{dependency:?}"), |value| value.to_string())
+}
+
 /// Parse the current line. Borrowed from clap-repl crate.
 #[must_use]
 pub fn parse_line(line: &str) -> (String, Vec<String>) {
@@ -0,0 +1,186 @@
+use firestorm::profile_fn;
+use lazy_static::lazy_static;
+use reedline::{Completer, Span, Suggestion};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::highlight::{KEYWORDS, PRIMITIVE_TYPES};
+
+lazy_static! {
+    /// The paths of the stable public modules and items of `std`, generated by
+    /// `tools/gen_std_index.rs` from the standard library documentation of the Rust version
+    /// recorded in the header of the file. It misses paths added in later versions and may
+    /// offer paths that older toolchains lack, so regenerate it on moving to a new toolchain.
+    static ref STD_INDEX: Vec<&'static str> = include_str!("std_index.txt")
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+}
+
+/// The crate roots that can follow `use` besides the names of dependencies.
+const USE_ROOTS: &[&str] = &["alloc", "core", "crate", "self", "std", "super"];
+
+/// A `reedline` completer for Rust input to the REPL, which completes:
+///
+/// * REPL command names, keywords, primitive types and the names defined in the session,
+///   for the first word of the input;
+/// * keywords, primitive types and names defined in the session elsewhere;
+/// * paths into `std`, one segment at a time, from a bundled index;
+/// * crate names from the local registry cache after `use`.
+#[allow(clippy::module_name_repetitions)]
+pub struct ReplCompleter {
+    commands: Vec<String>,
+    session_names: Arc<Mutex<BTreeSet<String>>>,
+    cargo_home: PathBuf,
+    // Loaded on first use, as the registry cache may hold thousands of crates
+    crate_names: Option<BTreeSet<String>>,
+}
+
+impl ReplCompleter {
+    /// Create a completer for the given REPL command names, which completes the session
+    /// names as currently held by `session_names`, and crate names from the registry cache
+    /// under `cargo_home`.
+    #[must_use]
+    pub const fn new(
+        commands: Vec<String>,
+        session_names: Arc<Mutex<BTreeSet<String>>>,
+        cargo_home: PathBuf,
+    ) -> Self {
+        Self {
+            commands,
+            session_names,
+            cargo_home,
+            crate_names: None,
+        }
+    }
+
+    fn crate_candidates(&mut self) -> Vec<String> {
+        let cargo_home = &self.cargo_home;
+        self.crate_names
+            .get_or_insert_with(|| cached_crate_names(cargo_home))
+            .iter()
+            .cloned()
+            .chain(USE_ROOTS.iter().map(ToString::to_string))
+            .collect()
+    }
+
+    fn word_candidates(&self, is_first_word: bool) -> Vec<String> {
+        let commands = if is_first_word {
+            self.commands.as_slice()
+        } else {
+            &[]
+        };
+        let session_names = self
+            .session_names
+            .lock()
+            .map(|names| names.iter().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        commands
+            .iter()
+            .cloned()
+            .chain(
+                KEYWORDS
+                    .iter()
+                    .chain(PRIMITIVE_TYPES)
+                    .chain(&["std"])
+                    .map(ToString::to_string),
+            )
+            .chain(session_names)
+            .collect()
+    }
+}
+
+impl Completer for ReplCompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        profile_fn!(complete);
+        let before = &line[..pos];
+        let start = before
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !(c.is_alphanumeric() || c == '_' || c == ':'))
+            .map_or(0, |(index, c)| index + c.len_utf8());
+        let word = &before[start..];
+        if word.is_empty() {
+            return vec![];
+        }
+        let preceding = before[..start].trim_end();
+        let candidates = if word.contains("::") {
+            std_path_candidates(word)
+        } else if preceding
+            .strip_suffix("use")
+            .is_some_and(|rest| !rest.ends_with(|c: char| c.is_alphanumeric() || c == '_'))
+        {
+            self.crate_candidates()
+        } else {
+            self.word_candidates(preceding.is_empty())
+        };
+        candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(word) && candidate != word)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|value| Suggestion {
+                value,
+                description: None,
+                style: None,
+                extra: None,
+                span: Span::new(start, pos),
+                append_whitespace: false,
+            })
+            .collect()
+    }
+}
+
+// Complete a path into `std` up to the end of its next segment.
+fn std_path_candidates(word: &str) -> Vec<String> {
+    STD_INDEX
+        .iter()
+        .filter(|path| path.starts_with(word))
+        .map(|path| {
+            path[word.len()..]
+                .find("::")
+                .map_or(*path, |index| &path[..word.len() + index])
+                .to_string()
+        })
+        .collect()
+}
+
+/// The names of the crates in the local registry cache, which holds the crates that Cargo
+/// has downloaded, in the form used in paths, with underscores in place of hyphens.
+#[must_use]
+pub fn cached_crate_names(cargo_home: &Path) -> BTreeSet<String> {
+    profile_fn!(cached_crate_names);
+    let Ok(registries) = fs::read_dir(cargo_home.join("registry").join("cache")) else {
+        return BTreeSet::new();
+    };
+    registries
+        .flatten()
+        .filter_map(|registry| fs::read_dir(registry.path()).ok())
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name();
+            let stem = file_name.to_str()?.strip_suffix(".crate")?;
+            crate_name(stem).map(|name| name.replace('-', "_"))
+        })
+        .collect()
+}
+
+// Split the crate name from a `<name>-<version>` file stem. Since a name may itself
+// contain hyphens followed by digits, the version is taken to start after the first
+// hyphen that is followed by a full `major.minor.patch` version.
+fn crate_name(stem: &str) -> Option<&str> {
+    stem.match_indices('-')
+        .map(|(index, _)| index)
+        .find(|&index| {
+            let version = stem[index + 1..].split(['-', '+']).next().unwrap_or("");
+            let parts: Vec<_> = version.split('.').collect();
+            parts.len() == 3
+                && parts
+                    .iter()
+                    .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+        })
+        .map(|index| &stem[..index])
+}
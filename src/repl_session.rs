@@ -0,0 +1,422 @@
+use cargo_toml::Dependency;
+use firestorm::profile_fn;
+use proc_macro2::{LineColumn, Span};
+use quote::ToTokens;
+use std::collections::{BTreeMap, BTreeSet};
+use syn::spanned::Spanned;
+use syn::visit::Visit;
+use syn::{BinOp, Expr, Item, Stmt, UnOp};
+
+use crate::code_utils::infer_deps_from_ast;
+use crate::errors::ThagError;
+use crate::shared::Ast;
+
+/// The kind of definition that a REPL session entry holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    /// An item, such as a `use` declaration, function, struct or impl block.
+    Item,
+    /// A `let` binding.
+    Let,
+}
+
+/// A definition accumulated in a REPL session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionEntry {
+    pub kind: EntryKind,
+    /// The name that the entry defines, if any, by which a later item of the same name
+    /// replaces it. For a `let` binding this is its pattern, for display only.
+    pub name: Option<String>,
+    /// The source of the entry as entered.
+    pub source: String,
+}
+
+impl SessionEntry {
+    /// Whether this entry replaces an earlier one: an item replaces an item of the same
+    /// name, or if it has no name, such as a `use` declaration, an identical one.
+    #[must_use]
+    pub fn replaces(&self, earlier: &Self) -> bool {
+        self.kind == EntryKind::Item
+            && earlier.kind == EntryKind::Item
+            && match self.name {
+                Some(_) => earlier.name == self.name,
+                None => earlier.source == self.source,
+            }
+    }
+}
+
+/// The items and `let` bindings accumulated over a REPL session, together with any
+/// dependencies from toml blocks, so that each evaluation can see the definitions made
+/// by earlier ones.
+///
+/// Each evaluation is still a separate program, so the session source precedes the new
+/// input in it: items are simply redefined, while `let` bindings are replayed, which
+/// means their initializers run again each time. Other statements and expressions run
+/// once only, so any change they make to a binding, such as `v.push(1)` or `n += 1`, is
+/// lost after that evaluation. The program is generated in the same project directory
+/// for every evaluation, so Cargo only rebuilds what has changed.
+#[derive(Clone, Debug, Default)]
+pub struct Session {
+    entries: Vec<SessionEntry>,
+    dependencies: BTreeMap<String, Dependency>,
+}
+
+impl Session {
+    /// The entries accumulated so far, in the order they were added.
+    #[must_use]
+    pub fn entries(&self) -> &[SessionEntry] {
+        &self.entries
+    }
+
+    /// The dependencies accumulated so far from the toml blocks of evaluated input.
+    #[must_use]
+    pub const fn dependencies(&self) -> &BTreeMap<String, Dependency> {
+        &self.dependencies
+    }
+
+    /// Determine whether the given word is a name that the session defines, either as
+    /// an item or in the pattern of a `let` binding.
+    #[must_use]
+    pub fn defines(&self, word: &str) -> bool {
+        self.entries.iter().any(|entry| {
+            entry.name.as_deref().is_some_and(|name| {
+                name.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .any(|name_word| name_word == word)
+            })
+        })
+    }
+
+    /// The identifiers that the session defines, as items or in the patterns of `let`
+    /// bindings, in sorted order.
+    #[must_use]
+    pub fn names(&self) -> BTreeSet<String> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.name.as_deref())
+            .filter(|name| !name.starts_with("impl "))
+            .flat_map(|name| name.split(|c: char| !(c.is_alphanumeric() || c == '_')))
+            .filter(|word| {
+                word.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                    && !matches!(*word, "_" | "mut" | "ref")
+            })
+            .map(String::from)
+            .collect()
+    }
+
+    /// Identify the mutable `let` bindings, of the session or of the input itself, that the
+    /// input may change other than in a `let` binding: by assigning to them, borrowing them
+    /// mutably or calling methods on them. Such changes are not kept in the session.
+    #[must_use]
+    pub fn mutated_bindings(&self, input: &str) -> BTreeSet<String> {
+        profile_fn!(mutated_bindings);
+        let Ok((_, stmts)) = input_stmts(input) else {
+            return BTreeSet::new();
+        };
+        let mut finder = FindMutations {
+            mutable: self.mutable_names(),
+            found: BTreeSet::new(),
+        };
+        for stmt in &stmts {
+            match stmt {
+                Stmt::Local(local) => finder
+                    .mutable
+                    .extend(mutable_names(&tokens_text(&local.pat))),
+                Stmt::Expr(expr, _) => finder.visit_expr(expr),
+                Stmt::Item(_) | Stmt::Macro(_) => (),
+            }
+        }
+        finder.found
+    }
+
+    // The names bound mutably by the session's `let` bindings.
+    fn mutable_names(&self) -> BTreeSet<String> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.kind == EntryKind::Let)
+            .filter_map(|entry| entry.name.as_deref())
+            .flat_map(mutable_names)
+            .collect()
+    }
+
+    /// Combine the session source with the given input into a block to evaluate, whose
+    /// value is that of the input. Items that the input redefines are left out, as they
+    /// would clash with the new definitions in the same block.
+    #[must_use]
+    pub fn source_with(&self, input: &str) -> String {
+        profile_fn!(source_with);
+        let new_entries = parse_entries(input).unwrap_or_default();
+        let mut source = String::from("{\n");
+        for entry in self
+            .entries
+            .iter()
+            .filter(|existing| !new_entries.iter().any(|entry| entry.replaces(existing)))
+        {
+            source.push_str(&entry.source);
+            source.push('\n');
+        }
+        source.push_str(input);
+        source.push_str("\n}");
+        source
+    }
+
+    /// Add the entries from input that has been evaluated successfully, replacing any
+    /// earlier items of the same name, and any dependencies from its toml block.
+    pub fn add(&mut self, entries: Vec<SessionEntry>, dependencies: BTreeMap<String, Dependency>) {
+        profile_fn!(add);
+        for entry in entries {
+            self.entries.retain(|existing| !entry.replaces(existing));
+            self.entries.push(entry);
+        }
+        self.dependencies.extend(dependencies);
+    }
+
+    /// Add a dependency to the session, replacing any of the same name.
+    pub fn add_dependency(&mut self, name: String, dependency: Dependency) {
+        self.dependencies.insert(name, dependency);
+    }
+
+    /// Remove a dependency from the session, allowing for hyphens in place of underscores
+    /// in its name or vice versa, and returning the name it was found under.
+    pub fn remove_dependency(&mut self, name: &str) -> Option<String> {
+        [
+            name.to_string(),
+            name.replace('-', "_"),
+            name.replace('_', "-"),
+        ]
+        .into_iter()
+        .find(|candidate| self.dependencies.remove(candidate).is_some())
+    }
+
+    /// Drop the item entries that would have a crate inferred as a dependency, such as its
+    /// `use` declarations, returning the dropped entries. Otherwise removing the crate from
+    /// the session's dependencies would be undone by inferring it again at the next
+    /// evaluation.
+    pub fn drop_uses_of(&mut self, crate_name: &str) -> Vec<SessionEntry> {
+        profile_fn!(drop_uses_of);
+        let crate_name = crate_name.replace('-', "_");
+        let (dropped, kept) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|entry| {
+                entry.kind == EntryKind::Item
+                    && syn::parse_str::<syn::File>(&entry.source).is_ok_and(|file| {
+                        infer_deps_from_ast(&Ast::File(file)).contains(&crate_name)
+                    })
+            });
+        self.entries = kept;
+        dropped
+    }
+
+    /// Add the session's dependencies to those of a manifest, unless already present.
+    pub fn merge_dependencies(&self, rs_dep_map: &mut BTreeMap<String, Dependency>) {
+        for (name, dependency) in &self.dependencies {
+            rs_dep_map
+                .entry(name.clone())
+                .or_insert_with(|| dependency.clone());
+        }
+    }
+
+    /// Drop the entries with the given 1-based numbers, as listed by `entries`, returning
+    /// the dropped entries.
+    /// # Errors
+    /// Will return `Err` without dropping anything if any number is out of range.
+    pub fn drop_entries(&mut self, numbers: &[usize]) -> Result<Vec<SessionEntry>, ThagError> {
+        profile_fn!(drop_entries);
+        if let Some(number) = numbers
+            .iter()
+            .find(|&&number| number == 0 || number > self.entries.len())
+        {
+            return Err(format!(
+                "No session entry {number}: entries are numbered 1 to {}",
+                self.entries.len()
+            )
+            .into());
+        }
+        let (dropped, kept) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .enumerate()
+            .partition::<Vec<_>, _>(|(index, _)| numbers.contains(&(index + 1)));
+        self.entries = kept.into_iter().map(|(_, entry)| entry).collect();
+        Ok(dropped.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    /// Drop all entries and dependencies.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.dependencies.clear();
+    }
+}
+
+/// Identify the items and `let` bindings in REPL input, which are to be added to the
+/// session if the input evaluates successfully. If the input is enclosed in braces, as
+/// it must be to include a toml block, its statements are taken from within them.
+/// # Errors
+/// Will return `Err` if the input can't be parsed as a sequence of statements.
+pub fn parse_entries(input: &str) -> Result<Vec<SessionEntry>, syn::Error> {
+    profile_fn!(parse_entries);
+    let (source, stmts) = input_stmts(input)?;
+    Ok(stmts
+        .iter()
+        .filter_map(|stmt| {
+            let (kind, name) = match stmt {
+                Stmt::Local(local) => (EntryKind::Let, Some(tokens_text(&local.pat))),
+                Stmt::Item(item) => (EntryKind::Item, item_name(item)),
+                Stmt::Expr(..) | Stmt::Macro(_) => return None,
+            };
+            Some(SessionEntry {
+                kind,
+                name,
+                source: span_text(&source, stmt.span()),
+            })
+        })
+        .collect())
+}
+
+// Parse REPL input as statements, from within any braces enclosing it, returning them
+// with the source they were parsed from.
+fn input_stmts(input: &str) -> Result<(String, Vec<Stmt>), syn::Error> {
+    // Start the input on a line of its own so that its line numbers are offset by one
+    let source = format!("{{\n{input}\n}}");
+    let block = syn::parse_str::<syn::Block>(&source)?;
+    let stmts = match block.stmts.as_slice() {
+        [Stmt::Expr(Expr::Block(expr_block), None)]
+            if expr_block.attrs.is_empty() && expr_block.label.is_none() =>
+        {
+            expr_block.block.stmts.clone()
+        }
+        _ => block.stmts,
+    };
+    Ok((source, stmts))
+}
+
+// The names bound mutably by a pattern, given as text.
+fn mutable_names(pattern: &str) -> Vec<String> {
+    let words: Vec<&str> = pattern
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+        .collect();
+    words
+        .windows(2)
+        .filter(|pair| pair[0] == "mut")
+        .map(|pair| pair[1].to_string())
+        .collect()
+}
+
+// Find the given mutable bindings that an expression may change.
+struct FindMutations {
+    mutable: BTreeSet<String>,
+    found: BTreeSet<String>,
+}
+
+impl FindMutations {
+    // Record the binding at the root of a place expression, such as `v` in `v.0[i]`.
+    fn mutates(&mut self, mut place: &Expr) {
+        loop {
+            place = match place {
+                Expr::Field(field) => &field.base,
+                Expr::Index(index) => &index.expr,
+                Expr::Paren(paren) => &paren.expr,
+                Expr::Unary(unary) if matches!(unary.op, UnOp::Deref(_)) => &unary.expr,
+                Expr::Path(path) => {
+                    if let Some(ident) = path.path.get_ident() {
+                        if self.mutable.contains(&ident.to_string()) {
+                            self.found.insert(ident.to_string());
+                        }
+                    }
+                    return;
+                }
+                _ => return,
+            };
+        }
+    }
+}
+
+impl<'a> Visit<'a> for FindMutations {
+    fn visit_expr_assign(&mut self, node: &'a syn::ExprAssign) {
+        self.mutates(&node.left);
+        syn::visit::visit_expr_assign(self, node);
+    }
+
+    fn visit_expr_binary(&mut self, node: &'a syn::ExprBinary) {
+        if matches!(
+            node.op,
+            BinOp::AddAssign(_)
+                | BinOp::SubAssign(_)
+                | BinOp::MulAssign(_)
+                | BinOp::DivAssign(_)
+                | BinOp::RemAssign(_)
+                | BinOp::BitXorAssign(_)
+                | BinOp::BitAndAssign(_)
+                | BinOp::BitOrAssign(_)
+                | BinOp::ShlAssign(_)
+                | BinOp::ShrAssign(_)
+        ) {
+            self.mutates(&node.left);
+        }
+        syn::visit::visit_expr_binary(self, node);
+    }
+
+    fn visit_expr_method_call(&mut self, node: &'a syn::ExprMethodCall) {
+        self.mutates(&node.receiver);
+        syn::visit::visit_expr_method_call(self, node);
+    }
+
+    fn visit_expr_reference(&mut self, node: &'a syn::ExprReference) {
+        if node.mutability.is_some() {
+            self.mutates(&node.expr);
+        }
+        syn::visit::visit_expr_reference(self, node);
+    }
+
+    fn visit_item(&mut self, _node: &'a Item) {}
+}
+
+// The name by which a later item replaces an earlier one: its identifier, or for an impl
+// block, its trait and type. `use` declarations have none and are only replaced by the
+// same declaration.
+fn item_name(item: &Item) -> Option<String> {
+    let ident = match item {
+        Item::Const(item) => &item.ident,
+        Item::Enum(item) => &item.ident,
+        Item::ExternCrate(item) => &item.ident,
+        Item::Fn(item) => &item.sig.ident,
+        Item::Macro(item) => item.ident.as_ref()?,
+        Item::Mod(item) => &item.ident,
+        Item::Static(item) => &item.ident,
+        Item::Struct(item) => &item.ident,
+        Item::Trait(item) => &item.ident,
+        Item::TraitAlias(item) => &item.ident,
+        Item::Type(item) => &item.ident,
+        Item::Union(item) => &item.ident,
+        Item::Impl(item) => {
+            let self_ty = tokens_text(&item.self_ty);
+            return Some(item.trait_.as_ref().map_or_else(
+                || format!("impl {self_ty}"),
+                |(_, path, _)| format!("impl {} for {self_ty}", tokens_text(path)),
+            ));
+        }
+        _ => return None,
+    };
+    Some(ident.to_string())
+}
+
+fn tokens_text(tokens: &impl ToTokens) -> String {
+    tokens.to_token_stream().to_string()
+}
+
+// Extract the text of a span from the source it was parsed from.
+fn span_text(source: &str, span: Span) -> String {
+    let offset = |position: LineColumn| {
+        let line_start: usize = source
+            .split_inclusive('\n')
+            .take(position.line - 1)
+            .map(str::len)
+            .sum();
+        line_start
+            + source[line_start..]
+                .chars()
+                .take(position.column)
+                .map(char::len_utf8)
+                .sum::<usize>()
+    };
+    source[offset(span.start())..offset(span.end())].to_string()
+}
@@ -0,0 +1,114 @@
+use firestorm::profile_fn;
+use std::fs;
+use std::path::Path;
+
+use crate::cmd_args::ProcFlags;
+use crate::debug_log;
+use crate::errors::ThagError;
+use crate::shared::BuildState;
+use crate::VERSION;
+
+/// Environment variable giving the running program the path of the script it was built from.
+pub const SCRIPT_PATH_VAR: &str = "THAG_SCRIPT_PATH";
+/// Environment variable giving the running program the directory of its script.
+pub const SCRIPT_DIR_VAR: &str = "THAG_SCRIPT_DIR";
+/// Environment variable giving the running program the version of `thag` that ran it.
+pub const VERSION_VAR: &str = "THAG_VERSION";
+/// Environment variable telling the running program how `thag` was invoked: `script`,
+/// `expr`, `loop`, `repl`, `stdin` or `edit`.
+pub const MODE_VAR: &str = "THAG_MODE";
+/// Name of the optional file beside a script of environment variables to run it with.
+pub const ENV_FILE_NAME: &str = ".env";
+
+/// Determine the invocation mode to report to the running program.
+#[must_use]
+pub fn invocation_mode(proc_flags: &ProcFlags) -> &'static str {
+    if proc_flags.contains(ProcFlags::REPL) {
+        "repl"
+    } else if proc_flags.contains(ProcFlags::EXPR) {
+        "expr"
+    } else if proc_flags.contains(ProcFlags::LOOP) {
+        "loop"
+    } else if proc_flags.contains(ProcFlags::STDIN) {
+        "stdin"
+    } else if proc_flags.contains(ProcFlags::EDIT) {
+        "edit"
+    } else {
+        "script"
+    }
+}
+
+/// Parse the contents of a `.env` file into name-value pairs. Each line is either blank,
+/// a `#` comment, or `NAME=VALUE`, optionally preceded by `export`. A value may be
+/// enclosed in single or double quotes, which are removed.
+/// # Errors
+/// Will return `Err` if a line is not in one of these forms.
+pub fn parse_env_file(contents: &str) -> Result<Vec<(String, String)>, ThagError> {
+    profile_fn!(parse_env_file);
+    let mut vars = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(format!(
+                "Invalid line {} in {ENV_FILE_NAME} file: expected NAME=VALUE",
+                index + 1
+            )
+            .into());
+        };
+        let value = value.trim();
+        let value = ['"', '\'']
+            .into_iter()
+            .find_map(|quote| {
+                value
+                    .strip_prefix(quote)
+                    .and_then(|value| value.strip_suffix(quote))
+            })
+            .unwrap_or(value);
+        vars.push((name.trim().to_string(), value.to_string()));
+    }
+    Ok(vars)
+}
+
+/// Determine the environment variables to run the program with: those from any `.env`
+/// file beside a script, other than any already set, followed by those telling the
+/// program about its script and how `thag` was invoked.
+/// # Errors
+/// Will return `Err` if there is an error reading or parsing the `.env` file.
+pub fn script_env(
+    proc_flags: &ProcFlags,
+    build_state: &BuildState,
+) -> Result<Vec<(String, String)>, ThagError> {
+    profile_fn!(script_env);
+    let mode = invocation_mode(proc_flags);
+    let mut vars = Vec::new();
+    if mode == "script" {
+        let env_file = build_state.source_dir_path.join(ENV_FILE_NAME);
+        if env_file.is_file() {
+            debug_log!("Loading environment variables from {env_file:?}");
+            vars.extend(
+                parse_env_file(&fs::read_to_string(&env_file)?)?
+                    .into_iter()
+                    .filter(|(name, _)| std::env::var_os(name).is_none()),
+            );
+        }
+        vars.push((
+            SCRIPT_PATH_VAR.to_string(),
+            path_string(&build_state.source_path),
+        ));
+        vars.push((
+            SCRIPT_DIR_VAR.to_string(),
+            path_string(&build_state.source_dir_path),
+        ));
+    }
+    vars.push((VERSION_VAR.to_string(), VERSION.to_string()));
+    vars.push((MODE_VAR.to_string(), mode.to_string()));
+    Ok(vars)
+}
+
+fn path_string(path: &Path) -> String {
+    path.display().to_string()
+}
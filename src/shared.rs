@@ -1,10 +1,15 @@
 #![allow(clippy::uninlined_format_args)]
 use crate::cmd_args::{Cli, ProcFlags};
+use crate::code_utils::{read_file_contents, result_format, wrapper_template, OutputMode};
 use crate::errors::ThagError;
+use crate::events::{emit, Event};
 use crate::logging::Verbosity;
 use crate::modified_since_compiled;
+use crate::source_map::{SourceMap, SourceSegment};
 use crate::DYNAMIC_SUBDIR;
+use crate::OUTPUT_OPTIONS_NAME;
 use crate::REPL_SUBDIR;
+use crate::RESULT_FILE_NAME;
 use crate::RS_SUFFIX;
 use crate::TEMP_SCRIPT_NAME;
 use crate::TMPDIR;
@@ -18,6 +23,8 @@ use home::home_dir;
 use proc_macro2::TokenStream;
 use quote::ToTokens;
 use std::{
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
     path::{Path, PathBuf},
     time::Instant,
 };
@@ -93,6 +100,10 @@ pub struct BuildState {
     pub must_gen: bool,
     pub must_build: bool,
     pub build_from_orig_source: bool,
+    pub source_segments: Vec<SourceSegment>,
+    pub source_map: Option<SourceMap>,
+    pub result_file: Option<PathBuf>,
+    pub output_options: String,
 }
 
 impl BuildState {
@@ -210,6 +221,7 @@ impl BuildState {
         let source_stem = { source_stem.to_string() };
         let source_name = source_name.to_string();
 
+        let output_options = output_options(proc_flags, args, &source_path);
         let mut build_state = Self {
             working_dir_path,
             source_stem,
@@ -220,8 +232,13 @@ impl BuildState {
             target_dir_path,
             target_path,
             cargo_toml_path,
+            output_options,
             ..Default::default()
         };
+        // A snippet's result is passed back in this file to be rendered. This doesn't depend
+        // on whether the program is generated in this run, since the run needs it regardless.
+        build_state.result_file = (args.output == OutputMode::Rich && !is_loop)
+            .then(|| build_state.target_dir_path.join(RESULT_FILE_NAME));
 
         let force = proc_flags.contains(ProcFlags::FORCE);
         (build_state.must_gen, build_state.must_build) = if force {
@@ -229,7 +246,8 @@ impl BuildState {
         } else {
             let stale_executable = matches!(script_state, ScriptState::NamedEmpty { .. })
                 || !target_path_exists
-                || modified_since_compiled(&build_state)?.is_some();
+                || modified_since_compiled(&build_state)?.is_some()
+                || output_options_changed(&build_state);
             let gen_requested = proc_flags.contains(ProcFlags::GENERATE);
             let build_requested = proc_flags.intersects(ProcFlags::BUILD | ProcFlags::CHECK);
             let must_gen =
@@ -250,6 +268,35 @@ impl BuildState {
     }
 }
 
+/// Describe the options that shape the output of a generated program, so that they can
+/// be recorded with it. The wrapper template is resolved as for generation, so that a change
+/// to its text is caught as well as one to its name.
+fn output_options(proc_flags: &ProcFlags, args: &Cli, source_path: &Path) -> String {
+    let template_hash = read_file_contents(source_path)
+        .ok()
+        .and_then(|rs_source| wrapper_template(args, &rs_source).ok())
+        .map(|template| {
+            let mut hasher = DefaultHasher::new();
+            template.hash(&mut hasher);
+            hasher.finish()
+        });
+    format!(
+        "format={:?}\noutput={:?}\nunquote={}\ntemplate={:?}\ntemplate_hash={:?}\n",
+        result_format(args),
+        args.output,
+        proc_flags.contains(ProcFlags::UNQUOTE),
+        args.template,
+        template_hash
+    )
+}
+
+/// Whether the program was generated with different output options from those now in
+/// force, in which case it must be generated again.
+fn output_options_changed(build_state: &BuildState) -> bool {
+    fs::read_to_string(build_state.target_dir_path.join(OUTPUT_OPTIONS_NAME))
+        .map_or(true, |recorded| recorded != build_state.output_options)
+}
+
 /// An enum to encapsulate the type of script in play.
 #[derive(Debug)]
 pub enum ScriptState {
@@ -311,6 +358,10 @@ pub fn display_timings(start: &Instant, process: &str, proc_flags: &ProcFlags) {
     let msg = format!("{process} in {}.{}s", dur.as_secs(), dur.subsec_millis());
 
     debug_log!("{msg}");
+    emit(&Event::Timing {
+        process,
+        duration_ms: dur.as_millis(),
+    });
     if proc_flags.intersects(ProcFlags::DEBUG | ProcFlags::VERBOSE | ProcFlags::TIMINGS) {
         log!(Verbosity::Quieter, "{msg}");
     }
@@ -0,0 +1,459 @@
+use firestorm::profile_fn;
+use lazy_static::lazy_static;
+use proc_macro2::{Delimiter, LineColumn, TokenStream, TokenTree};
+use regex::{Captures, Regex};
+use serde_json::{json, Value};
+use std::path::Path;
+use std::str::FromStr;
+
+/// A piece of the user's original input, such as a script, an expression or a loop filter,
+/// that finds its way into the generated source.
+#[derive(Clone, Debug, Default)]
+pub struct SourceSegment {
+    /// The name to report in diagnostics: a script path or a label such as `<expr>`.
+    pub name: String,
+    /// The text as entered by the user.
+    pub text: String,
+    /// The line number of the first line of `text` within the named source.
+    pub first_line: usize,
+}
+
+impl SourceSegment {
+    /// Create a segment whose text starts at line 1 of the named source.
+    #[must_use]
+    pub fn new(name: &str, text: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            text: text.to_string(),
+            first_line: 1,
+        }
+    }
+}
+
+/// A position in the user's original input. Lines and columns are 1-based, as in
+/// rustc diagnostics.
+#[derive(Debug, PartialEq, Eq)]
+pub struct OriginalLocation<'a> {
+    pub name: &'a str,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    start: LineColumn,
+    end: LineColumn,
+}
+
+#[derive(Clone, Debug)]
+struct Mapping {
+    generated_start: LineColumn,
+    generated_end: LineColumn,
+    segment: usize,
+    // Adjusted for the first line of the segment
+    original: LineColumn,
+}
+
+/// Maps positions in a generated source file back to the segments of the user's input
+/// that they came from. The tokens of the two are matched by diffing, so the mapping
+/// survives the wrapping and reformatting that take place during generation.
+#[derive(Clone, Debug, Default)]
+pub struct SourceMap {
+    generated_name: String,
+    segments: Vec<SourceSegment>,
+    // Sorted by generated_start
+    mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    /// Record the source map for the generated source file of the given name.
+    #[must_use]
+    pub fn new(segments: &[SourceSegment], generated_name: &str, generated: &str) -> Self {
+        profile_fn!(new);
+        let original: Vec<(usize, Token)> = segments
+            .iter()
+            .enumerate()
+            .flat_map(|(index, segment)| {
+                tokenize(&segment.text)
+                    .into_iter()
+                    .map(move |token| (index, token))
+            })
+            .collect();
+        let generated = tokenize(generated);
+
+        let original_text: Vec<&str> = original.iter().map(|(_, t)| t.text.as_str()).collect();
+        let generated_text: Vec<&str> = generated.iter().map(|t| t.text.as_str()).collect();
+
+        let mut mappings: Vec<Mapping> = matching_pairs(&original_text, &generated_text)
+            .into_iter()
+            .map(|(orig, gen)| {
+                let (segment, ref token) = original[orig];
+                Mapping {
+                    generated_start: generated[gen].start,
+                    generated_end: generated[gen].end,
+                    segment,
+                    original: LineColumn {
+                        line: token.start.line + segments[segment].first_line - 1,
+                        column: token.start.column,
+                    },
+                }
+            })
+            .collect();
+        mappings.sort_by_key(|mapping| mapping.generated_start);
+
+        Self {
+            generated_name: generated_name.to_string(),
+            segments: segments.to_vec(),
+            mappings,
+        }
+    }
+
+    /// Find where a position in the generated source came from, given its 1-based line and
+    /// column. A position in generated code is attributed to the nearest original token on
+    /// the same line, if any, otherwise the result is `None`.
+    #[must_use]
+    pub fn lookup(&self, line: usize, column: usize) -> Option<OriginalLocation<'_>> {
+        let position = LineColumn {
+            line,
+            column: column.saturating_sub(1),
+        };
+        let index = self
+            .mappings
+            .partition_point(|mapping| mapping.generated_start <= position);
+        let preceding = index.checked_sub(1).map(|i| &self.mappings[i]);
+
+        if let Some(mapping) = preceding.filter(|mapping| position < mapping.generated_end) {
+            let offset = if mapping.generated_start.line == line {
+                position.column - mapping.generated_start.column
+            } else {
+                0
+            };
+            return Some(self.location(mapping, mapping.original.column + offset));
+        }
+
+        let mapping = self
+            .mappings
+            .get(index)
+            .filter(|mapping| mapping.generated_start.line == line)
+            .or_else(|| preceding.filter(|mapping| mapping.generated_end.line == line))?;
+        Some(self.location(mapping, mapping.original.column))
+    }
+
+    // Find where the exclusive end of a span in the generated source maps to. An end that
+    // falls in generated code or whitespace is taken to be the end of the preceding original
+    // token on the same line.
+    fn lookup_end(&self, line: usize, column: usize) -> Option<OriginalLocation<'_>> {
+        let position = LineColumn {
+            line,
+            column: column.saturating_sub(1),
+        };
+        let index = self
+            .mappings
+            .partition_point(|mapping| mapping.generated_start < position);
+        let mapping = index
+            .checked_sub(1)
+            .map(|i| &self.mappings[i])
+            .filter(|mapping| mapping.generated_start.line == line)?;
+        let length = if mapping.generated_end.line == line {
+            mapping.generated_end.column - mapping.generated_start.column
+        } else {
+            0
+        };
+        let offset = (position.column - mapping.generated_start.column).min(length);
+        Some(self.location(mapping, mapping.original.column + offset))
+    }
+
+    fn location(&self, mapping: &Mapping, column: usize) -> OriginalLocation<'_> {
+        OriginalLocation {
+            name: &self.segments[mapping.segment].name,
+            line: mapping.original.line,
+            column: column + 1,
+        }
+    }
+
+    /// Rewrite a rustc diagnostic, in the form of the `message` object of a Cargo
+    /// `compiler-message` record, to refer to the original input wherever possible.
+    pub fn rewrite_diagnostic(&self, message: &mut Value) {
+        profile_fn!(rewrite_diagnostic);
+        if let Some(spans) = message.get_mut("spans").and_then(Value::as_array_mut) {
+            for span in spans {
+                self.rewrite_span(span);
+            }
+        }
+        if let Some(children) = message.get_mut("children").and_then(Value::as_array_mut) {
+            for child in children {
+                self.rewrite_diagnostic(child);
+            }
+        }
+        if let Some(rendered) = message.get("rendered").and_then(Value::as_str) {
+            let rendered = self.rewrite_rendered(rendered);
+            message["rendered"] = Value::String(rendered);
+        }
+    }
+
+    fn is_generated(&self, file_name: &str) -> bool {
+        Path::new(file_name)
+            .file_name()
+            .is_some_and(|name| name == self.generated_name.as_str())
+    }
+
+    fn rewrite_span(&self, span: &mut Value) {
+        if !span
+            .get("file_name")
+            .and_then(Value::as_str)
+            .is_some_and(|file_name| self.is_generated(file_name))
+        {
+            return;
+        }
+        let number = |key: &str| {
+            span.get(key)
+                .and_then(Value::as_u64)
+                .and_then(|n| usize::try_from(n).ok())
+        };
+        let (Some(line_start), Some(column_start), Some(line_end), Some(column_end)) = (
+            number("line_start"),
+            number("column_start"),
+            number("line_end"),
+            number("column_end"),
+        ) else {
+            return;
+        };
+        let Some(start) = self.lookup(line_start, column_start) else {
+            return;
+        };
+        // Keep insertion points, as used by suggestions, zero-width
+        let (line_end, column_end) = if (line_start, column_start) == (line_end, column_end) {
+            (start.line, start.column)
+        } else {
+            self.lookup_end(line_end, column_end)
+                .filter(|end| {
+                    end.name == start.name && (end.line, end.column) > (start.line, start.column)
+                })
+                .map_or((start.line, start.column + 1), |end| (end.line, end.column))
+        };
+
+        let text: Vec<Value> = (start.line..=line_end)
+            .filter_map(|line| {
+                let text = self.original_line(start.name, line)?;
+                let highlight_start = if line == start.line { start.column } else { 1 };
+                let highlight_end = if line == line_end {
+                    column_end
+                } else {
+                    text.chars().count() + 1
+                };
+                Some(json!({
+                    "text": text,
+                    "highlight_start": highlight_start,
+                    "highlight_end": highlight_end,
+                }))
+            })
+            .collect();
+
+        span["file_name"] = Value::from(start.name);
+        span["line_start"] = Value::from(start.line);
+        span["column_start"] = Value::from(start.column);
+        span["line_end"] = Value::from(line_end);
+        span["column_end"] = Value::from(column_end);
+        span["text"] = Value::Array(text);
+    }
+
+    /// The text of a line of the named original source, if it is covered by a segment.
+    #[must_use]
+    pub fn original_line(&self, name: &str, line: usize) -> Option<&str> {
+        self.segments
+            .iter()
+            .filter(|segment| segment.name == name && line >= segment.first_line)
+            .find_map(|segment| segment.text.lines().nth(line - segment.first_line))
+    }
+
+    // Rewrite the `-->` and `:::` location lines of rendered diagnostic text.
+    fn rewrite_rendered(&self, rendered: &str) -> String {
+        lazy_static! {
+            static ref LOCATION_RE: Regex =
+                Regex::new(r"((?:-->|:::)\s+(?:\x1b\[[0-9;]*m)*)([^\s\x1b]+):(\d+):(\d+)").unwrap();
+        }
+        LOCATION_RE
+            .replace_all(rendered, |caps: &Captures| {
+                let location = if self.is_generated(&caps[2]) {
+                    caps[3]
+                        .parse()
+                        .ok()
+                        .zip(caps[4].parse().ok())
+                        .and_then(|(line, column)| self.lookup(line, column))
+                } else {
+                    None
+                };
+                location.map_or_else(
+                    || caps[0].to_string(),
+                    |location| {
+                        format!(
+                            "{}{}:{}:{}",
+                            &caps[1], location.name, location.line, location.column
+                        )
+                    },
+                )
+            })
+            .into_owned()
+    }
+}
+
+// Flatten source code into its tokens, including delimiters, with their positions.
+// Source that fails to tokenize contributes no tokens and so goes unmapped.
+fn tokenize(source: &str) -> Vec<Token> {
+    fn flatten(stream: TokenStream, tokens: &mut Vec<Token>) {
+        for tree in stream {
+            if let TokenTree::Group(group) = tree {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::None => ("", ""),
+                };
+                let delimited = !open.is_empty();
+                if delimited {
+                    let span = group.span_open();
+                    tokens.push(Token {
+                        text: open.to_string(),
+                        start: span.start(),
+                        end: span.end(),
+                    });
+                }
+                flatten(group.stream(), tokens);
+                if delimited {
+                    let span = group.span_close();
+                    tokens.push(Token {
+                        text: close.to_string(),
+                        start: span.start(),
+                        end: span.end(),
+                    });
+                }
+            } else {
+                let span = tree.span();
+                tokens.push(Token {
+                    text: tree.to_string(),
+                    start: span.start(),
+                    end: span.end(),
+                });
+            }
+        }
+    }
+
+    let mut tokens = Vec::new();
+    if let Ok(stream) = TokenStream::from_str(source) {
+        flatten(stream, &mut tokens);
+    }
+    tokens
+}
+
+// Find the index pairs of the matching elements of a longest common subsequence of `a`
+// and `b`, using the linear-space variant of Myers' diff algorithm. This is efficient when
+// the differences are few, as they are between a snippet and the code generated from it,
+// or between a script before and after fixes, and needs memory only in proportion to the
+// lengths, however many the differences.
+pub(crate) fn matching_pairs(a: &[&str], b: &[&str]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    push_matching_pairs(a, b, (0, 0), &mut pairs);
+    pairs
+}
+
+// Add the matching pairs of `a` and `b`, which start at the given indices of the whole
+// sequences, by splitting them either side of a middle snake of an optimal edit path, and
+// recursing on the parts before and after it.
+fn push_matching_pairs(
+    a: &[&str],
+    b: &[&str],
+    (a_start, b_start): (usize, usize),
+    pairs: &mut Vec<(usize, usize)>,
+) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    pairs.extend((0..prefix).map(|i| (a_start + i, b_start + i)));
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let (a_start, b_start) = (a_start + prefix, b_start + prefix);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_len, b_len) = (a.len() - suffix, b.len() - suffix);
+
+    // With no common prefix or suffix, an optimal path takes at least two edits, so the
+    // parts either side of the middle snake are both smaller than the whole
+    if a_len > 0 && b_len > 0 {
+        let (x, y, u, v) = middle_snake(&a[..a_len], &b[..b_len]);
+        push_matching_pairs(&a[..x], &b[..y], (a_start, b_start), pairs);
+        pairs.extend((0..u - x).map(|i| (a_start + x + i, b_start + y + i)));
+        push_matching_pairs(
+            &a[u..a_len],
+            &b[v..b_len],
+            (a_start + u, b_start + v),
+            pairs,
+        );
+    }
+    pairs.extend((0..suffix).map(|i| (a_start + a_len + i, b_start + b_len + i)));
+}
+
+// Find the start `(x, y)` and end `(u, v)` of the snake in the middle of an optimal edit
+// path from `a` to `b`, by searching forwards from the start and backwards from the end
+// until the paths overlap. Each search only keeps the furthest x reached on each diagonal.
+#[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+fn middle_snake(a: &[&str], b: &[&str]) -> (usize, usize, usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    let index = |k: isize| (k + offset) as usize;
+    // The furthest x reached on each diagonal, going forwards from (0, 0) and, in reversed
+    // coordinates, backwards from (n, m)
+    let mut forward = vec![0_isize; 2 * offset as usize + 1];
+    let mut backward = forward.clone();
+    let furthest = |v: &[isize], k: isize, d: isize| {
+        if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+            v[index(k + 1)]
+        } else {
+            v[index(k - 1)] + 1
+        }
+    };
+
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let start_x = furthest(&forward, k, d);
+            let (mut x, mut y) = (start_x, start_x - k);
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+            let c = delta - k;
+            if odd && (1 - d..d).contains(&c) && x + backward[index(c)] >= n {
+                return (
+                    start_x as usize,
+                    (start_x - k) as usize,
+                    x as usize,
+                    y as usize,
+                );
+            }
+        }
+        for c in (-d..=d).step_by(2) {
+            let start_x = furthest(&backward, c, d);
+            let (mut x, mut y) = (start_x, start_x - c);
+            while x < n && y < m && a[(n - 1 - x) as usize] == b[(m - 1 - y) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[index(c)] = x;
+            let k = delta - c;
+            if !odd && (-d..=d).contains(&k) && x + forward[index(k)] >= n {
+                return (
+                    (n - x) as usize,
+                    (m - y) as usize,
+                    (n - start_x) as usize,
+                    (m - start_x + c) as usize,
+                );
+            }
+        }
+    }
+    unreachable!("The forward and backward paths overlap within (n + m) / 2 steps")
+}
@@ -1,5 +1,6 @@
 #![allow(clippy::uninlined_format_args)]
 use crate::errors::ThagError;
+use crate::highlight::{ratatui_style, token_style, tokenize_lines};
 use crate::log;
 use crate::logging::Verbosity;
 
@@ -16,11 +17,13 @@ use crossterm::terminal::{
 use lazy_static::lazy_static;
 use mockall::{automock, predicate::str};
 use ratatui::backend::CrosstermBackend;
+use ratatui::buffer::Buffer;
 use ratatui::layout::{Alignment, Constraint, Direction, Layout, Margin};
 use ratatui::prelude::Rect;
 use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::Span;
 use ratatui::widgets::block::{Block, Title};
-use ratatui::widgets::{Borders, Clear, Paragraph};
+use ratatui::widgets::{Borders, Clear, Paragraph, Widget};
 use ratatui::Terminal;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
@@ -205,21 +208,22 @@ pub fn edit<R: EventReader>(event_reader: &R) -> Result<Vec<String>, ThagError>
     textarea.move_cursor(CursorMove::Bottom);
 
     apply_highlights(alt_highlights, &mut textarea);
+    let mut view = EditorView::default();
 
     loop {
         term.draw(|f| {
-            f.render_widget(&textarea, f.area());
+            view.render(&mut textarea, f.area(), f.buffer_mut());
             if popup {
                 show_popup(f);
             }
             apply_highlights(alt_highlights, &mut textarea);
         })
         .map_err(|e| {
-            println!("Error drawing terminal: {:?}", e);
+            eprintln!("Error drawing terminal: {:?}", e);
             e
         })?;
         let event = event_reader.read_event().map_err(|e| {
-            println!("Error reading event: {:?}", e);
+            eprintln!("Error reading event: {:?}", e);
             e
         })?;
         if let Paste(ref data) = event {
@@ -295,9 +299,7 @@ pub fn edit<R: EventReader>(event_reader: &R) -> Result<Vec<String>, ThagError>
                         textarea.insert_str(entry);
                     }
                 }
-                input => {
-                    textarea.input(input);
-                }
+                input => view.input(&mut textarea, input),
             }
         }
     }
@@ -373,6 +375,199 @@ pub fn apply_highlights(alt_highlights: bool, textarea: &mut TextArea) {
     }
 }
 
+/// The view of a text area being edited, which is rendered in place of the text area itself
+/// so as to highlight Rust syntax, since `tui-textarea` has no way to style spans of the
+/// text. The view keeps its own scroll position, which follows the cursor and is moved by
+/// the scrolling keys and the mouse wheel, so all input should be passed through `input`.
+#[derive(Debug, Default)]
+pub struct EditorView {
+    top_row: usize,
+    top_col: usize,
+    height: usize,
+}
+
+impl EditorView {
+    /// Apply an input to the text area, except for scrolling, which the view does itself.
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn input(&mut self, textarea: &mut TextArea, input: Input) {
+        let page = self.height as isize;
+        let rows = match input {
+            Input {
+                key: Key::PageDown, ..
+            }
+            | Input {
+                key: Key::Char('v'),
+                ctrl: true,
+                alt: false,
+                ..
+            } => page,
+            Input {
+                key: Key::PageUp, ..
+            }
+            | Input {
+                key: Key::Char('v'),
+                ctrl: false,
+                alt: true,
+                ..
+            } => -page,
+            Input {
+                key: Key::MouseScrollDown,
+                ..
+            } => 1,
+            Input {
+                key: Key::MouseScrollUp,
+                ..
+            } => -1,
+            input => {
+                textarea.input(input);
+                return;
+            }
+        };
+        // As for other cursor movements, Shift extends the selection
+        if !input.shift {
+            textarea.cancel_selection();
+        } else if !textarea.is_selecting() {
+            textarea.start_selection();
+        }
+        self.scroll(textarea, rows);
+    }
+
+    /// Scroll the view by the given number of rows, moving the cursor as little as needed
+    /// to keep it in view.
+    pub fn scroll(&mut self, textarea: &mut TextArea, rows: isize) {
+        let last_row = textarea.lines().len().saturating_sub(1);
+        self.top_row = self.top_row.saturating_add_signed(rows).min(last_row);
+        let bottom_row = (self.top_row + self.height.saturating_sub(1)).min(last_row);
+        let (row, col) = textarea.cursor();
+        let row_in_view = row.clamp(self.top_row, bottom_row);
+        if row_in_view != row {
+            let to_u16 = |n: usize| u16::try_from(n).unwrap_or(u16::MAX);
+            textarea.move_cursor(CursorMove::Jump(to_u16(row_in_view), to_u16(col)));
+        }
+    }
+
+    /// Render the text area's block and the part of its text in view to `area` of the buffer,
+    /// first scrolling as little as needed to bring the cursor into view. The text area is
+    /// only borrowed mutably because `tui-textarea` requires it to read the selection style.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn render(&mut self, textarea: &mut TextArea, area: Rect, buf: &mut Buffer) {
+        let inner = textarea.block().map_or(area, |block| {
+            block.render(area, buf);
+            block.inner(area)
+        });
+        let base_style = textarea.style();
+        buf.set_style(inner, base_style);
+        let selection_style = base_style.patch(textarea.selection_style());
+        let cursor_style = base_style.patch(textarea.cursor_style());
+        let cursor_line_style = base_style.patch(textarea.cursor_line_style());
+        let line_number_style = textarea.line_number_style();
+
+        let lines = textarea.lines();
+        let tab_len = textarea.tab_length();
+        let gutter = line_number_style.map_or(0, |_| lines.len().to_string().len() + 2);
+        let width = usize::from(inner.width).saturating_sub(gutter);
+        let cursor = textarea.cursor();
+        let cursor_x = lines.get(cursor.0).map_or(0, |line| {
+            let end = line
+                .char_indices()
+                .nth(cursor.1)
+                .map_or(line.len(), |(i, _)| i);
+            display_width(&line[..end], tab_len)
+        });
+        self.height = usize::from(inner.height);
+        self.top_row = scroll_to(self.top_row, cursor.0, self.height);
+        self.top_col = scroll_to(self.top_col, cursor_x, width);
+
+        let selection = textarea.selection_range();
+        let is_selected =
+            |position| selection.is_some_and(|(start, end)| start <= position && position < end);
+        let screen_x = |x: usize| inner.x + (gutter + x - self.top_col) as u16;
+        let line_tokens = tokenize_lines(lines);
+        for (row, (line, tokens)) in lines
+            .iter()
+            .zip(&line_tokens)
+            .enumerate()
+            .skip(self.top_row)
+            .take(self.height)
+        {
+            let y = inner.y + (row - self.top_row) as u16;
+            if let Some(style) = line_number_style {
+                let number = format!("{:>1$} ", row + 1, gutter - 1);
+                buf.set_stringn(inner.x, y, number, gutter, style);
+            }
+
+            let line_style = if row == cursor.0 {
+                cursor_line_style
+            } else {
+                base_style
+            };
+            let mut styles = vec![line_style; line.chars().count()];
+            for (range, kind) in tokens {
+                let token_style = ratatui_style(token_style(*kind));
+                let first_col = line[..range.start].chars().count();
+                let len = line[range.clone()].chars().count();
+                for style in styles.iter_mut().skip(first_col).take(len) {
+                    *style = style.patch(token_style);
+                }
+            }
+
+            // A cell past the end of the line shows the cursor there, or a selection that
+            // runs on to the next line
+            let mut x = 0;
+            for (col, c) in line.chars().chain([' ']).enumerate() {
+                let style = if (row, col) == cursor {
+                    cursor_style
+                } else if is_selected((row, col)) {
+                    selection_style
+                } else if let Some(style) = styles.get(col) {
+                    *style
+                } else {
+                    break;
+                };
+                let end = x + char_width(c, x, tab_len);
+                let (first, last) = (x.max(self.top_col), end.min(self.top_col + width));
+                if c != '\t' && first == x && last == end && first < last {
+                    buf.set_string(screen_x(x), y, c.encode_utf8(&mut [0; 4]), style);
+                } else {
+                    // Tabs, and wide characters cut off by the edge of the view
+                    for x in first..last {
+                        buf[(screen_x(x), y)].set_symbol(" ").set_style(style);
+                    }
+                }
+                x = end;
+            }
+        }
+    }
+}
+
+// The first row or column of a view of the given size that brings the cursor's row or
+// column into view, moving as little as possible from the current one.
+fn scroll_to(top: usize, cursor: usize, size: usize) -> usize {
+    if cursor < top {
+        cursor
+    } else if size > 0 && top + size <= cursor {
+        cursor + 1 - size
+    } else {
+        top
+    }
+}
+
+// The width of text as displayed in the editor, which expands tabs to the next tab stop.
+fn display_width(text: &str, tab_len: u8) -> usize {
+    text.chars()
+        .fold(0, |width, c| width + char_width(c, width, tab_len))
+}
+
+// The display width of a character following text of the given width.
+fn char_width(c: char, width: usize, tab_len: u8) -> usize {
+    if c == '\t' {
+        let tab_len = usize::from(tab_len);
+        tab_len.saturating_sub(width.checked_rem(tab_len).unwrap_or(0))
+    } else {
+        Span::raw(c.encode_utf8(&mut [0; 4]) as &str).width()
+    }
+}
+
 fn reset_term(mut term: Terminal<CrosstermBackend<io::StdoutLock<'_>>>) -> Result<(), ThagError> {
     disable_raw_mode()?;
     crossterm::execute!(
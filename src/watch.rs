@@ -0,0 +1,247 @@
+use cargo_toml::Dependency;
+use crossterm::{
+    cursor::MoveTo,
+    execute,
+    terminal::{Clear, ClearType},
+};
+use firestorm::profile_fn;
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus};
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::builder::{gen_build_run, handle_signals};
+use crate::cmd_args::{Cli, ProcFlags};
+use crate::code_utils::{extract_manifest, find_modules_source, read_file_contents};
+use crate::colors::{nu_resolve_style, MessageLevel};
+use crate::errors::ThagError;
+use crate::logging::Verbosity;
+use crate::shared::{Ast, BuildState, ScriptState};
+use crate::{debug_log, log, RS_SUFFIX};
+
+/// How often to check the watched files for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long the watched files must stay unchanged after a change before rerunning, so
+/// that a burst of saves only causes one rerun.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+lazy_static! {
+    // The files being watched and their modification times when last checked
+    static ref SNAPSHOT: Mutex<Option<Snapshot>> = Mutex::new(None);
+}
+
+/// The modification times of a set of files, or `None` for any that don't exist.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot(BTreeMap<PathBuf, Option<SystemTime>>);
+
+impl Snapshot {
+    /// Record the current modification times of the given files.
+    #[must_use]
+    pub fn take(paths: &[PathBuf]) -> Self {
+        Self(
+            paths
+                .iter()
+                .map(|path| {
+                    let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+                    (path.clone(), modified)
+                })
+                .collect(),
+        )
+    }
+
+    /// Take a fresh snapshot of the same files.
+    #[must_use]
+    pub fn retake(&self) -> Self {
+        Self::take(&self.0.keys().cloned().collect::<Vec<_>>())
+    }
+
+    /// Identify the first file that has changed since the snapshot was taken, if any.
+    #[must_use]
+    pub fn first_change(&self) -> Option<PathBuf> {
+        let current = self.retake();
+        self.0
+            .iter()
+            .find(|(path, modified)| current.0.get(*path) != Some(modified))
+            .map(|(path, _)| path.clone())
+    }
+}
+
+/// Determine whether `thag` is in watch mode.
+#[must_use]
+pub fn is_watching() -> bool {
+    SNAPSHOT.lock().is_ok_and(|snapshot| snapshot.is_some())
+}
+
+/// End watch mode, as when `thag` is interrupted while the script is running. The
+/// current run is allowed to finish.
+pub fn stop() {
+    if let Ok(mut snapshot) = SNAPSHOT.lock() {
+        *snapshot = None;
+    }
+}
+
+/// Identify a watched file that has changed since the current run started, if any.
+#[must_use]
+pub fn changed_file() -> Option<PathBuf> {
+    SNAPSHOT
+        .lock()
+        .ok()
+        .and_then(|snapshot| snapshot.as_ref().and_then(Snapshot::first_change))
+}
+
+/// Wait for the script to finish, unless a watched file changes first, in which case
+/// kill the script and return `None` so that it can be rebuilt and rerun.
+/// # Errors
+/// Will return `Err` if there is an error waiting for or killing the script.
+pub fn wait_unless_changed(child: &mut Child) -> Result<Option<ExitStatus>, ThagError> {
+    profile_fn!(wait_unless_changed);
+    loop {
+        if let Some(exit_status) = child.try_wait()? {
+            return Ok(Some(exit_status));
+        }
+        if changed_file().is_some() {
+            debug_log!("Killing script process {} after a change", child.id());
+            // It may have finished in the meantime
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(None);
+        }
+        sleep(POLL_INTERVAL);
+    }
+}
+
+/// Identify the files to watch for a script: the script itself, any sibling module files
+/// that it declares with `mod`, and the source files of any path dependencies in its toml
+/// block.
+#[must_use]
+pub fn watched_files(script_path: &Path) -> Vec<PathBuf> {
+    profile_fn!(watched_files);
+    let mut paths = vec![script_path.to_path_buf()];
+    let Some(script_dir) = script_path.parent() else {
+        return paths;
+    };
+    let Ok(rs_source) = read_file_contents(script_path) else {
+        return paths;
+    };
+
+    for module in find_modules_source(&rs_source) {
+        let module_file = script_dir.join(format!("{module}{RS_SUFFIX}"));
+        let module_dir = script_dir.join(&module);
+        if module_dir.is_dir() {
+            collect_files(&module_dir, &mut paths);
+        }
+        // Watch the module file even if it doesn't exist yet
+        paths.push(module_file);
+    }
+
+    if let Ok(rs_manifest) = extract_manifest(&rs_source, Instant::now()) {
+        for dependency in rs_manifest.dependencies.values() {
+            if let Dependency::Detailed(detail) = dependency {
+                if let Some(ref path) = detail.path {
+                    collect_files(&script_dir.join(path), &mut paths);
+                }
+            }
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+// Collect the Rust source and Cargo.toml files in a directory tree, skipping any target
+// directories and hidden directories.
+fn collect_files(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if path.is_dir() {
+            if name != "target" && !name.starts_with('.') {
+                collect_files(&path, paths);
+            }
+        } else if name.ends_with(RS_SUFFIX) || name == "Cargo.toml" {
+            paths.push(path);
+        }
+    }
+}
+
+/// Generate, build and run the script, then do so again every time it or one of the
+/// files it depends on changes, until interrupted. A change while the script is still
+/// running kills it. Errors are reported but don't stop the watching. SIGINT or SIGTERM
+/// while the script is running is left to the script and then ends the watch, and at any
+/// other time stops `thag` straight away.
+/// # Errors
+/// Will return `Err` if there is an error setting up the build state or clearing the screen,
+/// or with the outcome of the last run if the watch was ended by a signal.
+pub fn watch(
+    args: &Cli,
+    proc_flags: &ProcFlags,
+    script_state: &ScriptState,
+    mut build_state: BuildState,
+    start: &Instant,
+) -> Result<(), ThagError> {
+    profile_fn!(watch);
+    handle_signals()?;
+    loop {
+        let paths = watched_files(&build_state.source_path);
+        debug_log!("Watching {paths:#?}");
+        let snapshot = Snapshot::take(&paths);
+        *SNAPSHOT.lock()? = Some(snapshot.clone());
+
+        let result = gen_build_run(args, proc_flags, &mut build_state, None::<Ast>, start);
+        if !is_watching() {
+            return result;
+        }
+        match result {
+            Ok(()) | Err(ThagError::ScriptExit(_)) => (),
+            Err(err) => log!(Verbosity::Quieter, "{err}"),
+        }
+
+        let changed = wait_for_change(&snapshot);
+        if args.clear {
+            execute!(io::stdout(), Clear(ClearType::All), MoveTo(0, 0))?;
+        }
+        log!(
+            Verbosity::Quiet,
+            "{}",
+            nu_resolve_style(MessageLevel::Emphasis).paint(format!(
+                "[watch] {} changed: rebuilding and rerunning",
+                changed.display()
+            ))
+        );
+
+        build_state = BuildState::pre_configure(proc_flags, args, script_state)?;
+        build_state.must_gen = true;
+        build_state.must_build = true;
+    }
+}
+
+// Wait until one of the files in the snapshot changes, and then until the files stop
+// changing, returning the first file that changed.
+fn wait_for_change(snapshot: &Snapshot) -> PathBuf {
+    let changed = loop {
+        if let Some(changed) = snapshot.first_change() {
+            break changed;
+        }
+        sleep(POLL_INTERVAL);
+    };
+    let mut latest = snapshot.retake();
+    let mut stable_since = Instant::now();
+    while stable_since.elapsed() < DEBOUNCE {
+        sleep(POLL_INTERVAL);
+        let current = latest.retake();
+        if current != latest {
+            latest = current;
+            stable_since = Instant::now();
+        }
+    }
+    changed
+}
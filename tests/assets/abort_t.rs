@@ -0,0 +1,5 @@
+/// Test script that is terminated by a signal, namely SIGABRT.
+fn main() {
+    println!("Aborting");
+    std::process::abort();
+}
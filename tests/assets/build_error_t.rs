@@ -0,0 +1,5 @@
+/// Test script that deliberately fails to compile.
+fn main() {
+    let x: u32 = "not a number";
+    println!("{x}");
+}
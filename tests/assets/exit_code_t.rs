@@ -0,0 +1,5 @@
+/// Test script that terminates with a specific non-zero exit code.
+fn main() {
+    println!("Exiting with code 42");
+    std::process::exit(42);
+}
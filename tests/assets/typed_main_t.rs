@@ -0,0 +1,12 @@
+/// Repeat a word a number of times.
+fn main(
+    /// The word to repeat
+    word: String,
+    /// How many times to repeat it
+    count: usize,
+    /// Print it in upper case
+    upper: bool,
+) {
+    let word = if upper { word.to_uppercase() } else { word };
+    println!("{}", vec![word; count].join(" "));
+}
@@ -4,12 +4,15 @@ mod tests {
     use cargo_toml::Manifest;
     use thag_rs::builder::{build, generate, run};
     use thag_rs::cmd_args::Cli;
+    use thag_rs::errors::{EXIT_BUILD, EXIT_SIGNAL_BASE, EXIT_USAGE};
+    use thag_rs::events::strip_ansi;
     use thag_rs::{code_utils, escape_path_for_windows, execute, TMPDIR};
     use thag_rs::{BuildState, ProcFlags};
     // use sequential_test::sequential;
     use std::env::current_dir;
     use std::fs::{self, OpenOptions};
     use std::io::Write;
+    use std::process::{Command, Output};
 
     // Set environment variables before running tests
     fn set_up() {
@@ -62,6 +65,10 @@ mod tests {
             must_gen: true,
             must_build: true,
             build_from_orig_source: false,
+            source_segments: Vec::new(),
+            source_map: None,
+            result_file: None,
+            output_options: String::new(),
         }
     }
 
@@ -123,7 +130,7 @@ mod tests {
         let rs_source = code_utils::read_file_contents(&build_state.source_path)
             .expect("Error reading script contents");
         let proc_flags = ProcFlags::empty();
-        let result = generate(&build_state, Some(&rs_source), &proc_flags);
+        let result = generate(&mut build_state, Some(&rs_source), &proc_flags);
         assert!(result.is_ok());
         assert!(build_state.target_dir_path.join(script_name).exists());
         assert!(build_state.cargo_toml_path.exists());
@@ -210,6 +217,10 @@ name = "bitflags_t"
             must_gen: true,
             must_build: true,
             build_from_orig_source: false,
+            source_segments: Vec::new(),
+            source_map: None,
+            result_file: None,
+            output_options: String::new(),
         };
         dbg!(&build_state);
         let proc_flags = ProcFlags::empty();
@@ -257,4 +268,675 @@ name = "bitflags_t"
         let result = run(&proc_flags, &cli.args, &build_state);
         assert!(result.is_ok());
     }
+
+    // Run the thag binary with the given arguments and capture its output.
+    fn run_thag(args: &[&str]) -> Output {
+        set_up();
+        Command::new(env!("CARGO_BIN_EXE_thag"))
+            .args(args)
+            .output()
+            .expect("Failed to run thag")
+    }
+
+    // Run the thag binary with the given arguments, feeding it the given input on stdin.
+    fn run_thag_with_input(args: &[&str], input: &str) -> Output {
+        use std::io::Write;
+        use std::process::Stdio;
+        set_up();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_thag"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to run thag");
+        child
+            .stdin
+            .take()
+            .expect("Failed to open stdin")
+            .write_all(input.as_bytes())
+            .expect("Failed to write to stdin");
+        child.wait_with_output().expect("Failed to wait for thag")
+    }
+
+    // Run the thag binary with the given arguments and return its exit code.
+    fn thag_exit_code(args: &[&str]) -> Option<i32> {
+        run_thag(args).status.code()
+    }
+
+    #[test]
+    fn test_exit_code_success() {
+        assert_eq!(thag_exit_code(&["-qq", "tests/assets/hello_t.rs"]), Some(0));
+    }
+
+    #[test]
+    fn test_exit_code_propagated_from_script() {
+        assert_eq!(
+            thag_exit_code(&["-qq", "-f", "tests/assets/exit_code_t.rs"]),
+            Some(42)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_code_script_killed_by_signal() {
+        // SIGABRT is 6
+        assert_eq!(
+            thag_exit_code(&["-qq", "-f", "tests/assets/abort_t.rs"]),
+            Some(EXIT_SIGNAL_BASE + 6)
+        );
+    }
+
+    #[test]
+    fn test_exit_code_build_error() {
+        assert_eq!(
+            thag_exit_code(&["-qq", "-f", "tests/assets/build_error_t.rs"]),
+            Some(EXIT_BUILD)
+        );
+    }
+
+    #[test]
+    fn test_exit_code_invalid_args() {
+        assert_eq!(
+            thag_exit_code(&["tests/assets/Cargo_t.toml"]),
+            Some(EXIT_USAGE)
+        );
+    }
+
+    #[test]
+    fn test_stdout_only_has_script_output() {
+        // Normal verbosity with timings, so that thag has plenty to say on stderr.
+        let output = run_thag(&["-t", "-f", "tests/assets/hello_t.rs"]);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello world!\n");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Completed run"));
+    }
+
+    #[test]
+    fn test_stdout_only_has_expr_output() {
+        let output = run_thag(&["-e", "6 * 7"]);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "42\n");
+    }
+
+    #[test]
+    fn test_slurp() {
+        let output = run_thag_with_input(
+            &[
+                "-qq",
+                "-S",
+                "-e",
+                "lines.sort(); (lines.join(\",\"), input.len())",
+            ],
+            "b\na\nc\n",
+        );
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "(\"a,b,c\", 6)\n");
+
+        let output = run_thag_with_input(
+            &[
+                "-qq",
+                "-S",
+                "--format",
+                "debug",
+                "--unquote=true",
+                "-e",
+                "input.trim()",
+            ],
+            "hi\n",
+        );
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\n");
+    }
+
+    #[test]
+    fn test_condensed_diagnostics() {
+        let output = run_thag(&["-e", "let x: i32 = \"a\";\nx"]);
+        assert_eq!(output.status.code(), Some(EXIT_BUILD));
+        let stderr = strip_ansi(&String::from_utf8_lossy(&output.stderr));
+        assert!(stderr.contains("error[E0308]: mismatched types\n  --> <expr>:1:14\n"));
+        assert!(!stderr.contains("Compiling"));
+        assert!(!stderr.contains("could not compile"));
+    }
+
+    #[test]
+    fn test_raw_cargo_output() {
+        let output = run_thag(&["--cargo-output", "-e", "let x: i32 = \"a\";\nx"]);
+        assert_eq!(output.status.code(), Some(EXIT_BUILD));
+        let stderr = strip_ansi(&String::from_utf8_lossy(&output.stderr));
+        assert!(stderr.contains("could not compile"));
+    }
+
+    #[test]
+    fn test_result_printed_by_type() {
+        let cases = [
+            (
+                "let v = vec![3, 1, 2]; v.iter().max().copied()",
+                "Some(3)\n",
+            ),
+            ("format!(\"{}\", 6 * 7)", "42\n"),
+            ("fn f() -> u8 { 4 }\nf()", "4\n"),
+            ("let mut v = vec![1]; v.push(2)", ""),
+            ("print!(\"done\")", "done"),
+        ];
+        for (expr, expected) in cases {
+            let output = run_thag(&["-qq", "-e", expr]);
+            assert!(output.status.success(), "{expr}");
+            assert_eq!(String::from_utf8_lossy(&output.stdout), expected, "{expr}");
+        }
+    }
+
+    #[test]
+    fn test_result_format() {
+        let expr = "(String::from(\"a\"), 1)";
+        let cases = [
+            ("display", "(\"a\", 1)\n"),
+            ("debug", "(\"a\", 1)\n"),
+            ("pretty", "(\n    \"a\",\n    1,\n)\n"),
+        ];
+        for (format, expected) in cases {
+            let output = run_thag(&["-qq", "--format", format, "-e", expr]);
+            assert!(output.status.success(), "{format}");
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                expected,
+                "{format}"
+            );
+        }
+        let output = run_thag(&["-qq", "--format", "debug", "-e", "String::from(\"a\")"]);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "\"a\"\n");
+    }
+
+    #[test]
+    fn test_format_change_regenerates_script() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let script_path = temp_dir.path().join("format_change_t.rs");
+        fs::write(&script_path, "String::from(\"a\")\n").expect("Failed to write script");
+        let script = script_path.to_str().expect("Temp path should be UTF-8");
+        // The cached program must not be reused when the format changes
+        for (format, expected) in [("display", "a\n"), ("debug", "\"a\"\n"), ("display", "a\n")] {
+            let output = run_thag(&["-qq", "--format", format, script]);
+            assert!(output.status.success(), "{format}");
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                expected,
+                "{format}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_unquote_and_template_change_regenerate_script() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let script_path = temp_dir.path().join("unquote_change_t.rs");
+        fs::write(&script_path, "String::from(\"a\")\n").expect("Failed to write script");
+        let script = script_path.to_str().expect("Temp path should be UTF-8");
+        for (unquote, expected) in [("true", "a\n"), ("false", "\"a\"\n")] {
+            let output = run_thag(&[
+                "-qq",
+                "--format",
+                "debug",
+                &format!("--unquote={unquote}"),
+                script,
+            ]);
+            assert!(output.status.success(), "{unquote}");
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                expected,
+                "{unquote}"
+            );
+        }
+
+        let template_path = temp_dir.path().join("template.rs");
+        let template = template_path.to_str().expect("Temp path should be UTF-8");
+        for greeting in ["start", "begin"] {
+            fs::write(
+                &template_path,
+                format!("{{{{prelude}}}}\n{{{{main}}}} {{\n    println!(\"{greeting}\");\n{{{{body}}}}\n}}\n"),
+            )
+            .expect("Failed to write template file");
+            let output = run_thag(&["-qq", "--template", template, script]);
+            assert!(output.status.success(), "{greeting}");
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                format!("{greeting}\na\n")
+            );
+        }
+    }
+
+    #[test]
+    fn test_output_json() {
+        let output = run_thag(&[
+            "-qq",
+            "--output",
+            "json",
+            "-e",
+            "vec![(\"a\", 1), (\"b\", 2)]",
+        ]);
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "[[\"a\",1],[\"b\",2]]\n"
+        );
+
+        let output = run_thag(&["-qq", "--output", "json", "-e", "struct Foo;\nFoo"]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .contains("Foo` doesn't implement `serde::Serialize`"));
+    }
+
+    #[test]
+    fn test_output_rich() {
+        let output = run_thag(&[
+            "-qq",
+            "--output",
+            "rich",
+            "-e",
+            "vec![(\"apples\".to_string(), 3), (\"kiwis\".to_string(), 12)]",
+        ]);
+        assert!(output.status.success());
+        assert_eq!(
+            strip_ansi(&String::from_utf8_lossy(&output.stdout)),
+            "apples │  3\nkiwis  │ 12\n"
+        );
+
+        // JSON can't have tuple keys, so the map is shown with Debug instead
+        let output = run_thag(&[
+            "-qq",
+            "--output",
+            "rich",
+            "-e",
+            "std::collections::BTreeMap::from([((1, 2), 3)])",
+        ]);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "{(1, 2): 3}\n");
+    }
+
+    #[test]
+    fn test_output_change_with_cached_script() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let script_path = temp_dir.path().join("output_change_t.rs");
+        fs::write(
+            &script_path,
+            "vec![(\"apples\".to_string(), 3), (\"kiwis\".to_string(), 12)]\n",
+        )
+        .expect("Failed to write script");
+        let script = script_path.to_str().expect("Temp path should be UTF-8");
+        let table = "apples │  3\nkiwis  │ 12\n";
+        let text = "[(\"apples\", 3), (\"kiwis\", 12)]\n";
+        // The second run reuses the program built by the first
+        for (mode, expected) in [
+            ("rich", table),
+            ("rich", table),
+            ("text", text),
+            ("rich", table),
+        ] {
+            let output = run_thag(&["-qq", "--output", mode, script]);
+            assert!(output.status.success(), "{mode}");
+            assert_eq!(
+                strip_ansi(&String::from_utf8_lossy(&output.stdout)),
+                expected,
+                "{mode}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_output_json_loop() {
+        use std::io::Write;
+        use std::process::Stdio;
+        set_up();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_thag"))
+            .args(["-qq", "--output", "json", "-l", "(i, line.len())"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to run thag");
+        child
+            .stdin
+            .take()
+            .expect("Failed to open stdin")
+            .write_all(b"a\nbb\n")
+            .expect("Failed to write to stdin");
+        let output = child.wait_with_output().expect("Failed to wait for thag");
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "[1,1]\n[2,2]\n");
+    }
+
+    #[test]
+    fn test_loop_fields() {
+        let output = run_thag_with_input(
+            &[
+                "-qq",
+                "-F",
+                ",",
+                "-B",
+                "let mut total = 0;",
+                "-l",
+                "total += fields[1].parse::<i32>()?; (nr, fnr, nf, fields[0])",
+                "-E",
+                "println!(\"total={total}\");",
+            ],
+            "a,1\nb,2\n",
+        );
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "(1, 1, 2, \"a\")\n(2, 2, 2, \"b\")\ntotal=3\n"
+        );
+
+        let output = run_thag_with_input(
+            &["-qq", "-F", "[0-9]+", "-l", "fields.join(\"|\")"],
+            "a1b22c\n",
+        );
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "a|b|c\n");
+    }
+
+    #[test]
+    fn test_loop_input_files() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let file_a = temp_dir.path().join("a.txt");
+        let file_b = temp_dir.path().join("b.txt");
+        fs::write(&file_a, "foo one\nbar foo\n").expect("Failed to write input file");
+        fs::write(&file_b, "baz\n").expect("Failed to write input file");
+        let file_a = file_a.display().to_string();
+        let file_b = file_b.display().to_string();
+
+        let output = run_thag(&[
+            "-qq",
+            "-l",
+            "format!(\"{}:{fnr}:{nr}:{line}\", filename.ends_with(\"b.txt\"))",
+            "--",
+            &file_a,
+            &file_b,
+        ]);
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "false:1:1:foo one\nfalse:2:2:bar foo\ntrue:1:3:baz\n"
+        );
+
+        let output = run_thag(&[
+            "-qq",
+            "-l",
+            "line = line.replace(\"foo\", \"qux\")",
+            "-p",
+            "-i.bak",
+            "--",
+            &file_a,
+        ]);
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+        assert_eq!(fs::read_to_string(&file_a).unwrap(), "qux one\nbar qux\n");
+        assert_eq!(
+            fs::read_to_string(format!("{file_a}.bak")).unwrap(),
+            "foo one\nbar foo\n"
+        );
+    }
+
+    #[test]
+    fn test_loop_in_place_requires_files() {
+        let output = run_thag_with_input(&["-qq", "-l", "line", "-i"], "a\n");
+        assert_eq!(output.status.code(), Some(EXIT_USAGE));
+    }
+
+    #[test]
+    fn test_loop_parallel() {
+        let input: String = (1..=20).map(|n| format!("{n}\n")).collect();
+        // Later lines finish first, but the output stays in order, and --begin and --end
+        // run once, sharing state with the filter through a sync type
+        let output = run_thag_with_input(
+            &[
+                "-qq",
+                "-j",
+                "4",
+                "-B",
+                "println!(\"begin\"); let total = std::sync::atomic::AtomicUsize::new(0);",
+                "-l",
+                "let n = line.parse::<usize>()?;
+                std::thread::sleep(std::time::Duration::from_millis((20 - n as u64) * 2));
+                total.fetch_add(n, std::sync::atomic::Ordering::Relaxed);
+                (nr, n)",
+                "-E",
+                "println!(\"end {}\", total.into_inner());",
+            ],
+            &input,
+        );
+        assert!(output.status.success());
+        let expected = format!(
+            "begin\n{}end 210\n",
+            (1..=20)
+                .map(|n| format!("({n}, {n})\n"))
+                .collect::<String>()
+        );
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    }
+
+    #[test]
+    fn test_loop_parallel_continue() {
+        // `continue` skips a record, and `?` works on errors that aren't `Send`
+        let output = run_thag_with_input(
+            &[
+                "-qq",
+                "-j",
+                "2",
+                "-l",
+                "if line.starts_with('#') { continue; }
+                let parsed: Result<i32, Box<dyn std::error::Error>> = line.parse().map_err(|e: std::num::ParseIntError| e.to_string().into());
+                parsed? * 2",
+            ],
+            "1\n# comment\n2\n",
+        );
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n4\n");
+
+        let output = run_thag_with_input(
+            &["-qq", "-j", "2", "-l", "if nr > 1 { break; } line"],
+            "a\n",
+        );
+        assert_eq!(output.status.code(), Some(EXIT_USAGE));
+    }
+
+    #[test]
+    fn test_loop_jsonl() {
+        let input = "{\"a\": 1}\n\nnot json\n{\"a\": 2}\n";
+        let output = run_thag_with_input(&["-qq", "--jsonl", "-l", "rec[\"a\"].clone()"], input);
+        assert!(!output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("-:3: malformed JSON record"));
+
+        let output = run_thag_with_input(
+            &[
+                "-qq",
+                "--jsonl",
+                "--skip-malformed",
+                "-l",
+                "(nr, fnr, rec[\"a\"].clone())",
+            ],
+            input,
+        );
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "(1, 1, Number(1))\n(2, 4, Number(2))\n"
+        );
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("-:3: skipping malformed JSON record")
+        );
+    }
+
+    #[test]
+    fn test_loop_csv() {
+        let input = "name,qty\napple,3\n\"pear, green\",5\nbad\nplum,1\n";
+        let output = run_thag_with_input(
+            &[
+                "-qq",
+                "--csv",
+                "-l",
+                "format!(\"{} x{}\", rec[\"name\"], fields[1])",
+            ],
+            input,
+        );
+        assert!(!output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "apple x3\npear, green x5\n"
+        );
+
+        let output = run_thag_with_input(
+            &[
+                "-qq",
+                "--csv",
+                "--skip-malformed",
+                "-l",
+                "(nr, nf, rec[\"name\"])",
+            ],
+            input,
+        );
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "(1, 2, \"apple\")\n(2, 2, \"pear, green\")\n(3, 2, \"plum\")\n"
+        );
+    }
+
+    #[test]
+    fn test_async_snippet() {
+        let output = run_thag(&[
+            "-qq",
+            "-e",
+            "let h = tokio::spawn(async { 6 * 7 });\nh.await?",
+        ]);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "42\n");
+    }
+
+    #[test]
+    fn test_template_file() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let template_path = temp_dir.path().join("template.rs");
+        fs::write(
+            &template_path,
+            "{{prelude}}\n{{main}} -> Result<(), String> {\n    println!(\"start\");\n{{begin}}\n{{body}}\n{{end}}\n    Ok(())\n}\n",
+        )
+        .expect("Failed to write template file");
+        let template = template_path.to_str().expect("Invalid template path");
+
+        let output = run_thag(&["-qq", "--template", template, "-e", "6 * 7"]);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "start\n42\n");
+
+        let output = run_thag(&["-qq", "--template", "no_such_template", "-e", "6 * 7"]);
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("Unknown template no_such_template")
+        );
+    }
+
+    #[test]
+    fn test_typed_main() {
+        let output = run_thag(&[
+            "-qq",
+            "-f",
+            "tests/assets/typed_main_t.rs",
+            "--",
+            "hi",
+            "3",
+            "--upper",
+        ]);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "HI HI HI\n");
+
+        let output = run_thag(&["-qq", "tests/assets/typed_main_t.rs", "--", "--help"]);
+        assert!(output.status.success());
+        let help = String::from_utf8_lossy(&output.stdout);
+        assert!(help.starts_with("Repeat a word a number of times"));
+        assert!(help.contains("<COUNT>  How many times to repeat it"));
+        assert!(help.contains("--upper  Print it in upper case"));
+    }
+
+    #[test]
+    fn test_script_env() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let script_dir = temp_dir
+            .path()
+            .canonicalize()
+            .expect("Failed to resolve temp dir");
+        let script_path = script_dir.join("env_t.rs");
+        fs::write(
+            &script_path,
+            r#"fn main() {
+    for name in ["THAG_SCRIPT_PATH", "THAG_SCRIPT_DIR", "THAG_MODE", "GREETING"] {
+        println!("{}", std::env::var(name).unwrap_or_default());
+    }
+}
+"#,
+        )
+        .expect("Failed to write script");
+        fs::write(script_dir.join(".env"), "GREETING=\"Hello\"\n").expect("Failed to write .env");
+
+        let output = run_thag(&["-qq", script_path.to_str().expect("Invalid script path")]);
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!(
+                "{}\n{}\nscript\nHello\n",
+                script_path.display(),
+                script_dir.display()
+            )
+        );
+
+        let output = run_thag(&["-qq", "-e", "std::env::var(\"THAG_MODE\")?"]);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "expr\n");
+    }
+
+    #[test]
+    fn test_fix_expr() {
+        let output = run_thag(&[
+            "--fix",
+            "-e",
+            "fn f(s: &String) -> usize { s.len() }\nlet s = String::from(\"abc\");\nf(s)",
+        ]);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+        let stderr = strip_ansi(&String::from_utf8_lossy(&output.stderr));
+        assert!(stderr.contains("-f(s)\n+f(&s)"));
+    }
+
+    #[test]
+    fn test_diagnostics_mapped_to_expr() {
+        let output = run_thag(&["--message-format", "json", "-e", "let x: i32 = \"a\";\nx"]);
+        assert_eq!(output.status.code(), Some(EXIT_BUILD));
+        let span = String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .find(|event| event["event"] == "diagnostic")
+            .map(|event| event["message"]["spans"][0].clone())
+            .expect("Expected a diagnostic event");
+        assert_eq!(span["file_name"], "<expr>");
+        assert_eq!(span["line_start"], 1);
+        assert_eq!(span["column_start"], 14);
+    }
+
+    #[test]
+    fn test_message_format_json() {
+        let output = run_thag(&["--message-format", "json", "-e", "6 * 7"]);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "42\n");
+        let events: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .map(|line| {
+                serde_json::from_str(line).expect("Every stderr line should be a JSON event")
+            })
+            .collect();
+        for phase in ["generate", "build", "run"] {
+            assert!(events.iter().any(|event| event["event"] == "phase_finish"
+                && event["phase"] == phase
+                && event["success"] == true));
+        }
+        let last = events.last().expect("Expected at least one event");
+        assert_eq!(last["event"], "exit");
+        assert_eq!(last["code"], 0);
+    }
 }
@@ -174,3 +174,22 @@ fn test_proc_flags_executable() {
         ProcFlags::GENERATE | ProcFlags::BUILD | ProcFlags::NORUN | ProcFlags::EXECUTABLE
     ));
 }
+
+#[test]
+fn test_skip_malformed_requires_record_format() {
+    set_up();
+    let cli = Cli::parse_from(vec!["thag", "-l", "line", "--skip-malformed"]);
+    assert!(get_proc_flags(&cli).is_err());
+    let cli = Cli::parse_from(vec!["thag", "-l", "line", "--csv", "--skip-malformed"]);
+    assert!(get_proc_flags(&cli).is_ok());
+    assert!(Cli::try_parse_from(vec!["thag", "-l", "line", "--csv", "--jsonl"]).is_err());
+}
+
+#[test]
+fn test_slurp_requires_expr() {
+    set_up();
+    let cli = Cli::parse_from(vec!["thag", "-l", "line", "-S"]);
+    assert!(get_proc_flags(&cli).is_err());
+    let cli = Cli::parse_from(vec!["thag", "-e", "input", "-S"]);
+    assert!(get_proc_flags(&cli).is_ok());
+}
@@ -5,12 +5,12 @@ mod tests {
     use thag_rs::code_utils::find_use_renames_source;
     use thag_rs::code_utils::infer_deps_from_ast;
     use thag_rs::code_utils::infer_deps_from_source;
-    use thag_rs::code_utils::is_last_stmt_unit_type;
-    use thag_rs::code_utils::is_path_unit_type;
-    use thag_rs::code_utils::is_stmt_unit_type;
     use thag_rs::code_utils::path_to_str;
     use thag_rs::code_utils::read_file_contents;
-    use thag_rs::code_utils::wrap_snippet;
+    use thag_rs::code_utils::{
+        breaks_record_loop, has_typed_main, is_async, is_regex_separator, wrap_snippet,
+        wrap_typed_main, AsyncRuntime, WrapperTemplate,
+    };
     use thag_rs::extract_manifest;
 
     use std::io::Write;
@@ -112,586 +112,127 @@ mod tests {
             "#;
 
         let (inner_attribs, body) = extract_inner_attribs(source_code);
-        let wrapped = wrap_snippet(&inner_attribs, &body);
+        let wrapped = wrap_snippet(
+            &WrapperTemplate::default(),
+            &inner_attribs,
+            &body,
+            AsyncRuntime::None,
+        );
         assert!(wrapped.contains("fn main() -> Result<(), Box<dyn Error>>"));
     }
 
     #[test]
-    fn test_find_use_renames_source() {
+    fn test_wrap_async_snippet() {
         set_up();
-        let source_code = r#"
-            use foo as bar;
-            use std::fmt;
-            "#;
-
-        let use_renames = find_use_renames_source(source_code);
-        assert_eq!(use_renames, vec!["bar"]);
-    }
-
-    #[test]
-    fn test_find_modules_source() {
-        set_up();
-        let source_code = r#"
-            mod foo;
-            mod bar;
-            "#;
-
-        let modules = find_modules_source(source_code);
-        assert_eq!(modules, vec!["foo", "bar"]);
-    }
-
-    use std::collections::HashMap;
-    use syn::{parse_quote, Expr, ReturnType, Stmt};
-
-    fn set_up_function_map() -> HashMap<String, ReturnType> {
-        let mut function_map = HashMap::new();
-        function_map.insert("unit_fn".to_string(), ReturnType::Default);
-        function_map.insert(
-            "non_unit_fn".to_string(),
-            ReturnType::Type(Default::default(), Box::new(syn::parse_quote!(i32))),
+        let wrapped = wrap_snippet(
+            &WrapperTemplate::default(),
+            "",
+            "f().await",
+            AsyncRuntime::Tokio,
         );
-        function_map
-    }
-
-    #[test]
-    fn test_for_loop_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            for i in 0..10 { println!("{}", i); }
-        };
-        let function_map = set_up_function_map();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_while_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            while true { break; }
-        };
-        let function_map = set_up_function_map();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
+        assert!(wrapped.contains("#[tokio::main]\nasync fn main() -> Result<(), Box<dyn Error>>"));
     }
 
     #[test]
-    fn test_loop_expr() {
+    fn test_wrap_snippet_custom_template() {
         set_up();
-        let expr: Expr = parse_quote! {
-            loop { break; }
+        let template = WrapperTemplate {
+            text: "{{prelude}}\n{{main}} -> anyhow::Result<()> {\n{{body}}\nOk(())\n}\n{{other}}"
+                .to_string(),
+            prelude: "use itertools::Itertools;".to_string(),
         };
-        let function_map = set_up_function_map();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_if_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            if true { 1 } else { 0 }
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_block_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            { let x = 1; x + 1 }
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
+        let wrapped = wrap_snippet(&template, "", "let s = \"{{body}}\";", AsyncRuntime::None);
+        assert_eq!(
+            wrapped,
+            "use itertools::Itertools;\nfn main() -> anyhow::Result<()> {\nlet s = \"{{body}}\";\nOk(())\n}\n{{other}}"
+        );
     }
 
     #[test]
-    fn test_match_expr() {
+    fn test_wrap_typed_main() {
         set_up();
-        let expr: Expr = parse_quote! {
-            match x {
-                1 => 2,
-                _ => 3,
+        let source = r#"
+            /// Say hello
+            fn main(name: String, loud: bool, mut times: Option<u8>) {
+                println!("Hello, {name}");
             }
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_call_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            unit_fn()
-        };
-        let function_map = set_up_function_map();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_closure_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            || { 42 }
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_method_call_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            foo.bar()
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_array_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            [0, 1, 2]
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_return_unit_stmt() {
-        set_up();
-        let stmt: Stmt = parse_quote! {
-            return;
-        };
-        let function_map = set_up_function_map();
-        assert!(is_stmt_unit_type(&stmt, &function_map));
-    }
-
-    #[test]
-    fn test_return_non_unit_stmt() {
-        set_up();
-        let stmt: Stmt = parse_quote! {
-            return 5;
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_stmt_unit_type(&stmt, &function_map));
-    }
-
-    #[test]
-    fn test_yield_expr() {
-        set_up();
-        let stmt: Stmt = parse_quote! {
-            yield;
-        };
-        let function_map = set_up_function_map();
-        assert!(is_stmt_unit_type(&stmt, &function_map));
-    }
-
-    #[test]
-    fn test_local_stmt() {
-        set_up();
-        let stmt: Stmt = parse_quote! {
-            let x = 5;
-        };
-        let function_map = set_up_function_map();
-        assert!(is_stmt_unit_type(&stmt, &function_map));
-    }
-
-    #[test]
-    fn test_item_const_stmt() {
-        set_up();
-        let stmt: Stmt = parse_quote! {
-            const X: i32 = 5;
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_stmt_unit_type(&stmt, &function_map));
-    }
+            "#;
+        assert!(has_typed_main(&Ast::File(syn::parse_file(source).unwrap())));
+        let wrapped = syn::parse_file(&wrap_typed_main(source).unwrap()).unwrap();
+        assert!(!has_typed_main(&Ast::File(wrapped.clone())));
+        let wrapped = prettyplease::unparse(&wrapped);
+        assert!(wrapped.contains(
+            "let ThagMainArgs { name, loud, mut times } = <ThagMainArgs as clap::Parser>::parse();"
+        ));
+        assert!(wrapped.contains("#[arg(long)]\n    loud: bool,"));
+        assert!(wrapped.contains("#[arg(long)]\n    times: Option<u8>,"));
+        assert!(wrapped.contains("/// Say hello\n#[derive(clap::Parser, Debug)]\nstruct ThagMainArgs {\n    name: String,"));
 
-    #[test]
-    fn test_expr_stmt_with_semicolon() {
-        set_up();
-        let stmt: Stmt = parse_quote! {
-            42;
-        };
-        let function_map = set_up_function_map();
-        assert!(is_stmt_unit_type(&stmt, &function_map));
+        assert!(wrap_typed_main("fn main((a, b): (u8, u8)) {}").is_err());
     }
 
     #[test]
-    fn test_expr_stmt_without_semicolon() {
+    fn test_is_regex_separator() {
         set_up();
-        let expr: Expr = parse_quote! {
-            42
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
+        assert!(!is_regex_separator(","));
+        assert!(!is_regex_separator("|"));
+        assert!(!is_regex_separator("::"));
+        assert!(is_regex_separator("[,;]"));
+        assert!(is_regex_separator(r"\s+"));
     }
 
     #[test]
-    fn test_path_unit_type() {
+    fn test_breaks_record_loop() {
         set_up();
-        let expr: Expr = parse_quote! {
-            unit_fn()
-        };
-        let function_map = set_up_function_map();
-        if let Expr::Call(call) = expr {
-            if let Expr::Path(path) = *call.func {
-                assert!(is_path_unit_type(&path, &function_map).unwrap());
-            } else {
-                panic!("Expected Expr::Path");
-            }
-        } else {
-            panic!("Expected Expr::Call");
-        }
+        assert!(breaks_record_loop("if nr > 10 { break; } line"));
+        assert!(!breaks_record_loop("if line.is_empty() { continue; } line"));
+        assert!(!breaks_record_loop(
+            "for c in line.chars() { if c == ',' { break; } }\nloop { break; }\nline"
+        ));
+        assert!(!breaks_record_loop(
+            "'found: { if line.is_empty() { break 'found; } }\nline"
+        ));
+        assert!(!breaks_record_loop("let f = || loop { break 1 }; f()"));
     }
 
     #[test]
-    fn test_path_non_unit_type() {
+    fn test_is_async() {
         set_up();
-        let expr: Expr = parse_quote! {
-            non_unit_fn()
-        };
-        let function_map = set_up_function_map();
-        if let Expr::Call(call) = expr {
-            if let Expr::Path(path) = *call.func {
-                assert!(!is_path_unit_type(&path, &function_map).unwrap());
-            } else {
-                panic!("Expected Expr::Path");
+        let expr: syn::Expr = syn::parse_quote!({ reqwest::get(u).await?.text().await? });
+        assert!(is_async(&expr));
+        let expr: syn::Expr = syn::parse_quote!({ tokio::spawn(async { 1 }) });
+        assert!(is_async(&expr));
+        // Awaiting within the snippet's own async functions doesn't call for a runtime
+        let expr: syn::Expr = syn::parse_quote!({
+            async fn f() -> u8 {
+                g().await
             }
-        } else {
-            panic!("Expected Expr::Call");
-        }
-    }
-
-    #[test]
-    fn test_macro_expr() {
-        set_up();
-        let stmt: Stmt = parse_quote! {
-            println!("Hello, world!");
-        };
-        let function_map = set_up_function_map();
-        assert!(is_stmt_unit_type(&stmt, &function_map));
-    }
-
-    #[test]
-    fn test_async_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            async { 42 }
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_await_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            foo.await
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_binary_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            1 + 2
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_cast_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            1 as f64
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_index_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            arr[0]
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_tuple_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            (1, 2, 3)
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_unary_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            -42
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_paren_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            (42)
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_reference_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            &42
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_field_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            foo.bar
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_infer_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            _
-        };
-        let function_map = set_up_function_map();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_continue_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            continue
-        };
-        let function_map = set_up_function_map();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_break_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            break
-        };
-        let function_map = set_up_function_map();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_assign_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            x = 1
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_struct_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            Struct { field: 1 }
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_range_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            1..10
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_try_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            some_result?
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_verbatim_expr() {
-        set_up();
-        let expr: Expr = parse_quote! {
-            ver
-        };
-        let function_map = set_up_function_map();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_assign_op_expr() {
-        set_up();
-        let expr: Expr = parse_quote!(a += 1);
-        let function_map = HashMap::new();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_block_with_return_expr() {
-        set_up();
-        let expr: Expr = parse_quote!({
-            let x = 5;
-            x
+            5
         });
-        let function_map = HashMap::new();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_closure_non_unit_expr() {
-        set_up();
-        let expr: Expr = parse_quote!(|x| x + 1);
-        let function_map = HashMap::new();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_closure_unit_expr() {
-        set_up();
-        let expr: Expr = parse_quote!(|| {});
-        let function_map = HashMap::new();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    // #[test]
-    // fn test_continue_stmt() {
-    //     set_up();
-    //     let stmt: Stmt = parse_quote!(continue);
-    //     let function_map = HashMap::new();
-    //     assert!(is_stmt_unit_type(&stmt, &function_map));
-    // }
-
-    #[test]
-    fn test_if_let_expr() {
-        set_up();
-        let expr: Expr = parse_quote!(if let Some(x) = y { x } else { 0 });
-        let function_map = HashMap::new();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_let_expr() {
-        set_up();
-        let expr: Expr = parse_quote!(let x = 5);
-        let function_map = HashMap::new();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_literal_expr() {
-        set_up();
-        let expr: Expr = parse_quote!(42);
-        let function_map = HashMap::new();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_loop_break_expr() {
-        set_up();
-        let expr: Expr = parse_quote!(break);
-        let function_map = HashMap::new();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_macro_expr_with_semi() {
-        set_up();
-        let stmt: Stmt = parse_quote!(println!("Hello"););
-        let function_map = HashMap::new();
-        assert!(is_stmt_unit_type(&stmt, &function_map));
+        assert!(!is_async(&expr));
     }
 
     #[test]
-    fn test_macro_expr_without_semi() {
-        set_up();
-        let expr: Expr = parse_quote!(println!("Hello"));
-        let function_map = HashMap::new();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_macro_with_debug() {
-        set_up();
-        let expr: Expr = parse_quote!(debug!("debug message"));
-        let function_map = HashMap::new();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_macro_with_print() {
-        set_up();
-        let expr: Expr = parse_quote!(print!("printed message"));
-        let function_map = HashMap::new();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
-
-    #[test]
-    fn test_macro_with_write() {
+    fn test_find_use_renames_source() {
         set_up();
-        let expr: Expr = parse_quote!(write!(std::io::stdout(), "written message"));
-        let function_map = HashMap::new();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
+        let source_code = r#"
+            use foo as bar;
+            use std::fmt;
+            "#;
 
-    #[test]
-    fn test_path_expr() {
-        set_up();
-        let expr: Expr = parse_quote!(some_function());
-        let function_map = {
-            let mut map = HashMap::new();
-            map.insert("some_function".to_string(), ReturnType::Default);
-            map
-        };
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
+        let use_renames = find_use_renames_source(source_code);
+        assert_eq!(use_renames, vec!["bar"]);
     }
 
     #[test]
-    fn test_return_expr() {
+    fn test_find_modules_source() {
         set_up();
-        let expr: Expr = parse_quote!(return);
-        let function_map = HashMap::new();
-        assert!(is_last_stmt_unit_type(&expr, &function_map));
-    }
+        let source_code = r#"
+            mod foo;
+            mod bar;
+            "#;
 
-    #[test]
-    fn test_stmt_expr() {
-        set_up();
-        let expr: Expr = parse_quote!(5);
-        let function_map = HashMap::new();
-        assert!(!is_last_stmt_unit_type(&expr, &function_map));
+        let modules = find_modules_source(source_code);
+        assert_eq!(modules, vec!["foo", "bar"]);
     }
 }
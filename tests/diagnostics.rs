@@ -0,0 +1,97 @@
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use thag_rs::diagnostics::render_diagnostic;
+    use thag_rs::events::strip_ansi;
+    use thag_rs::logging::Verbosity;
+
+    // Set environment variables before running tests
+    fn set_up() {
+        std::env::set_var("TEST_ENV", "1");
+        std::env::set_var("VISUAL", "cat");
+        std::env::set_var("EDITOR", "cat");
+    }
+
+    fn span(
+        column_start: u64,
+        column_end: u64,
+        is_primary: bool,
+        label: &str,
+    ) -> serde_json::Value {
+        json!({
+            "file_name": "<expr>",
+            "line_start": 1,
+            "line_end": 1,
+            "column_start": column_start,
+            "column_end": column_end,
+            "is_primary": is_primary,
+            "label": label,
+            "text": [{
+                "text": "let x: i32 = \"a\";",
+                "highlight_start": column_start,
+                "highlight_end": column_end,
+            }],
+        })
+    }
+
+    #[test]
+    fn test_render_error() {
+        set_up();
+        let message = json!({
+            "level": "error",
+            "message": "mismatched types",
+            "code": { "code": "E0308" },
+            "spans": [
+                span(14, 17, true, "expected `i32`, found `&str`"),
+                span(8, 11, false, "expected due to this"),
+            ],
+            "children": [{
+                "level": "help",
+                "message": "try using a conversion method",
+                "spans": [{ "suggested_replacement": "\"a\".parse()?" }],
+            }],
+        });
+        let (verbosity, rendered) = render_diagnostic(&message).expect("Expected a rendering");
+        assert_eq!(verbosity, Verbosity::Quieter);
+        assert_eq!(
+            strip_ansi(&rendered),
+            r#"error[E0308]: mismatched types
+  --> <expr>:1:14
+1 | let x: i32 = "a";
+  |        --- expected due to this
+  |              ^^^ expected `i32`, found `&str`
+  = help: try using a conversion method: `"a".parse()?`"#
+        );
+    }
+
+    #[test]
+    fn test_render_warning() {
+        set_up();
+        let message = json!({
+            "level": "warning",
+            "message": "unused variable: `x`",
+            "code": { "code": "unused_variables" },
+            "spans": [span(5, 6, true, "")],
+            "children": [],
+        });
+        let (verbosity, rendered) = render_diagnostic(&message).expect("Expected a rendering");
+        assert_eq!(verbosity, Verbosity::Quiet);
+        assert!(strip_ansi(&rendered).starts_with("warning[unused_variables]: unused variable"));
+    }
+
+    #[test]
+    fn test_render_skips_summaries_and_notes() {
+        set_up();
+        for (level, text) in [
+            ("error", "aborting due to 1 previous error"),
+            ("warning", "2 warnings emitted"),
+            (
+                "failure-note",
+                "For more information about this error, try `rustc --explain E0308`.",
+            ),
+        ] {
+            let message = json!({ "level": level, "message": text, "spans": [], "children": [] });
+            assert!(render_diagnostic(&message).is_none(), "{text}");
+        }
+    }
+}
@@ -8,7 +8,7 @@ use strum::ParseError as StrumParseError;
 use toml::de::Error as TomlDeError;
 use toml::ser::Error as TomlSerError;
 
-use thag_rs::errors::ThagError;
+use thag_rs::errors::{ThagError, EXIT_BUILD, EXIT_FAILURE, EXIT_GENERATE, EXIT_USAGE};
 
 // Set environment variables before running tests
 fn set_up() {
@@ -114,6 +114,9 @@ fn test_source() {
     let io_err = io::Error::new(io::ErrorKind::Other, "I/O error occurred");
     let build_run_err: ThagError = io_err.into();
     assert!(build_run_err.source().is_some());
+    // Errors that originate in thag itself have no underlying cause
+    assert!(ThagError::Build("Build failed".into()).source().is_none());
+    assert!(ThagError::ScriptExit(42).source().is_none());
 }
 
 #[test]
@@ -125,3 +128,19 @@ fn test_cancelled() {
         _ => panic!("Expected ThagError::Cancelled variant"),
     }
 }
+
+#[test]
+fn test_exit_codes() {
+    set_up();
+    let args_err = ThagError::Args(Box::new("Missing script name".into()));
+    assert_eq!(args_err.exit_code(), EXIT_USAGE);
+    let gen_err = ThagError::Generate(Box::new("Could not write source".into()));
+    assert_eq!(gen_err.exit_code(), EXIT_GENERATE);
+    assert_eq!(
+        ThagError::Build("Build failed".into()).exit_code(),
+        EXIT_BUILD
+    );
+    assert_eq!(ThagError::ScriptExit(42).exit_code(), 42);
+    let other_err: ThagError = "Some other error".into();
+    assert_eq!(other_err.exit_code(), EXIT_FAILURE);
+}
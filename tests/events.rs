@@ -0,0 +1,42 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use thag_rs::builder::generate;
+    use thag_rs::events::enable_events;
+    use thag_rs::{BuildState, ProcFlags};
+
+    // Set environment variables before running tests
+    fn set_up() {
+        std::env::set_var("TEST_ENV", "1");
+    }
+
+    #[test]
+    fn test_phase_finish_reported_on_failure() {
+        set_up();
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let message_file = temp_dir.path().join("events.jsonl");
+        enable_events(Some(&message_file)).expect("Failed to enable events");
+
+        let mut build_state = BuildState {
+            source_name: "broken.rs".into(),
+            target_dir_path: temp_dir.path().join("broken"),
+            ..Default::default()
+        };
+        let result = generate(&mut build_state, Some("fn main() {"), &ProcFlags::empty());
+        assert!(result.is_err());
+
+        let events: Vec<serde_json::Value> = fs::read_to_string(&message_file)
+            .expect("Failed to read events")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Every line should be a JSON event"))
+            .collect();
+        assert_eq!(events[0]["event"], "phase_start");
+        assert_eq!(events[0]["phase"], "generate");
+        let finish = events
+            .iter()
+            .find(|event| event["event"] == "phase_finish")
+            .expect("The failed phase should still report its finish");
+        assert_eq!(finish["phase"], "generate");
+        assert_eq!(finish["success"], false);
+    }
+}
@@ -0,0 +1,136 @@
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use thag_rs::events::strip_ansi;
+    use thag_rs::fixes::{
+        apply_suggestions, collect_suggestions, import_position, unified_diff, Suggestion,
+    };
+    use thag_rs::source_map::SourceSegment;
+
+    // Set environment variables before running tests
+    fn set_up() {
+        std::env::set_var("TEST_ENV", "1");
+        std::env::set_var("VISUAL", "cat");
+        std::env::set_var("EDITOR", "cat");
+    }
+
+    fn suggestion(line: usize, column_start: usize, column_end: usize, text: &str) -> Suggestion {
+        Suggestion {
+            file_name: "<expr>".to_string(),
+            line_start: line,
+            column_start,
+            line_end: line,
+            column_end,
+            replacement: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_collect_suggestions() {
+        set_up();
+        let message = json!({
+            "level": "error",
+            "message": "mismatched types",
+            "children": [
+                {
+                    "level": "help",
+                    "message": "consider borrowing here",
+                    "spans": [{
+                        "file_name": "<expr>", "line_start": 1, "line_end": 1,
+                        "column_start": 3, "column_end": 3,
+                        "suggested_replacement": "&",
+                        "suggestion_applicability": "MachineApplicable",
+                    }],
+                },
+                {
+                    "level": "help",
+                    "message": "try one of these",
+                    "spans": [{
+                        "file_name": "<expr>", "line_start": 1, "line_end": 1,
+                        "column_start": 1, "column_end": 4,
+                        "suggested_replacement": "foo",
+                        "suggestion_applicability": "MaybeIncorrect",
+                    }],
+                },
+            ],
+        });
+        assert_eq!(
+            collect_suggestions(&message),
+            vec![suggestion(1, 3, 3, "&")]
+        );
+
+        let warning = json!({ "level": "warning", "children": message["children"].clone() });
+        assert!(collect_suggestions(&warning).is_empty());
+    }
+
+    #[test]
+    fn test_collect_machine_applicable_imports_only() {
+        set_up();
+        let import_span = |path: &str, applicability: &str| {
+            json!({
+                "file_name": "temp.rs", "line_start": 1, "line_end": 1,
+                "column_start": 1, "column_end": 1,
+                "suggested_replacement": format!("use {path};\n\n"),
+                "suggestion_applicability": applicability,
+            })
+        };
+        let message = |spans: Vec<serde_json::Value>| {
+            json!({
+                "level": "error",
+                "children": [{ "level": "help", "message": "consider importing", "spans": spans }],
+            })
+        };
+        let applicable = collect_suggestions(&message(vec![import_span(
+            "std::collections::HashMap",
+            "MachineApplicable",
+        )]));
+        assert_eq!(applicable.len(), 1);
+        assert!(applicable[0].is_import());
+
+        // Even a single candidate may be the wrong one
+        let single = message(vec![import_span(
+            "std::collections::HashMap",
+            "MaybeIncorrect",
+        )]);
+        assert!(collect_suggestions(&single).is_empty());
+    }
+
+    #[test]
+    fn test_apply_suggestions() {
+        set_up();
+        let segment = SourceSegment::new("<expr>", "let s = String::new();\nf(s)\n");
+        let borrow = suggestion(2, 3, 3, "&");
+        let rename = suggestion(1, 5, 6, "t");
+        let overlapping = suggestion(1, 5, 7, "u");
+        let outside = suggestion(9, 1, 1, "x");
+        assert_eq!(
+            apply_suggestions(&segment, &[&borrow, &rename, &overlapping, &outside]),
+            (String::from("let t = String::new();\nf(&s)\n"), 2)
+        );
+    }
+
+    #[test]
+    fn test_import_position() {
+        set_up();
+        let segment = SourceSegment::new(
+            "<expr>",
+            "/*[toml]\n[dependencies]\n*/\n#![allow(unused)]\n// A comment\n\nlet x = 1;\n",
+        );
+        assert_eq!(import_position(&segment), (7, 1));
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        set_up();
+        let original = SourceSegment {
+            name: "script.rs".to_string(),
+            text: "fn main() {\n    let s = 1;\n    f(s);\n}\n".to_string(),
+            first_line: 2,
+        };
+        let fixed = "fn main() {\n    let s = 1;\n    f(&s);\n}\n";
+        assert_eq!(
+            strip_ansi(&unified_diff(&original, fixed)),
+            "--- script.rs\n+++ script.rs\n@@ -2,4 +2,4 @@\n fn main() {\n     let s = 1;\n-    f(s);\n+    f(&s);\n }"
+        );
+    }
+}
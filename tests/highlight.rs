@@ -0,0 +1,128 @@
+#[cfg(test)]
+mod tests {
+    use reedline::Highlighter;
+    use thag_rs::highlight::{tokenize, tokenize_lines, RustHighlighter, TokenKind};
+
+    // Set environment variables before running tests
+    fn set_up() {
+        std::env::set_var("TEST_ENV", "1");
+    }
+
+    fn kinds(source: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(source)
+            .into_iter()
+            .filter(|token| token.kind != TokenKind::Whitespace)
+            .map(|token| (token.kind, &source[token.range]))
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        set_up();
+        assert_eq!(
+            kinds(
+                r##"#[derive(Debug)] fn f<'a>(s: &'a str) -> Vec<u8> { println!("{s}", r#"x"#, 'c', 1.5e-3); } // done"##
+            ),
+            vec![
+                (TokenKind::Attribute, "#[derive(Debug)]"),
+                (TokenKind::Keyword, "fn"),
+                (TokenKind::Function, "f"),
+                (TokenKind::Punctuation, "<"),
+                (TokenKind::Lifetime, "'a"),
+                (TokenKind::Punctuation, ">"),
+                (TokenKind::Punctuation, "("),
+                (TokenKind::Identifier, "s"),
+                (TokenKind::Punctuation, ":"),
+                (TokenKind::Punctuation, "&"),
+                (TokenKind::Lifetime, "'a"),
+                (TokenKind::Type, "str"),
+                (TokenKind::Punctuation, ")"),
+                (TokenKind::Punctuation, "-"),
+                (TokenKind::Punctuation, ">"),
+                (TokenKind::Type, "Vec"),
+                (TokenKind::Punctuation, "<"),
+                (TokenKind::Type, "u8"),
+                (TokenKind::Punctuation, ">"),
+                (TokenKind::Punctuation, "{"),
+                (TokenKind::Macro, "println!"),
+                (TokenKind::Punctuation, "("),
+                (TokenKind::String, r#""{s}""#),
+                (TokenKind::Punctuation, ","),
+                (TokenKind::String, r##"r#"x"#"##),
+                (TokenKind::Punctuation, ","),
+                (TokenKind::String, "'c'"),
+                (TokenKind::Punctuation, ","),
+                (TokenKind::Number, "1.5e-3"),
+                (TokenKind::Punctuation, ")"),
+                (TokenKind::Punctuation, ";"),
+                (TokenKind::Punctuation, "}"),
+                (TokenKind::Comment, "// done"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_incomplete_input() {
+        set_up();
+        assert_eq!(
+            kinds("let s = \"unterminated\nx != 0..2 /* open"),
+            vec![
+                (TokenKind::Keyword, "let"),
+                (TokenKind::Identifier, "s"),
+                (TokenKind::Punctuation, "="),
+                (TokenKind::String, "\"unterminated\nx != 0..2 /* open"),
+            ]
+        );
+        assert_eq!(
+            kinds("x != 0..2 /* a /* nested */ comment"),
+            vec![
+                (TokenKind::Identifier, "x"),
+                (TokenKind::Punctuation, "!"),
+                (TokenKind::Punctuation, "="),
+                (TokenKind::Number, "0"),
+                (TokenKind::Punctuation, "."),
+                (TokenKind::Punctuation, "."),
+                (TokenKind::Number, "2"),
+                (TokenKind::Comment, "/* a /* nested */ comment"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_lines() {
+        set_up();
+        let lines: Vec<String> = ["let x = /* a", "b */ 1;"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            tokenize_lines(&lines),
+            vec![
+                vec![
+                    (0..3, TokenKind::Keyword),
+                    (4..5, TokenKind::Identifier),
+                    (6..7, TokenKind::Punctuation),
+                    (8..12, TokenKind::Comment),
+                ],
+                vec![
+                    (0..4, TokenKind::Comment),
+                    (5..6, TokenKind::Number),
+                    (6..7, TokenKind::Punctuation),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_highlighter_preserves_text() {
+        set_up();
+        let line = "let v = vec![1, 2]; // ünïcödé\n  v.len()";
+        let styled_text = RustHighlighter.highlight(line, 0);
+        let text: String = styled_text
+            .buffer
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect();
+        assert_eq!(text, line);
+    }
+}
@@ -4,7 +4,7 @@ mod tests {
     use std::env;
     use std::io::Write;
     use std::process::{Command, Stdio};
-    use std::sync::Once;
+    use std::sync::{Arc, Mutex, Once};
     use thag_rs::debug_log;
     use thag_rs::logging::{set_global_verbosity, Logger, Verbosity, LOGGER};
 
@@ -80,9 +80,37 @@ fn main() {{
 
         let output = run(input);
 
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .ends_with("Quieter message\nQuiet message\nNormal message\n"));
+    }
+
+    // A sink that captures logged messages for inspection.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[parallel]
+    fn test_logger_sink() {
+        set_up();
+        let buf = SharedBuf::default();
+        let mut logger = Logger::new(Verbosity::Quiet);
+        logger.set_sink(Box::new(buf.clone()));
+        logger.log(Verbosity::Quiet, "Quiet message");
+        logger.log(Verbosity::Normal, "Normal message");
         assert_eq!(
-            String::from_utf8_lossy(&output.stdout),
-            "Quieter message\nQuiet message\nNormal message\n"
+            String::from_utf8_lossy(&buf.0.lock().unwrap()),
+            "Quiet message\n"
         );
     }
 
@@ -133,10 +161,9 @@ fn main() {{
 
         let output = run(input);
 
-        assert_eq!(
-            String::from_utf8_lossy(&output.stdout),
-            "Macro quieter message\nMacro quiet message\nMacro normal message\n"
-        );
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .ends_with("Macro quieter message\nMacro quiet message\nMacro normal message\n"));
     }
 
     fn run(input: String) -> std::process::Output {
@@ -148,6 +175,7 @@ fn main() {{
             .arg("-s")
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .spawn()
             .expect("Failed to spawn child process");
 
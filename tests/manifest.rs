@@ -3,8 +3,10 @@ mod tests {
     use cargo_toml::{Dependency, Edition, Manifest};
     use mockall::predicate::*;
     use std::process::Output;
+    use thag_rs::code_utils::AsyncRuntime;
     use thag_rs::manifest::{
-        capture_dep, cargo_search, configure_default, merge, MockCommandRunner,
+        add_csv_deps, add_json_deps, add_runtime_deps, capture_dep, cargo_search,
+        configure_default, merge, resolve_dependency, MockCommandRunner,
     };
     use thag_rs::BuildState;
 
@@ -62,6 +64,35 @@ mod tests {
         assert_eq!(version, "1.0.203");
     }
 
+    #[test]
+    fn test_resolve_dependency() {
+        set_up();
+        // A given version needs no search
+        let mock_runner = MockCommandRunner::new();
+        let (name, dependency) = resolve_dependency(&mock_runner, "regex@1.10", vec![]).unwrap();
+        assert_eq!(name, "regex");
+        assert_eq!(dependency, Dependency::Simple("1.10".to_string()));
+        assert!(resolve_dependency(&mock_runner, "regex@", vec![]).is_err());
+
+        let output = Output {
+            status: successful_exit_status(),
+            stdout: b"serde = \"1.0.203\"".to_vec(),
+            stderr: Vec::new(),
+        };
+        let mut mock_runner = MockCommandRunner::new();
+        mock_runner
+            .expect_run_command()
+            .returning(move |_, _| Ok(output.clone()));
+        let (name, dependency) =
+            resolve_dependency(&mock_runner, "serde", vec!["derive".to_string()]).unwrap();
+        assert_eq!(name, "serde");
+        let Dependency::Detailed(detail) = dependency else {
+            panic!("Expected a detailed dependency, found {dependency:?}");
+        };
+        assert_eq!(detail.version.as_deref(), Some("1.0.203"));
+        assert_eq!(detail.features, vec!["derive"]);
+    }
+
     #[test]
     fn test_capture_dep_valid() {
         set_up();
@@ -81,6 +112,47 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_add_json_deps() {
+        set_up();
+        let mut dep_map = std::collections::BTreeMap::new();
+        dep_map.insert(
+            "serde_json".to_string(),
+            Dependency::Simple("1.0.100".to_string()),
+        );
+        add_json_deps(&mut dep_map);
+        let serde = dep_map.get("serde").expect("serde should be added");
+        assert_eq!(serde.req(), "1.0");
+        assert_eq!(serde.req_features(), ["derive"]);
+        // A version the script specifies is kept
+        assert_eq!(dep_map["serde_json"].req(), "1.0.100");
+    }
+
+    #[test]
+    fn test_add_csv_deps() {
+        set_up();
+        let mut dep_map = std::collections::BTreeMap::new();
+        add_csv_deps(&mut dep_map);
+        assert_eq!(dep_map["csv"].req(), "1");
+    }
+
+    #[test]
+    fn test_add_runtime_deps() {
+        set_up();
+        let mut dep_map = std::collections::BTreeMap::new();
+        add_runtime_deps(&mut dep_map, AsyncRuntime::Tokio);
+        assert_eq!(dep_map["tokio"].req_features(), ["full"]);
+
+        let mut dep_map = std::collections::BTreeMap::new();
+        dep_map.insert("tokio".to_string(), Dependency::Simple("1.40".to_string()));
+        add_runtime_deps(&mut dep_map, AsyncRuntime::Tokio);
+        assert_eq!(dep_map["tokio"].req(), "1.40");
+
+        let mut dep_map = std::collections::BTreeMap::new();
+        add_runtime_deps(&mut dep_map, AsyncRuntime::None);
+        assert!(dep_map.is_empty());
+    }
+
     #[test]
     fn test_default_manifest() {
         set_up();
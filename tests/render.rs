@@ -0,0 +1,79 @@
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use thag_rs::events::strip_ansi;
+    use thag_rs::render::render_value;
+
+    // Set environment variables before running tests
+    fn set_up() {
+        std::env::set_var("TEST_ENV", "1");
+        std::env::set_var("VISUAL", "cat");
+        std::env::set_var("EDITOR", "cat");
+    }
+
+    fn render(value: &serde_json::Value, width: usize) -> String {
+        strip_ansi(&render_value(value, width))
+    }
+
+    #[test]
+    fn test_render_tuples_as_table() {
+        set_up();
+        let value = json!([["apples", 3], ["kiwis", 12]]);
+        assert_eq!(render(&value, 80), "apples │  3\nkiwis  │ 12");
+    }
+
+    #[test]
+    fn test_render_structs_with_header() {
+        set_up();
+        let value = json!([{ "name": "Ann", "age": 31 }, { "name": "Bob", "age": 4 }]);
+        assert_eq!(
+            render(&value, 80),
+            "name │ age\n─────┼────\nAnn  │  31\nBob  │   4"
+        );
+    }
+
+    #[test]
+    fn test_render_map_as_two_columns() {
+        set_up();
+        let value = json!({ "a": 1, "b": [2, 3] });
+        assert_eq!(render(&value, 80), "a │     1\nb │ [2,3]");
+    }
+
+    #[test]
+    fn test_render_table_fits_width() {
+        set_up();
+        let value = json!([["a very long description here", 1, "xyz"]]);
+        let rendered = render(&value, 24);
+        assert_eq!(rendered, "a very long d… │ 1 │ xyz");
+        assert_eq!(rendered.chars().count(), 24);
+    }
+
+    #[test]
+    fn test_render_sparkline() {
+        set_up();
+        let value = json!([1, 8, 1, 8]);
+        assert_eq!(render(&value, 80), "▁█▁█\nn=4 min=1 max=8 mean=4.5");
+    }
+
+    #[test]
+    fn test_render_histogram_when_too_wide() {
+        set_up();
+        let value = json!((0..100).collect::<Vec<_>>());
+        let rendered = render(&value, 40);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines.len(), 11);
+        assert!(lines[0].starts_with("   0 – 9.9  │ █"));
+        assert!(lines[9].starts_with("89.1 – 99   │ █"));
+        assert!(lines[0].ends_with(" 10"));
+        assert!(lines.iter().all(|line| line.chars().count() <= 40));
+        assert_eq!(lines[10], "n=100 min=0 max=99 mean=49.5");
+    }
+
+    #[test]
+    fn test_render_plain() {
+        set_up();
+        assert_eq!(render(&json!("text"), 80), "text");
+        assert_eq!(render(&json!(42), 80), "42");
+        assert_eq!(render(&json!([1]), 80), "[\n  1\n]");
+    }
+}
@@ -1,13 +1,20 @@
 #[cfg(test)]
 mod tests {
     use clap::{ArgMatches, Parser};
+    use std::collections::BTreeMap;
     #[cfg(not(windows))]
     use std::path::PathBuf;
-    use thag_rs::cmd_args::{Cli, ProcFlags};
-    use thag_rs::repl::{delete, disp_repl_banner, list, parse_line, run_expr, Context};
+    use thag_rs::cmd_args::{get_proc_flags, Cli, ProcFlags};
+    use thag_rs::code_utils::{extract_ast_expr, extract_manifest};
+    use thag_rs::manifest;
+    use thag_rs::repl::{
+        delete, dep, disp_repl_banner, eval, format_parse_error, is_incomplete, list, parse_line,
+        run_expr, show_session, Context,
+    };
     #[cfg(not(windows))]
     use thag_rs::repl::{edit, edit_history, toml};
-    use thag_rs::shared::BuildState;
+    use thag_rs::repl_session::{parse_entries, Session};
+    use thag_rs::shared::{Ast, BuildState, ScriptState};
 
     use std::time::Instant;
 
@@ -23,6 +30,7 @@ mod tests {
             proc_flags,
             build_state,
             start,
+            session: Session::default(),
         }
     }
 
@@ -144,4 +152,178 @@ mod tests {
     //     let result = run_repl(&mut options, &proc_flags, &mut build_state, start);
     //     assert!(result.is_ok());
     // }
+
+    #[test]
+    fn test_show_session() {
+        set_up();
+        let mut options = Cli::parse_from(["test", "--repl"]);
+        let proc_flags = ProcFlags::default();
+        let mut build_state = BuildState::default();
+        let mut context = create_mock_context(&mut options, &proc_flags, &mut build_state);
+        let entries = thag_rs::repl_session::parse_entries("let x = 5;").unwrap();
+        context
+            .session
+            .add(entries, std::collections::BTreeMap::new());
+        let result = show_session(&ArgMatches::default(), &mut context);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_dep() {
+        set_up();
+        let mut options = Cli::parse_from(["test", "--repl"]);
+        let proc_flags = ProcFlags::default();
+        let mut build_state = BuildState::default();
+        let mut context = create_mock_context(&mut options, &proc_flags, &mut build_state);
+        let command = clap::Command::new("")
+            .no_binary_name(true)
+            .subcommand(
+                clap::Command::new("add")
+                    .arg(clap::Arg::new("crate"))
+                    .arg(clap::Arg::new("features").num_args(0..)),
+            )
+            .subcommand(clap::Command::new("rm").arg(clap::Arg::new("crates").num_args(1..)))
+            .subcommand(clap::Command::new("list"));
+
+        // A given version needs no Cargo search
+        let args = command
+            .clone()
+            .get_matches_from(["add", "serde-json@1.0", "preserve_order"]);
+        assert!(dep(&args, &mut context).is_ok());
+        let args = command.clone().get_matches_from(["list"]);
+        assert!(dep(&args, &mut context).is_ok());
+        assert!(context.session.dependencies().contains_key("serde-json"));
+
+        let args = command.get_matches_from(["rm", "serde_json", "unknown"]);
+        assert!(dep(&args, &mut context).is_ok());
+        assert!(context.session.dependencies().is_empty());
+    }
+
+    #[test]
+    fn test_eval_redefinition() {
+        set_up();
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let script_path = temp_dir.path().join("repl_redefine.rs");
+        std::fs::write(&script_path, "").expect("Failed to write REPL source file");
+        let mut options = Cli::parse_from(["test", "--repl", "-qq"]);
+        let proc_flags = get_proc_flags(&options).unwrap();
+        let script_state = ScriptState::NamedEmpty {
+            script: script_path.display().to_string(),
+            script_dir_path: temp_dir.path().to_path_buf(),
+        };
+        let mut build_state =
+            BuildState::pre_configure(&proc_flags, &options, &script_state).unwrap();
+        let mut context = create_mock_context(&mut options, &proc_flags, &mut build_state);
+
+        // The function and the use declaration are both redefined
+        for input in [
+            "use std::fmt;\nfn f() -> i32 { 1 }",
+            "use std::fmt;\nfn f() -> i32 { 2 }",
+            "let x = f();",
+        ] {
+            assert!(eval(input, &mut context).unwrap(), "{input}");
+        }
+        let sources: Vec<_> = context
+            .session
+            .entries()
+            .iter()
+            .map(|entry| entry.source.as_str())
+            .collect();
+        assert_eq!(
+            sources,
+            vec!["use std::fmt;", "fn f() -> i32 { 2 }", "let x = f();"]
+        );
+        assert!(eval("assert_eq!(x, 2)", &mut context).unwrap());
+        assert!(!eval("assert_eq!(x, 1)", &mut context).unwrap());
+    }
+
+    #[test]
+    fn test_dep_rm_drops_uses() {
+        set_up();
+        let mut options = Cli::parse_from(["test", "--repl"]);
+        let proc_flags = ProcFlags::default();
+        let mut build_state = BuildState {
+            source_stem: "repl_dep_rm".to_string(),
+            source_name: "repl_dep_rm.rs".to_string(),
+            ..BuildState::default()
+        };
+        let mut context = create_mock_context(&mut options, &proc_flags, &mut build_state);
+        let command = clap::Command::new("")
+            .no_binary_name(true)
+            .subcommand(
+                clap::Command::new("add")
+                    .arg(clap::Arg::new("crate"))
+                    .arg(clap::Arg::new("features").num_args(0..)),
+            )
+            .subcommand(clap::Command::new("rm").arg(clap::Arg::new("crates").num_args(1..)));
+
+        let args = command.clone().get_matches_from(["add", "serde_json@1.0"]);
+        assert!(dep(&args, &mut context).is_ok());
+        // As after evaluating input using the crate
+        context.session.add(
+            parse_entries("use serde_json::Value;\nuse std::fmt;\nlet n = 1;").unwrap(),
+            BTreeMap::new(),
+        );
+        let args = command.get_matches_from(["rm", "serde_json"]);
+        assert!(dep(&args, &mut context).is_ok());
+        let sources: Vec<_> = context
+            .session
+            .entries()
+            .iter()
+            .map(|entry| entry.source.as_str())
+            .collect();
+        assert_eq!(sources, vec!["use std::fmt;", "let n = 1;"]);
+
+        // Prepare the manifest of the next evaluation as the REPL does
+        let mut rs_manifest = extract_manifest("n + 1", Instant::now()).unwrap();
+        context
+            .session
+            .merge_dependencies(&mut rs_manifest.dependencies);
+        context.build_state.rs_manifest = Some(rs_manifest);
+        let session_source = context.session.source_with("n + 1");
+        let syntax_tree = Some(Ast::Expr(extract_ast_expr(&session_source).unwrap()));
+        manifest::merge(context.build_state, &session_source, &syntax_tree).unwrap();
+        let cargo_manifest = context.build_state.cargo_manifest.as_ref().unwrap();
+        assert!(!cargo_manifest.dependencies.contains_key("serde_json"));
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        set_up();
+        for input in [
+            "1 +",
+            "fn double(n: i32) -> i32 {",
+            "vec![1,\n2,",
+            "let s = \"unterminated",
+            "/* unterminated",
+            "let x = 5",
+            "struct Point",
+        ] {
+            assert!(is_incomplete(input), "{input:?} should be incomplete");
+        }
+        for input in [
+            "1 + 2",
+            "let x = 5;",
+            "fn double(n: i32) -> i32 {\n    n * 2\n}",
+            "/*[toml]\n[dependencies]\nitertools = \"0.13\"\n*/\nitertools::max([1, 2])",
+            "foo(1 +)",
+            "(1]",
+            "1)",
+            "a b",
+            "list",
+        ] {
+            assert!(!is_incomplete(input), "{input:?} should be complete");
+        }
+    }
+
+    #[test]
+    fn test_format_parse_error() {
+        set_up();
+        let input = "let a = 1;\nlet b = a c;";
+        let err = parse_entries(input).unwrap_err();
+        assert_eq!(
+            format_parse_error(input, &err),
+            "Error parsing code at line 2, column 11: expected `;`\nlet b = a c;\n          ^"
+        );
+    }
 }
@@ -0,0 +1,110 @@
+#[cfg(test)]
+mod tests {
+    use reedline::{Completer, Span};
+    use std::collections::BTreeSet;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use thag_rs::repl_completion::{cached_crate_names, ReplCompleter};
+
+    // Set environment variables before running tests
+    fn set_up() {
+        std::env::set_var("TEST_ENV", "1");
+    }
+
+    fn create_completer(cargo_home: &Path) -> ReplCompleter {
+        let session_names = BTreeSet::from(["total".to_string(), "double".to_string()]);
+        ReplCompleter::new(
+            vec!["drop".to_string(), "list".to_string()],
+            Arc::new(Mutex::new(session_names)),
+            cargo_home.to_path_buf(),
+        )
+    }
+
+    fn create_registry_cache(cargo_home: &Path) {
+        let cache_dir = cargo_home
+            .join("registry")
+            .join("cache")
+            .join("index.crates.io-6f17d22bba15001f");
+        fs::create_dir_all(&cache_dir).unwrap();
+        for file_name in [
+            "serde-1.0.210.crate",
+            "serde_json-1.0.128.crate",
+            "sha-1-0.10.1.crate",
+            "tokio-macros-2.4.0.crate",
+            "zerocopy-0.8.0-alpha.6.crate",
+        ] {
+            fs::write(cache_dir.join(file_name), "").unwrap();
+        }
+    }
+
+    fn complete(completer: &mut ReplCompleter, line: &str) -> Vec<String> {
+        completer
+            .complete(line, line.len())
+            .into_iter()
+            .map(|suggestion| suggestion.value)
+            .collect()
+    }
+
+    #[test]
+    fn test_complete_words() {
+        set_up();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut completer = create_completer(temp_dir.path());
+        // Commands complete only as the first word
+        assert_eq!(complete(&mut completer, "d"), vec!["double", "drop", "dyn"]);
+        assert_eq!(complete(&mut completer, "let x = d"), vec!["double", "dyn"]);
+        assert_eq!(
+            complete(&mut completer, "let n: u"),
+            vec!["u128", "u16", "u32", "u64", "u8", "unsafe", "use", "usize"]
+        );
+        assert_eq!(complete(&mut completer, "to"), vec!["total"]);
+        assert!(complete(&mut completer, "total + ").is_empty());
+
+        let suggestions = completer.complete("1 + tot * 2", 7);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].value, "total");
+        assert_eq!(suggestions[0].span, Span::new(4, 7));
+    }
+
+    #[test]
+    fn test_complete_std_paths() {
+        set_up();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut completer = create_completer(temp_dir.path());
+        assert_eq!(
+            complete(&mut completer, "use std::coll"),
+            vec!["std::collections"]
+        );
+        assert_eq!(
+            complete(&mut completer, "let map = std::collections::Hash"),
+            vec!["std::collections::HashMap", "std::collections::HashSet"]
+        );
+        assert!(complete(&mut completer, "std::collections::")
+            .contains(&"std::collections::hash_map".to_string()));
+    }
+
+    #[test]
+    fn test_complete_crate_names() {
+        set_up();
+        let temp_dir = tempfile::tempdir().unwrap();
+        create_registry_cache(temp_dir.path());
+        let mut completer = create_completer(temp_dir.path());
+        assert_eq!(
+            complete(&mut completer, "use s"),
+            vec!["self", "serde", "serde_json", "sha_1", "std", "super"]
+        );
+        assert_eq!(
+            complete(&mut completer, "pub use tok"),
+            vec!["tokio_macros"]
+        );
+        // Not after `use`
+        assert!(complete(&mut completer, "fuse ser").is_empty());
+        assert_eq!(
+            cached_crate_names(temp_dir.path()),
+            BTreeSet::from(
+                ["serde", "serde_json", "sha_1", "tokio_macros", "zerocopy"].map(String::from)
+            )
+        );
+    }
+}
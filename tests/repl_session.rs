@@ -0,0 +1,177 @@
+#[cfg(test)]
+mod tests {
+    use cargo_toml::Dependency;
+    use std::collections::BTreeMap;
+    use thag_rs::repl_session::{parse_entries, EntryKind, Session};
+
+    // Set environment variables before running tests
+    fn set_up() {
+        std::env::set_var("TEST_ENV", "1");
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        set_up();
+        let entries = parse_entries(
+            "use std::fmt;\nlet (a, mut b) = (1, 2);\nb += a;\n/// Doubles\nfn double(n: i32) -> i32 { n * 2 }\nimpl fmt::Display for Foo {}\ndouble(b)",
+        )
+        .unwrap();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.kind, entry.name.as_deref(), entry.source.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (EntryKind::Item, None, "use std::fmt;"),
+                (
+                    EntryKind::Let,
+                    Some("(a , mut b)"),
+                    "let (a, mut b) = (1, 2);"
+                ),
+                (
+                    EntryKind::Item,
+                    Some("double"),
+                    "/// Doubles\nfn double(n: i32) -> i32 { n * 2 }"
+                ),
+                (
+                    EntryKind::Item,
+                    Some("impl fmt :: Display for Foo"),
+                    "impl fmt::Display for Foo {}"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_entries_braced() {
+        set_up();
+        let entries =
+            parse_entries("{\n/*[toml]\n[dependencies]\n*/\nlet x = \"é\";\nx\n}").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].source, "let x = \"é\";");
+        assert!(parse_entries("let x = ;").is_err());
+    }
+
+    #[test]
+    fn test_session_add_and_drop() {
+        set_up();
+        let mut session = Session::default();
+        session.add(
+            parse_entries("fn f() -> i32 { 1 }\nlet x = f();\nuse std::fmt;").unwrap(),
+            BTreeMap::new(),
+        );
+        // A new item replaces one of the same name, and a repeated use declaration the same
+        // declaration, while let bindings accumulate
+        session.add(
+            parse_entries("fn f() -> i32 { 2 }\nlet x = f() + 1;\nuse std::fmt;").unwrap(),
+            BTreeMap::from([("regex".to_string(), Dependency::Simple("1".to_string()))]),
+        );
+        let sources: Vec<_> = session
+            .entries()
+            .iter()
+            .map(|e| e.source.as_str())
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                "let x = f();",
+                "fn f() -> i32 { 2 }",
+                "let x = f() + 1;",
+                "use std::fmt;"
+            ]
+        );
+        assert!(session.defines("x"));
+        assert!(session.defines("f"));
+        assert!(!session.defines("fmt"));
+        assert_eq!(
+            session.source_with("x * 2"),
+            "{\nlet x = f();\nfn f() -> i32 { 2 }\nlet x = f() + 1;\nuse std::fmt;\nx * 2\n}"
+        );
+        // Items that the input redefines are left out
+        assert_eq!(
+            session.source_with("use std::fmt;\nfn f() -> i32 { 3 }\nf()"),
+            "{\nlet x = f();\nlet x = f() + 1;\nuse std::fmt;\nfn f() -> i32 { 3 }\nf()\n}"
+        );
+
+        let mut dep_map = BTreeMap::new();
+        session.merge_dependencies(&mut dep_map);
+        assert!(dep_map.contains_key("regex"));
+
+        assert!(session.drop_entries(&[5]).is_err());
+        assert_eq!(session.entries().len(), 4);
+        let dropped = session.drop_entries(&[1, 3]).unwrap();
+        assert_eq!(dropped.len(), 2);
+        let sources: Vec<_> = session
+            .entries()
+            .iter()
+            .map(|e| e.source.as_str())
+            .collect();
+        assert_eq!(sources, vec!["fn f() -> i32 { 2 }", "use std::fmt;"]);
+
+        session.clear();
+        assert!(session.entries().is_empty());
+        assert!(session.dependencies().is_empty());
+    }
+
+    #[test]
+    fn test_session_names() {
+        set_up();
+        let mut session = Session::default();
+        session.add(
+            parse_entries(
+                "use std::fmt;\nlet (a, mut b, _) = (1, 2, 3);\nstruct Foo;\nimpl fmt::Debug for Foo { fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { Ok(()) } }",
+            )
+            .unwrap(),
+            BTreeMap::new(),
+        );
+        assert_eq!(
+            session.names().into_iter().collect::<Vec<_>>(),
+            vec!["Foo", "a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_mutated_bindings() {
+        set_up();
+        let mut session = Session::default();
+        session.add(
+            parse_entries("let mut v = vec![1];\nlet (a, mut b) = (1, 2);\nlet n = 3;").unwrap(),
+            BTreeMap::new(),
+        );
+        let mutated = |input: &str| {
+            session
+                .mutated_bindings(input)
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(mutated("v.push(1)"), vec!["v"]);
+        assert_eq!(mutated("b += a;\nv[0] = n;\nb"), vec!["b", "v"]);
+        assert_eq!(
+            mutated("let mut w = Vec::new();\nstd::mem::swap(&mut w, &mut v);"),
+            vec!["v", "w"]
+        );
+        // Changes in `let` bindings are replayed, and `a` and `n` aren't mutable
+        assert!(mutated("let c = { b += 1; b };\nn.pow(2) + a").is_empty());
+        assert!(mutated("fn f(mut v: Vec<i32>) { v.push(1); }").is_empty());
+    }
+
+    #[test]
+    fn test_session_dependencies() {
+        set_up();
+        let mut session = Session::default();
+        session.add_dependency(
+            "serde_json".to_string(),
+            Dependency::Simple("1.0".to_string()),
+        );
+        let mut rs_dep_map = BTreeMap::new();
+        session.merge_dependencies(&mut rs_dep_map);
+        assert!(rs_dep_map.contains_key("serde_json"));
+        assert_eq!(session.remove_dependency("regex"), None);
+        assert_eq!(
+            session.remove_dependency("serde-json"),
+            Some("serde_json".to_string())
+        );
+        assert!(session.dependencies().is_empty());
+    }
+}
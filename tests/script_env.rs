@@ -0,0 +1,56 @@
+#[cfg(test)]
+mod tests {
+    use thag_rs::script_env::{invocation_mode, parse_env_file};
+    use thag_rs::ProcFlags;
+
+    // Set environment variables before running tests
+    fn set_up() {
+        std::env::set_var("TEST_ENV", "1");
+        std::env::set_var("VISUAL", "cat");
+        std::env::set_var("EDITOR", "cat");
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        set_up();
+        let contents = r#"
+# Database settings
+DB_HOST=localhost
+export DB_PORT = 5432
+GREETING="Hello, world"
+EMPTY=
+QUOTED='a=b'
+"#;
+        let vars = parse_env_file(contents).unwrap();
+        let expected = [
+            ("DB_HOST", "localhost"),
+            ("DB_PORT", "5432"),
+            ("GREETING", "Hello, world"),
+            ("EMPTY", ""),
+            ("QUOTED", "a=b"),
+        ];
+        assert_eq!(
+            vars,
+            expected
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_env_file_invalid_line() {
+        set_up();
+        let err = parse_env_file("A=1\nnot a variable\n").unwrap_err();
+        assert!(err.to_string().contains("Invalid line 2"));
+    }
+
+    #[test]
+    fn test_invocation_mode() {
+        set_up();
+        assert_eq!(invocation_mode(&ProcFlags::RUN), "script");
+        assert_eq!(invocation_mode(&(ProcFlags::RUN | ProcFlags::EXPR)), "expr");
+        assert_eq!(invocation_mode(&(ProcFlags::RUN | ProcFlags::LOOP)), "loop");
+        assert_eq!(invocation_mode(&ProcFlags::REPL), "repl");
+    }
+}
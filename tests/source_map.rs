@@ -0,0 +1,186 @@
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use thag_rs::code_utils::{wrap_snippet, AsyncRuntime, WrapperTemplate};
+    use thag_rs::source_map::{OriginalLocation, SourceMap, SourceSegment};
+
+    // Set environment variables before running tests
+    fn set_up() {
+        std::env::set_var("TEST_ENV", "1");
+        std::env::set_var("VISUAL", "cat");
+        std::env::set_var("EDITOR", "cat");
+    }
+
+    fn generated_from(snippet: &str) -> String {
+        let syntax_tree = syn::parse_file(&wrap_snippet(
+            &WrapperTemplate::default(),
+            "",
+            snippet,
+            AsyncRuntime::None,
+        ))
+        .unwrap();
+        prettyplease::unparse(&syntax_tree)
+    }
+
+    fn position_of(generated: &str, pattern: &str) -> (usize, usize) {
+        generated
+            .lines()
+            .enumerate()
+            .find_map(|(index, line)| line.find(pattern).map(|column| (index + 1, column + 1)))
+            .expect("Pattern not found in generated source")
+    }
+
+    #[test]
+    fn test_source_map_lookup_expr() {
+        set_up();
+        let snippet = "let total = 6 * 7;\nprintln!(\"{total}\");";
+        let generated = generated_from(snippet);
+        let source_map = SourceMap::new(
+            &[SourceSegment::new("<expr>", snippet)],
+            "temp.rs",
+            &generated,
+        );
+
+        let (line, column) = position_of(&generated, "7;");
+        assert_eq!(
+            source_map.lookup(line, column),
+            Some(OriginalLocation {
+                name: "<expr>",
+                line: 1,
+                column: 17
+            })
+        );
+        let (line, column) = position_of(&generated, "println");
+        assert_eq!(
+            source_map.lookup(line, column + 2),
+            Some(OriginalLocation {
+                name: "<expr>",
+                line: 2,
+                column: 3
+            })
+        );
+        // Scaffolding added by the wrapper has no counterpart
+        let (line, column) = position_of(&generated, "Ok(())");
+        assert_eq!(source_map.lookup(line, column), None);
+    }
+
+    #[test]
+    fn test_source_map_first_line_offset() {
+        set_up();
+        let snippet = "let a = 1;\n\nlet b: String = a;";
+        let generated = generated_from(snippet);
+        let segment = SourceSegment {
+            name: "script.rs".to_string(),
+            text: snippet.to_string(),
+            first_line: 2,
+        };
+        let source_map = SourceMap::new(&[segment], "script.rs", &generated);
+        let (line, column) = position_of(&generated, "= a;");
+        assert_eq!(
+            source_map.lookup(line, column + 2),
+            Some(OriginalLocation {
+                name: "script.rs",
+                line: 4,
+                column: 17
+            })
+        );
+        assert_eq!(
+            source_map.original_line("script.rs", 4),
+            Some("let b: String = a;")
+        );
+    }
+
+    #[test]
+    fn test_source_map_rewrite_diagnostic() {
+        set_up();
+        let snippet = "let x: i32 = \"a\";";
+        let generated = generated_from(snippet);
+        let source_map = SourceMap::new(
+            &[SourceSegment::new("<expr>", snippet)],
+            "temp.rs",
+            &generated,
+        );
+        let (line, column) = position_of(&generated, "\"a\"");
+        let mut message = json!({
+            "message": "mismatched types",
+            "spans": [{
+                "file_name": "temp.rs",
+                "line_start": line,
+                "column_start": column,
+                "line_end": line,
+                "column_end": column + 3,
+            }],
+            "children": [],
+            "rendered": format!("error[E0308]: mismatched types\n --> temp.rs:{line}:{column}\n"),
+        });
+        source_map.rewrite_diagnostic(&mut message);
+
+        let span = &message["spans"][0];
+        assert_eq!(span["file_name"], "<expr>");
+        assert_eq!(span["line_start"], 1);
+        assert_eq!(span["column_start"], 14);
+        assert_eq!(span["column_end"], 17);
+        assert_eq!(span["text"][0]["text"], snippet);
+        assert_eq!(
+            message["rendered"],
+            "error[E0308]: mismatched types\n --> <expr>:1:14\n"
+        );
+    }
+
+    #[test]
+    fn test_source_map_loop_segments() {
+        set_up();
+        let segments = [
+            SourceSegment::new("<begin>", "let mut count = 0;"),
+            SourceSegment::new("<loop>", "count += line.len();"),
+        ];
+        let generated = generated_from(
+            "let mut count = 0;\nfor line in [\"a\"] {\n    count += line.len();\n}",
+        );
+        let source_map = SourceMap::new(&segments, "temp.rs", &generated);
+        let (line, column) = position_of(&generated, "+=");
+        assert_eq!(
+            source_map.lookup(line, column),
+            Some(OriginalLocation {
+                name: "<loop>",
+                line: 1,
+                column: 7
+            })
+        );
+    }
+
+    #[test]
+    fn test_source_map_many_differences() {
+        set_up();
+        // Every other token differs, which takes thousands of edits to get from one to the
+        // other, in memory only in proportion to the length
+        let statements = |name: &str| -> String {
+            (0..3000)
+                .map(|i| format!("let {name}{i} = {i};\n"))
+                .collect()
+        };
+        let snippet = statements("a");
+        let generated = statements("b");
+        let source_map = SourceMap::new(
+            &[SourceSegment::new("<expr>", &snippet)],
+            "temp.rs",
+            &generated,
+        );
+        // The number in `let b1999 = 1999;`
+        assert_eq!(
+            source_map.lookup(2000, 13),
+            Some(OriginalLocation {
+                name: "<expr>",
+                line: 2000,
+                column: 13
+            })
+        );
+
+        let source_map = SourceMap::new(
+            &[SourceSegment::new("<expr>", "\"unterminated")],
+            "temp.rs",
+            &generated,
+        );
+        assert_eq!(source_map.lookup(2000, 13), None);
+    }
+}
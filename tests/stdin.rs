@@ -1,14 +1,19 @@
 use mockall::Sequence;
+use ratatui::buffer::Buffer;
 use ratatui::crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
 use ratatui::crossterm::tty::IsTty;
-use ratatui::style::{Color, Style};
+use ratatui::layout::Rect;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::Widget;
 use std::io::{stdout, Write};
 use std::process::{Command, Stdio};
 use thag_rs::colors::get_term_theme;
 use thag_rs::logging::Verbosity;
-use thag_rs::stdin::{apply_highlights, normalize_newlines, read_to_string, MockEventReader};
+use thag_rs::stdin::{
+    apply_highlights, normalize_newlines, read_to_string, EditorView, MockEventReader,
+};
 use thag_rs::{edit, log, ThagError};
-use tui_textarea::TextArea;
+use tui_textarea::{CursorMove, Input, TextArea};
 
 // Set environment variables before running tests
 fn set_up() {
@@ -187,3 +192,117 @@ fn test_apply_highlights() {
         Style::default().fg(Color::Black).bg(Color::Gray)
     );
 }
+
+#[test]
+fn test_highlight_syntax() {
+    set_up();
+    let mut textarea = TextArea::from(["fn main() {", "    let s = \"hi\";", "}"]);
+    textarea.set_line_number_style(Style::default().fg(Color::DarkGray));
+    apply_highlights(false, &mut textarea);
+    textarea.move_cursor(CursorMove::Bottom);
+
+    let mut view = EditorView::default();
+    let area = Rect::new(0, 0, 30, 3);
+    let mut buf = Buffer::empty(area);
+    view.render(&mut textarea, area, &mut buf);
+    // After the 3-column line number gutter
+    assert_eq!(buf[(1, 0)].symbol(), "1");
+    assert_eq!(buf[(1, 0)].fg, Color::DarkGray);
+    assert_eq!(buf[(3, 0)].symbol(), "f");
+    assert_eq!(buf[(3, 0)].fg, Color::Magenta);
+    assert!(buf[(3, 0)].modifier.contains(Modifier::BOLD));
+    assert_eq!(buf[(15, 1)].symbol(), "\"");
+    assert_eq!(buf[(15, 1)].fg, Color::Green);
+    // The cursor keeps its own style
+    assert_eq!(buf[(3, 2)].symbol(), "}");
+    assert_eq!(buf[(3, 2)].bg, Color::LightRed);
+    assert_eq!(buf[(3, 2)].fg, Color::White);
+
+    // Scrolled horizontally to keep the cursor at the end of the second line in view,
+    // with the gutter staying in place
+    textarea.move_cursor(CursorMove::Jump(1, 17));
+    let area = Rect::new(0, 0, 10, 3);
+    let mut buf = Buffer::empty(area);
+    view.render(&mut textarea, area, &mut buf);
+    assert_eq!(buf[(1, 1)].symbol(), "2");
+    assert_eq!(buf[(4, 1)].symbol(), "\"");
+    assert_eq!(buf[(4, 1)].fg, Color::Green);
+    assert_eq!(buf[(9, 1)].symbol(), " ");
+    assert_eq!(buf[(9, 1)].bg, Color::LightRed);
+    // Whitespace is unstyled, so keeps the cursor line style
+    assert_eq!(buf[(3, 1)].symbol(), " ");
+    assert_eq!(buf[(3, 1)].bg, Color::Gray);
+}
+
+#[test]
+fn test_editor_view_scroll() {
+    set_up();
+    let lines: Vec<_> = (0..20).map(|i| format!("let x{i} = {i};")).collect();
+    let mut textarea = TextArea::from(lines);
+    let mut view = EditorView::default();
+    let area = Rect::new(0, 0, 20, 5);
+    let mut buf = Buffer::empty(area);
+    view.render(&mut textarea, area, &mut buf);
+
+    // A page down moves the cursor along with the view
+    view.input(
+        &mut textarea,
+        Input::from(Event::Key(KeyEvent::from(KeyCode::PageDown))),
+    );
+    assert_eq!(textarea.cursor(), (5, 0));
+    view.render(&mut textarea, area, &mut buf);
+    assert_eq!(buf[(5, 0)].symbol(), "5");
+
+    // Scrolling back up leaves the cursor where it is while it stays in view
+    view.scroll(&mut textarea, -2);
+    assert_eq!(textarea.cursor(), (5, 0));
+    view.render(&mut textarea, area, &mut buf);
+    assert_eq!(buf[(5, 0)].symbol(), "3");
+    view.scroll(&mut textarea, -3);
+    assert_eq!(textarea.cursor(), (4, 0));
+
+    // Other input goes to the text area
+    view.input(
+        &mut textarea,
+        Input::from(Event::Key(KeyEvent::from(KeyCode::Down))),
+    );
+    assert_eq!(textarea.cursor(), (5, 0));
+    view.render(&mut textarea, area, &mut buf);
+    assert_eq!(buf[(5, 0)].symbol(), "1");
+}
+
+#[test]
+fn test_highlight_syntax_with_selection() {
+    set_up();
+    let lines: Vec<_> = (0..10).map(|i| format!("let x{i} = \"{i}\";")).collect();
+    let mut textarea = TextArea::from(lines);
+    // Cursor and selection styles without a background colour of their own
+    textarea.set_cursor_style(Style::default().add_modifier(Modifier::REVERSED));
+    textarea.set_selection_style(Style::default().add_modifier(Modifier::UNDERLINED));
+    textarea.set_cursor_line_style(Style::default());
+    textarea.move_cursor(CursorMove::Jump(8, 4));
+    textarea.start_selection();
+    textarea.move_cursor(CursorMove::Jump(9, 2));
+
+    let mut view = EditorView::default();
+    let area = Rect::new(0, 0, 20, 3);
+    let mut buf = Buffer::empty(area);
+    view.render(&mut textarea, area, &mut buf);
+    // Scrolled down to rows 7 to 9, with rows 8 and 9 selected from "x8" up to the cursor
+    assert_eq!(buf[(0, 0)].symbol(), "l");
+    assert_eq!(buf[(0, 0)].fg, Color::Magenta);
+    assert_eq!(buf[(9, 0)].symbol(), "\"");
+    assert_eq!(buf[(9, 0)].fg, Color::Green);
+    assert_eq!(buf[(0, 1)].fg, Color::Magenta);
+    assert_eq!(buf[(9, 1)].symbol(), "\"");
+    assert_eq!(buf[(9, 1)].fg, Color::Reset);
+    assert!(buf[(9, 1)].modifier.contains(Modifier::UNDERLINED));
+    assert_eq!(buf[(0, 2)].symbol(), "l");
+    assert_eq!(buf[(0, 2)].fg, Color::Reset);
+    // The cursor is past the selection
+    assert_eq!(buf[(2, 2)].symbol(), "t");
+    assert_eq!(buf[(2, 2)].fg, Color::Reset);
+    assert!(buf[(2, 2)].modifier.contains(Modifier::REVERSED));
+    assert_eq!(buf[(9, 2)].symbol(), "\"");
+    assert_eq!(buf[(9, 2)].fg, Color::Green);
+}
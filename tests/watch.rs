@@ -0,0 +1,203 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::path::Path;
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::{Duration, Instant, SystemTime};
+    use thag_rs::watch::{watched_files, Snapshot};
+
+    // Set environment variables before running tests
+    fn set_up() {
+        std::env::set_var("TEST_ENV", "1");
+        std::env::set_var("VISUAL", "cat");
+        std::env::set_var("EDITOR", "cat");
+    }
+
+    // Allow for building the script, which may take a while on a cold cache.
+    const TIMEOUT: Duration = Duration::from_secs(120);
+
+    // Start `thag --watch` on the given script, passing back the lines of its stdout.
+    fn watch_script(script: &Path) -> (Child, Receiver<String>) {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_thag"))
+            .args(["-qq", "--watch"])
+            .arg(script)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to run thag");
+        let stdout = child.stdout.take().expect("Failed to open stdout");
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        (child, receiver)
+    }
+
+    fn expect_line(receiver: &Receiver<String>, expected: &str) {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(remaining) {
+                Ok(line) if line == expected => return,
+                Ok(_) => (),
+                Err(e) => panic!("Expected {expected:?} from the script: {e}"),
+            }
+        }
+    }
+
+    // Save the script, making sure its modification time moves on.
+    fn save_script(script: &Path, source: &str) {
+        fs::write(script, source).unwrap();
+        let file = fs::File::options().write(true).open(script).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_watched_files() {
+        set_up();
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let dir = temp_dir.path();
+        let script = dir.join("watched.rs");
+        fs::write(
+            &script,
+            r#"/*[toml]
+[dependencies]
+mylib = { path = "mylib" }
+*/
+mod helper;
+mod nested;
+fn main() {}
+"#,
+        )
+        .unwrap();
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("nested/mod.rs"), "").unwrap();
+        fs::create_dir_all(dir.join("mylib/src")).unwrap();
+        fs::create_dir_all(dir.join("mylib/target/debug")).unwrap();
+        fs::write(dir.join("mylib/Cargo.toml"), "").unwrap();
+        fs::write(dir.join("mylib/src/lib.rs"), "").unwrap();
+        fs::write(dir.join("mylib/target/debug/build.rs"), "").unwrap();
+
+        let mut expected = vec![
+            script.clone(),
+            dir.join("helper.rs"),
+            dir.join("nested.rs"),
+            dir.join("nested/mod.rs"),
+            dir.join("mylib/Cargo.toml"),
+            dir.join("mylib/src/lib.rs"),
+        ];
+        expected.sort();
+        assert_eq!(watched_files(&script), expected);
+    }
+
+    #[test]
+    fn test_snapshot_first_change() {
+        set_up();
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let existing = temp_dir.path().join("existing.rs");
+        let missing = temp_dir.path().join("missing.rs");
+        fs::write(&existing, "fn main() {}").unwrap();
+        let snapshot = Snapshot::take(&[existing.clone(), missing.clone()]);
+        assert_eq!(snapshot.first_change(), None);
+
+        let file = fs::File::options().write(true).open(&existing).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(snapshot.first_change(), Some(existing.clone()));
+
+        let snapshot = snapshot.retake();
+        fs::write(&missing, "").unwrap();
+        assert_eq!(snapshot.first_change(), Some(missing));
+    }
+
+    #[test]
+    fn test_watch_reruns_on_change() {
+        set_up();
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let script = temp_dir.path().join("watch_rerun_t.rs");
+        save_script(&script, "fn main() {\n    println!(\"version 1\");\n}\n");
+        let (mut child, receiver) = watch_script(&script);
+        expect_line(&receiver, "version 1");
+
+        save_script(&script, "fn main() {\n    println!(\"version 2\");\n}\n");
+        expect_line(&receiver, "version 2");
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_watch_forwards_signals() {
+        set_up();
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let script = temp_dir.path().join("watch_signal_t.rs");
+        save_script(
+            &script,
+            "fn main() {\n    println!(\"started\");\n    std::thread::sleep(std::time::Duration::from_secs(60));\n}\n",
+        );
+        let (mut child, receiver) = watch_script(&script);
+        expect_line(&receiver, "started");
+
+        // The script is terminated by the forwarded signal, which ends the watch
+        let status = Command::new("kill")
+            .args(["-TERM", &child.id().to_string()])
+            .status()
+            .expect("Failed to run kill");
+        assert!(status.success());
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let exit_status = loop {
+            if let Some(exit_status) = child.try_wait().unwrap() {
+                break exit_status;
+            }
+            if Instant::now() > deadline {
+                child.kill().unwrap();
+                panic!("thag kept watching after the signal");
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        };
+        // 128 + SIGTERM
+        assert_eq!(exit_status.code(), Some(143));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_watch_stops_on_signal_when_idle() {
+        use std::os::unix::process::ExitStatusExt;
+
+        set_up();
+        for (signal, number) in [("-INT", 2), ("-TERM", 15)] {
+            let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+            let script = temp_dir.path().join("watch_idle_t.rs");
+            save_script(&script, "fn main() {\n    println!(\"finished\");\n}\n");
+            let (mut child, receiver) = watch_script(&script);
+            expect_line(&receiver, "finished");
+            // Give the script time to exit, leaving thag waiting for a change
+            std::thread::sleep(Duration::from_secs(1));
+
+            let status = Command::new("kill")
+                .args([signal, &child.id().to_string()])
+                .status()
+                .expect("Failed to run kill");
+            assert!(status.success());
+            let deadline = Instant::now() + Duration::from_secs(30);
+            let exit_status = loop {
+                if let Some(exit_status) = child.try_wait().unwrap() {
+                    break exit_status;
+                }
+                if Instant::now() > deadline {
+                    child.kill().unwrap();
+                    panic!("thag kept watching after {signal}");
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            };
+            assert_eq!(exit_status.signal(), Some(number));
+        }
+    }
+}
@@ -0,0 +1,155 @@
+/// Generate `src/std_index.txt`, the index of `std` paths that the REPL completes, from
+/// the standard library documentation of the active toolchain, which needs the `rust-docs`
+/// component (`rustup component add rust-docs`). Run it from the project directory with
+///
+/// `thag tools/gen_std_index.rs > src/std_index.txt`
+///
+/// The index lists the stable, non-deprecated public modules and items of `std`, except
+/// for platform-specific modules other than the common `os` ones, and modules that exist
+/// only for unstable features or for items now better reached elsewhere, such as the
+/// primitive integer modules. Its header records the toolchain it was generated from.
+//# Purpose: Regenerate the bundled index of `std` paths for REPL completion.
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Modules left out of the index, with everything under them.
+const SKIPPED_MODULES: &[&str] = &[
+    "arch",
+    "assert_matches",
+    "async_iter",
+    "autodiff",
+    "bstr",
+    "f128",
+    "f16",
+    "i128",
+    "i16",
+    "i32",
+    "i64",
+    "i8",
+    "intrinsics",
+    "isize",
+    "pat",
+    "prelude",
+    "primitive",
+    "random",
+    "range",
+    "simd",
+    "u128",
+    "u16",
+    "u32",
+    "u64",
+    "u8",
+    "unsafe_binder",
+    "usize",
+];
+
+// The submodules of `std::os` kept in the index.
+const OS_MODULES: &[&str] = &["fd", "raw", "unix", "windows"];
+
+// The kinds of item that have their own documentation page, named `<kind>.<name>.html`.
+const ITEM_KINDS: &[&str] = &[
+    "constant", "enum", "fn", "macro", "static", "struct", "trait", "type", "union",
+];
+
+fn rustc(args: &[&str]) -> String {
+    let output = Command::new("rustc")
+        .args(args)
+        .output()
+        .expect("Failed to run rustc");
+    String::from_utf8(output.stdout)
+        .expect("rustc output is not UTF-8")
+        .trim()
+        .to_string()
+}
+
+// Whether the heading of a documentation page marks it as unstable or deprecated.
+fn is_unstable(html: &str) -> bool {
+    let Some(start) = html.find("<h1") else {
+        return false;
+    };
+    let heading = &html[start..];
+    let end = heading
+        .find("class=\"docblock")
+        .or_else(|| heading.find("<details"))
+        .unwrap_or(heading.len().min(4000));
+    let heading = &heading[..end];
+    heading.contains("stab unstable") || heading.contains("stab deprecated")
+}
+
+fn is_skipped(module: &[String]) -> bool {
+    module
+        .iter()
+        .any(|name| SKIPPED_MODULES.contains(&name.as_str()))
+        || matches!(module, [os, sub, ..] if os == "os" && !OS_MODULES.contains(&sub.as_str()))
+}
+
+fn index_module(dir: &Path, module: &[String], paths: &mut BTreeSet<String>) {
+    if is_skipped(module) {
+        return;
+    }
+    let path = |name: Option<&str>| {
+        let mut segments = vec!["std"];
+        segments.extend(module.iter().map(String::as_str));
+        segments.extend(name);
+        segments.join("::")
+    };
+    if !module.is_empty() {
+        if let Ok(html) = fs::read_to_string(dir.join("index.html")) {
+            if is_unstable(&html) {
+                return;
+            }
+            paths.insert(path(None));
+        }
+    }
+
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .expect("Failed to read documentation directory")
+        .map(|entry| entry.expect("Failed to read documentation entry").path())
+        .collect();
+    entries.sort();
+    for entry in entries {
+        let file_name = entry.file_name().unwrap().to_string_lossy().into_owned();
+        if entry.is_dir() {
+            let mut submodule = module.to_vec();
+            submodule.push(file_name);
+            index_module(&entry, &submodule, paths);
+            continue;
+        }
+        let Some((kind, name)) = file_name
+            .strip_suffix(".html")
+            .and_then(|stem| stem.split_once('.'))
+        else {
+            continue;
+        };
+        if !ITEM_KINDS.contains(&kind) || name.contains('.') {
+            continue;
+        }
+        let html = fs::read_to_string(&entry).unwrap_or_default();
+        // Items re-exported from elsewhere have pages that just redirect
+        if html.contains("http-equiv=\"refresh\"") || is_unstable(&html) {
+            continue;
+        }
+        paths.insert(path(Some(name)));
+    }
+}
+
+fn main() {
+    let docs = Path::new(&rustc(&["--print", "sysroot"])).join("share/doc/rust/html/std");
+    assert!(
+        docs.is_dir(),
+        "No standard library documentation at {}: install it with `rustup component add rust-docs`",
+        docs.display()
+    );
+
+    let mut paths = BTreeSet::new();
+    index_module(&docs, &[], &mut paths);
+    println!(
+        "# Generated by tools/gen_std_index.rs from the documentation of {}",
+        rustc(&["--version"])
+    );
+    for path in paths {
+        println!("{path}");
+    }
+}